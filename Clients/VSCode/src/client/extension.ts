@@ -45,9 +45,13 @@ export function activate(context: ExtensionContext) {
     command: bin_path
   };
   const clientOptions: LanguageClientOptions = {
-    documentSelector: [{ scheme: "file", language: "Marlowe" }],
+    documentSelector: [
+      { scheme: "file", language: "Marlowe" },
+      { scheme: "file", language: "MarloweJSON" }
+    ],
     synchronize: {
-      fileEvents: workspace.createFileSystemWatcher("**/.clientrc"),
+      // The server reads the "marlowe" settings when it is told that they changed
+      configurationSection: "marlowe",
     },
   };
 
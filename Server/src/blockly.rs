@@ -0,0 +1,514 @@
+// Conversion between the contract model and the Blockly XML workspaces of the
+// Marlowe Playground, so that contracts built with blocks can be brought into
+// the editor and the other way around.
+//
+// The block and input names follow the block definitions of the Playground:
+//
+// - the workspace holds a BaseContractType block whose statement input
+//   "BaseContractType" is the root contract
+// - contracts: CloseContractType, PayContractType (party, payee, token, value,
+//   contract), IfContractType (observation, contract1, contract2),
+//   WhenContractType (case, timeout_type/timeout fields, contract),
+//   LetContractType (value_id, value, contract), AssertContractType (observation, contract)
+// - the cases of a When and the bounds of a Choice are chains of blocks linked with <next>
+// - actions: DepositActionType (party, from_party, token, value, contract),
+//   ChoiceActionType (choice_name, party, bounds, contract), NotifyActionType (observation, contract)
+// - values, observations, payees, parties and tokens are blocks in value inputs
+//
+// Holes are inputs without a block, which is also how the Playground shows them.
+
+use roxmltree::{Document, Node};
+use marlowe_lang::types::marlowe::*;
+use crate::contract_model::parse_contract;
+
+const NAMESPACE: &str = "https://developers.google.com/blockly/xml";
+
+// -- Reading ------------------------------------------------------------------
+
+struct Reader<'a, 'input> {
+    document: &'a Document<'input>,
+}
+
+fn is_element(node: &Node, name: &str) -> bool {
+    node.is_element() && node.tag_name().name() == name
+}
+
+impl<'a, 'input> Reader<'a, 'input> {
+
+    fn error<T>(&self, node: Node, message: String) -> Result<T, String> {
+        let position = self.document.text_pos_at(node.range().start);
+        Err(format!("Line {}: {message}", position.row))
+    }
+
+    fn block_type(&self, block: Node<'a, 'input>) -> &'a str {
+        block.attribute("type").unwrap_or_default()
+    }
+
+    fn unexpected<T>(&self, block: Node, expected: &str) -> Result<T, String> {
+        self.error(block, format!("Expected {expected}, found a block of type '{}'.", block.attribute("type").unwrap_or_default()))
+    }
+
+    /// The block in an input (value or statement) of a block, or None if the input is empty.
+    fn input(&self, block: Node<'a, 'input>, name: &str) -> Option<Node<'a, 'input>> {
+        block.children()
+            .filter(|c| (is_element(c, "value") || is_element(c, "statement")) && c.attribute("name") == Some(name))
+            .flat_map(|c| c.children())
+            .find(|c| is_element(c, "block"))
+    }
+
+    /// The block that follows a block in a chain of statements.
+    fn next(&self, block: Node<'a, 'input>) -> Option<Node<'a, 'input>> {
+        block.children().filter(|c| is_element(c, "next")).flat_map(|c| c.children()).find(|c| is_element(c, "block"))
+    }
+
+    fn chain(&self, block: Node<'a, 'input>, name: &str) -> Vec<Node<'a, 'input>> {
+        let mut blocks = vec![];
+        let mut current = self.input(block, name);
+        while let Some(b) = current {
+            blocks.push(b);
+            current = self.next(b);
+        }
+        blocks
+    }
+
+    fn field(&self, block: Node<'a, 'input>, name: &str) -> Result<String, String> {
+        match block.children().find(|c| is_element(c, "field") && c.attribute("name") == Some(name)) {
+            Some(field) => Ok(field.text().unwrap_or_default().to_string()),
+            None => self.error(block, format!("The '{}' block has no '{name}' field.", self.block_type(block))),
+        }
+    }
+
+    fn number(&self, block: Node<'a, 'input>, name: &str) -> Result<i64, String> {
+        let text = self.field(block, name)?;
+        match text.trim().parse::<i64>() {
+            Ok(n) => Ok(n),
+            Err(e) => self.error(block, format!("The '{name}' field of the '{}' block is not a valid number ('{text}'): {e}", self.block_type(block))),
+        }
+    }
+
+    fn boxed_contract(&self, block: Node<'a, 'input>, name: &str) -> Result<Option<Box<Contract>>, String> {
+        Ok(match self.input(block, name) {
+            Some(b) => Some(Box::new(self.contract(b)?)),
+            None => None,
+        })
+    }
+
+    fn contract(&self, block: Node<'a, 'input>) -> Result<Contract, String> {
+        Ok(match self.block_type(block) {
+            "CloseContractType" => Contract::Close,
+            "PayContractType" => Contract::Pay {
+                from_account: self.party(block, "party")?,
+                to: self.payee(block, "payee")?,
+                token: self.token(block, "token")?,
+                pay: self.value(block, "value")?,
+                then: self.boxed_contract(block, "contract")?,
+            },
+            "IfContractType" => Contract::If {
+                r#if: self.observation(block, "observation")?,
+                then: self.boxed_contract(block, "contract1")?,
+                r#else: self.boxed_contract(block, "contract2")?,
+            },
+            "WhenContractType" => {
+                let mut when = vec![];
+                for case in self.chain(block, "case") {
+                    when.push(Some(self.case(case)?))
+                }
+                Contract::When { when, timeout: self.timeout(block)?, timeout_continuation: self.boxed_contract(block, "contract")? }
+            }
+            "LetContractType" => Contract::Let {
+                r#let: self.field(block, "value_id")?,
+                be: self.value(block, "value")?.map(Box::new),
+                then: self.boxed_contract(block, "contract")?,
+            },
+            "AssertContractType" => Contract::Assert {
+                assert: self.observation(block, "observation")?,
+                then: self.boxed_contract(block, "contract")?,
+            },
+            _ => return self.unexpected(block, "a contract")
+        })
+    }
+
+    fn timeout(&self, block: Node<'a, 'input>) -> Result<Option<Timeout>, String> {
+        // Older workspaces have the timeout in a value input instead of in fields.
+        if let Some(timeout) = self.input(block, "timeout") {
+            return match self.block_type(timeout) {
+                "TimeValueType" => Ok(Some(Timeout::TimeConstant(self.number(timeout, "time")?))),
+                "TimeParamType" => Ok(Some(Timeout::TimeParam(self.field(timeout, "timeParam")?))),
+                _ => self.unexpected(timeout, "a timeout"),
+            }
+        }
+        let text = self.field(block, "timeout")?;
+        if text.trim().is_empty() {
+            return Ok(None)
+        }
+        match self.field(block, "timeout_type").as_deref() {
+            Ok("time_param") => Ok(Some(Timeout::TimeParam(text))),
+            _ => Ok(Some(Timeout::TimeConstant(self.number(block, "timeout")?))),
+        }
+    }
+
+    fn case(&self, block: Node<'a, 'input>) -> Result<Case, String> {
+        let action = match self.block_type(block) {
+            "DepositActionType" => Action::Deposit {
+                into_account: self.party(block, "party")?,
+                party: self.party(block, "from_party")?,
+                of_token: self.token(block, "token")?,
+                deposits: self.value(block, "value")?,
+            },
+            "ChoiceActionType" => {
+                let mut choose_between = vec![];
+                for bound in self.chain(block, "bounds") {
+                    if self.block_type(bound) != "BoundsType" {
+                        return self.unexpected(bound, "a bound")
+                    }
+                    choose_between.push(Some(Bound(self.number(bound, "from")?, self.number(bound, "to")?)))
+                }
+                Action::Choice { for_choice: Some(self.choice_id(block)?), choose_between }
+            }
+            "NotifyActionType" => Action::Notify { notify_if: self.observation(block, "observation")? },
+            _ => return self.unexpected(block, "an action")
+        };
+        Ok(Case { case: Some(action), then: self.boxed_contract(block, "contract")? })
+    }
+
+    fn choice_id(&self, block: Node<'a, 'input>) -> Result<ChoiceId, String> {
+        Ok(ChoiceId { choice_name: self.field(block, "choice_name")?, choice_owner: self.party(block, "party")? })
+    }
+
+    fn party(&self, parent: Node<'a, 'input>, name: &str) -> Result<Option<Party>, String> {
+        let block = match self.input(parent, name) { Some(b) => b, None => return Ok(None) };
+        Ok(Some(match self.block_type(block) {
+            "RolePartyType" => Party::Role { role_token: self.field(block, "role")? },
+            "PKPartyType" => Party::PK { pk_hash: self.field(block, "pubkey")? },
+            _ => return self.unexpected(block, "a party")
+        }))
+    }
+
+    fn payee(&self, parent: Node<'a, 'input>, name: &str) -> Result<Option<Payee>, String> {
+        let block = match self.input(parent, name) { Some(b) => b, None => return Ok(None) };
+        Ok(Some(match self.block_type(block) {
+            "AccountPayeeType" => Payee::Account(self.party(block, "party")?),
+            "PartyPayeeType" => Payee::Party(self.party(block, "party")?),
+            _ => return self.unexpected(block, "a payee")
+        }))
+    }
+
+    fn token(&self, parent: Node<'a, 'input>, name: &str) -> Result<Option<Token>, String> {
+        let block = match self.input(parent, name) { Some(b) => b, None => return Ok(None) };
+        Ok(Some(match self.block_type(block) {
+            "AdaTokenType" => Token::ADA,
+            "CustomTokenType" => {
+                let currency_symbol = self.field(block, "currency_symbol")?;
+                let token_name = self.field(block, "token_name")?;
+                if currency_symbol.is_empty() && token_name.is_empty() {
+                    Token::ADA
+                } else {
+                    Token::Custom { currency_symbol, token_name }
+                }
+            }
+            _ => return self.unexpected(block, "a token")
+        }))
+    }
+
+    fn boxed_value(&self, parent: Node<'a, 'input>, name: &str) -> Result<Option<Box<Value>>, String> {
+        Ok(self.value(parent, name)?.map(Box::new))
+    }
+
+    fn value(&self, parent: Node<'a, 'input>, name: &str) -> Result<Option<Value>, String> {
+        let block = match self.input(parent, name) { Some(b) => b, None => return Ok(None) };
+        Ok(Some(match self.block_type(block) {
+            "AvailableMoneyValueType" => Value::AvailableMoney(self.party(block, "party")?, self.token(block, "token")?),
+            "ConstantValueType" => Value::ConstantValue(self.number(block, "constant")?),
+            "ConstantParamValueType" => Value::ConstantParam(self.field(block, "paramName")?),
+            "NegValueValueType" => Value::NegValue(self.boxed_value(block, "value")?),
+            "AddValueValueType" => Value::AddValue(self.boxed_value(block, "value1")?, self.boxed_value(block, "value2")?),
+            "SubValueValueType" => Value::SubValue(self.boxed_value(block, "value1")?, self.boxed_value(block, "value2")?),
+            "MulValueValueType" => Value::MulValue(self.boxed_value(block, "value1")?, self.boxed_value(block, "value2")?),
+            "DivValueValueType" => Value::DivValue(self.boxed_value(block, "value1")?, self.boxed_value(block, "value2")?),
+            "ChoiceValueValueType" => Value::ChoiceValue(Some(self.choice_id(block)?)),
+            "TimeIntervalStartValueType" => Value::TimeIntervalStart,
+            "TimeIntervalEndValueType" => Value::TimeIntervalEnd,
+            "UseValueValueType" => Value::UseValue(self.field(block, "value_id")?),
+            "CondObservationValueValueType" => Value::Cond(
+                self.observation(block, "condition")?,
+                self.boxed_value(block, "then")?,
+                self.boxed_value(block, "else")?,
+            ),
+            _ => return self.unexpected(block, "a value")
+        }))
+    }
+
+    fn boxed_observation(&self, parent: Node<'a, 'input>, name: &str) -> Result<Option<Box<Observation>>, String> {
+        Ok(self.observation(parent, name)?.map(Box::new))
+    }
+
+    fn observation(&self, parent: Node<'a, 'input>, name: &str) -> Result<Option<Observation>, String> {
+        let block = match self.input(parent, name) { Some(b) => b, None => return Ok(None) };
+        let (a, b) = ("value1", "value2");
+        Ok(Some(match self.block_type(block) {
+            "AndObservationType" => Observation::AndObs {
+                both: self.boxed_observation(block, "observation1")?,
+                and: self.boxed_observation(block, "observation2")?,
+            },
+            "OrObservationType" => Observation::OrObs {
+                either: self.boxed_observation(block, "observation1")?,
+                or: self.boxed_observation(block, "observation2")?,
+            },
+            "NotObservationType" => Observation::NotObs { not: self.boxed_observation(block, "observation")? },
+            "ChoseSomethingObservationType" => Observation::ChoseSomething(Some(self.choice_id(block)?)),
+            "ValueGEObservationType" => Observation::ValueGE { value: self.boxed_value(block, a)?, ge_than: self.boxed_value(block, b)? },
+            "ValueGTObservationType" => Observation::ValueGT { value: self.boxed_value(block, a)?, gt_than: self.boxed_value(block, b)? },
+            "ValueLTObservationType" => Observation::ValueLT { value: self.boxed_value(block, a)?, lt_than: self.boxed_value(block, b)? },
+            "ValueLEObservationType" => Observation::ValueLE { value: self.boxed_value(block, a)?, le_than: self.boxed_value(block, b)? },
+            "ValueEQObservationType" => Observation::ValueEQ { value: self.boxed_value(block, a)?, equal_to: self.boxed_value(block, b)? },
+            "TrueObservationType" => Observation::True,
+            "FalseObservationType" => Observation::False,
+            _ => return self.unexpected(block, "an observation")
+        }))
+    }
+}
+
+/// Reads the contract of a Blockly workspace.
+pub fn xml_to_contract(xml: &str) -> Result<Contract, String> {
+    let document = Document::parse(xml).map_err(|e| format!("Invalid XML: {e}"))?;
+    let reader = Reader { document: &document };
+    let root = document.root_element();
+    let blocks: Vec<Node> = root.children().filter(|c| is_element(c, "block")).collect();
+    // Loose blocks next to the base block are not part of the contract.
+    if let Some(base) = blocks.iter().find(|b| reader.block_type(**b) == "BaseContractType") {
+        return match reader.input(*base, "BaseContractType") {
+            Some(contract) => reader.contract(contract),
+            None => reader.error(*base, String::from("The workspace does not contain a contract.")),
+        }
+    }
+    match blocks.as_slice() {
+        [block] => reader.contract(*block),
+        [] => reader.error(root, String::from("The workspace does not contain any blocks.")),
+        _ => reader.error(root, String::from("The workspace has no 'BaseContractType' block, and more than one block at the top level.")),
+    }
+}
+
+/// Converts a Blockly workspace to Marlowe DSL.
+pub fn xml_to_dsl(xml: &str) -> Result<String, String> {
+    Ok(format!("{:#}", xml_to_contract(xml)?))
+}
+
+// -- Writing ------------------------------------------------------------------
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+/// A block with its fields and inputs, before it is written as XML.
+struct Block {
+    kind: &'static str,
+    fields: Vec<(&'static str, String)>,
+    /// Inputs by name, with a flag telling if it is a statement input.
+    inputs: Vec<(&'static str, bool, Option<Block>)>,
+    next: Option<Box<Block>>,
+}
+
+impl Block {
+    fn new(kind: &'static str) -> Block {
+        Block { kind, fields: vec![], inputs: vec![], next: None }
+    }
+
+    fn field(mut self, name: &'static str, value: impl ToString) -> Block {
+        self.fields.push((name, value.to_string()));
+        self
+    }
+
+    fn value(mut self, name: &'static str, block: Option<Block>) -> Block {
+        self.inputs.push((name, false, block));
+        self
+    }
+
+    fn statement(mut self, name: &'static str, block: Option<Block>) -> Block {
+        self.inputs.push((name, true, block));
+        self
+    }
+
+    fn write(&self, out: &mut String, indent: usize, attributes: &str) {
+        let pad = " ".repeat(indent);
+        if self.fields.is_empty() && self.inputs.iter().all(|(_, _, block)| block.is_none()) && self.next.is_none() {
+            out.push_str(&format!("{pad}<block type=\"{}\"{attributes}/>\n", self.kind));
+            return
+        }
+        out.push_str(&format!("{pad}<block type=\"{}\"{attributes}>\n", self.kind));
+        for (name, value) in &self.fields {
+            out.push_str(&format!("{pad}  <field name=\"{name}\">{}</field>\n", escape(value)));
+        }
+        for (name, statement, block) in &self.inputs {
+            if let Some(block) = block {
+                let tag = if *statement { "statement" } else { "value" };
+                out.push_str(&format!("{pad}  <{tag} name=\"{name}\">\n"));
+                block.write(out, indent + 4, "");
+                out.push_str(&format!("{pad}  </{tag}>\n"));
+            }
+        }
+        if let Some(next) = &self.next {
+            out.push_str(&format!("{pad}  <next>\n"));
+            next.write(out, indent + 4, "");
+            out.push_str(&format!("{pad}  </next>\n"));
+        }
+        out.push_str(&format!("{pad}</block>\n"));
+    }
+}
+
+/// Links blocks into a chain of statements.
+fn chain(blocks: Vec<Block>) -> Option<Block> {
+    blocks.into_iter().rev().fold(None, |next, mut block| {
+        block.next = next.map(Box::new);
+        Some(block)
+    })
+}
+
+fn boxed_contract(contract: &Option<Box<Contract>>) -> Option<Block> {
+    contract.as_deref().map(contract_block)
+}
+
+fn contract_block(contract: &Contract) -> Block {
+    match contract {
+        Contract::Close => Block::new("CloseContractType"),
+        Contract::Pay { from_account, to, token, pay, then } => Block::new("PayContractType")
+            .value("party", party_block(from_account))
+            .value("payee", to.as_ref().map(payee_block))
+            .value("token", token_block(token))
+            .value("value", pay.as_ref().map(value_block))
+            .statement("contract", boxed_contract(then)),
+        Contract::If { r#if, then, r#else } => Block::new("IfContractType")
+            .value("observation", r#if.as_ref().map(observation_block))
+            .statement("contract1", boxed_contract(then))
+            .statement("contract2", boxed_contract(r#else)),
+        Contract::When { when, timeout, timeout_continuation } => {
+            // Blockly has no way to show a missing case in the middle of the list.
+            let cases = when.iter().flatten().map(case_block).collect();
+            let block = match timeout {
+                Some(Timeout::TimeConstant(time)) => Block::new("WhenContractType").field("timeout_type", "time").field("timeout", time),
+                Some(Timeout::TimeParam(name)) => Block::new("WhenContractType").field("timeout_type", "time_param").field("timeout", name),
+                None => Block::new("WhenContractType").field("timeout_type", "time").field("timeout", ""),
+            };
+            block.statement("case", chain(cases)).statement("contract", boxed_contract(timeout_continuation))
+        }
+        Contract::Let { r#let, be, then } => Block::new("LetContractType")
+            .field("value_id", r#let)
+            .value("value", be.as_deref().map(value_block))
+            .statement("contract", boxed_contract(then)),
+        Contract::Assert { assert, then } => Block::new("AssertContractType")
+            .value("observation", assert.as_ref().map(observation_block))
+            .statement("contract", boxed_contract(then)),
+    }
+}
+
+fn case_block(case: &Case) -> Block {
+    let block = match &case.case {
+        Some(Action::Deposit { party, of_token, into_account, deposits }) => Block::new("DepositActionType")
+            .value("party", party_block(into_account))
+            .value("from_party", party_block(party))
+            .value("token", token_block(of_token))
+            .value("value", deposits.as_ref().map(value_block)),
+        Some(Action::Choice { for_choice, choose_between }) => {
+            let bounds = choose_between.iter().flatten().map(|Bound(from, to)| Block::new("BoundsType").field("from", from).field("to", to)).collect();
+            choice_id_block(Block::new("ChoiceActionType"), for_choice).statement("bounds", chain(bounds))
+        }
+        Some(Action::Notify { notify_if }) => Block::new("NotifyActionType").value("observation", notify_if.as_ref().map(observation_block)),
+        // An action hole: there is no block for it, so we use a notification without an observation.
+        None => Block::new("NotifyActionType"),
+    };
+    block.statement("contract", boxed_contract(&case.then))
+}
+
+fn choice_id_block(block: Block, choice_id: &Option<ChoiceId>) -> Block {
+    match choice_id {
+        Some(ChoiceId { choice_name, choice_owner }) => block.field("choice_name", choice_name).value("party", party_block(choice_owner)),
+        None => block.field("choice_name", ""),
+    }
+}
+
+fn party_block(party: &Option<Party>) -> Option<Block> {
+    party.as_ref().map(|party| match party {
+        Party::Role { role_token } => Block::new("RolePartyType").field("role", role_token),
+        Party::PK { pk_hash } => Block::new("PKPartyType").field("pubkey", pk_hash),
+    })
+}
+
+fn payee_block(payee: &Payee) -> Block {
+    match payee {
+        Payee::Account(party) => Block::new("AccountPayeeType").value("party", party_block(party)),
+        Payee::Party(party) => Block::new("PartyPayeeType").value("party", party_block(party)),
+    }
+}
+
+fn token_block(token: &Option<Token>) -> Option<Block> {
+    token.as_ref().map(|token| match token {
+        Token::ADA => Block::new("AdaTokenType"),
+        Token::Custom { currency_symbol, token_name } => Block::new("CustomTokenType")
+            .field("currency_symbol", currency_symbol)
+            .field("token_name", token_name),
+    })
+}
+
+fn boxed_value_block(value: &Option<Box<Value>>) -> Option<Block> {
+    value.as_deref().map(value_block)
+}
+
+fn value_block(value: &Value) -> Block {
+    let binary = |kind, a, b| Block::new(kind).value("value1", boxed_value_block(a)).value("value2", boxed_value_block(b));
+    match value {
+        Value::TimeIntervalStart => Block::new("TimeIntervalStartValueType"),
+        Value::TimeIntervalEnd => Block::new("TimeIntervalEndValueType"),
+        Value::AvailableMoney(party, token) => Block::new("AvailableMoneyValueType")
+            .value("party", party_block(party))
+            .value("token", token_block(token)),
+        Value::ConstantValue(n) => Block::new("ConstantValueType").field("constant", n),
+        Value::ConstantParam(name) => Block::new("ConstantParamValueType").field("paramName", name),
+        Value::UseValue(name) => Block::new("UseValueValueType").field("value_id", name),
+        Value::AddValue(a, b) => binary("AddValueValueType", a, b),
+        Value::SubValue(a, b) => binary("SubValueValueType", a, b),
+        Value::MulValue(a, b) => binary("MulValueValueType", a, b),
+        Value::DivValue(a, b) => binary("DivValueValueType", a, b),
+        Value::NegValue(v) => Block::new("NegValueValueType").value("value", boxed_value_block(v)),
+        Value::ChoiceValue(choice_id) => choice_id_block(Block::new("ChoiceValueValueType"), choice_id),
+        Value::Cond(obs, a, b) => Block::new("CondObservationValueValueType")
+            .value("condition", obs.as_ref().map(observation_block))
+            .value("then", boxed_value_block(a))
+            .value("else", boxed_value_block(b)),
+    }
+}
+
+fn boxed_observation_block(observation: &Option<Box<Observation>>) -> Option<Block> {
+    observation.as_deref().map(observation_block)
+}
+
+fn observation_block(observation: &Observation) -> Block {
+    let values = |kind, a, b| Block::new(kind).value("value1", boxed_value_block(a)).value("value2", boxed_value_block(b));
+    let observations = |kind, a, b| Block::new(kind).value("observation1", boxed_observation_block(a)).value("observation2", boxed_observation_block(b));
+    match observation {
+        Observation::ValueGT { value, gt_than } => values("ValueGTObservationType", value, gt_than),
+        Observation::ValueGE { value, ge_than } => values("ValueGEObservationType", value, ge_than),
+        Observation::ValueLT { value, lt_than } => values("ValueLTObservationType", value, lt_than),
+        Observation::ValueLE { value, le_than } => values("ValueLEObservationType", value, le_than),
+        Observation::ValueEQ { value, equal_to } => values("ValueEQObservationType", value, equal_to),
+        Observation::True => Block::new("TrueObservationType"),
+        Observation::False => Block::new("FalseObservationType"),
+        Observation::ChoseSomething(choice_id) => choice_id_block(Block::new("ChoseSomethingObservationType"), choice_id),
+        Observation::OrObs { either, or } => observations("OrObservationType", either, or),
+        Observation::AndObs { both, and } => observations("AndObservationType", both, and),
+        Observation::NotObs { not } => Block::new("NotObservationType").value("observation", boxed_observation_block(not)),
+    }
+}
+
+/// Writes a contract as a Blockly workspace.
+pub fn contract_to_xml(contract: &Contract) -> String {
+    let mut out = format!("<xml xmlns=\"{NAMESPACE}\">\n");
+    Block::new("BaseContractType")
+        .statement("BaseContractType", Some(contract_block(contract)))
+        .write(&mut out, 2, " x=\"13\" y=\"187\"");
+    out.push_str("</xml>\n");
+    out
+}
+
+/// Converts Marlowe DSL to a Blockly workspace.
+pub fn dsl_to_xml(source: &str) -> Result<String, String> {
+    Ok(contract_to_xml(&parse_contract(source)?.contract))
+}
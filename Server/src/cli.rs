@@ -0,0 +1,247 @@
+// Headless mode, for checking contracts without an editor (in CI for example):
+//
+//   marlowe_lsp check <paths>                        errors, rustc style
+//   marlowe_lsp lint [--format human|json|sarif|junit] <paths>
+//                                                    errors and warnings
+//   marlowe_lsp fmt [--check] <paths>                formats files in place, or lists
+//                                                    the ones that are not formatted
+//
+// Directories are searched for .marlowe and .marlowe.json files. Every file goes
+// through update_asts/get_diagnostics like an open document in the editor, so
+// parameter files and continuation maps next to it are used as well. The
+// configuration is read from marlowe-lsp.toml in the current directory, see config.rs.
+//
+// Exit codes: 0 if all is well, 1 if there are errors (or unformatted files with
+// fmt --check), 2 if the arguments or the files could not be read.
+
+use std::io::{IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use codespan_reporting::diagnostic::{Diagnostic as Report, Label, Severity};
+use codespan_reporting::files::SimpleFile;
+use codespan_reporting::term::{self, termcolor::{ColorChoice, StandardStream}};
+use lsp_types::{Diagnostic, DiagnosticSeverity, Url};
+use serde_json::json;
+use crate::codespan_lsp_local::range_to_byte_span;
+use crate::config::Config;
+use crate::format::format_document;
+use crate::report::{FileReport, severity, severity_name};
+use crate::rules::{rule_for, rule_id};
+use crate::Document;
+
+const USAGE: &str = "\
+Usage: marlowe_lsp <command> [options] <files or directories>
+
+Commands:
+    check                       Report errors
+    lint [--format <format>]    Report errors and warnings, as human (default), json, sarif or junit
+    fmt [--check]               Format files in place, or with --check only list the ones that are not formatted
+
+Without a command, marlowe_lsp runs as a language server on stdin/stdout, or with
+--listen tcp:<address> or --listen ws:<address> for editors that connect to it.";
+
+#[derive(Debug, Default)]
+struct Options {
+    paths: Vec<String>,
+    format: Option<String>,
+    check: bool,
+}
+
+/// Runs a command line command. Returns None if the arguments are not a command.
+pub fn run(args: &[String]) -> Option<i32> {
+    let command = args.first()?.as_str();
+    if !matches!(command, "check" | "lint" | "fmt" | "help" | "--help" | "-h") {
+        return None
+    }
+    let options = match parse_options(command, &args[1..]) {
+        Ok(options) => options,
+        Err(message) => {
+            eprintln!("{message}\n\n{USAGE}");
+            return Some(2)
+        }
+    };
+    let files = match collect_files(&options.paths) {
+        Ok(files) => files,
+        Err(message) => {
+            eprintln!("{message}");
+            return Some(2)
+        }
+    };
+    let (config, problems) = Config::load(None, None, std::env::current_dir().ok().as_deref());
+    for problem in problems {
+        eprintln!("{problem}")
+    }
+    Some(match command {
+        "check" => lint(&files, &config, "human", true),
+        "lint" => lint(&files, &config, options.format.as_deref().unwrap_or("human"), false),
+        "fmt" => fmt(&files, &config, options.check),
+        _ => { println!("{USAGE}"); 0 }
+    })
+}
+
+fn parse_options(command: &str, args: &[String]) -> Result<Options, String> {
+    let mut options = Options::default();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--check" if command == "fmt" => options.check = true,
+            "--format" if command == "lint" => options.format = Some(args.next().ok_or("--format expects a value.")?.clone()),
+            a if command == "lint" && a.starts_with("--format=") => options.format = Some(a["--format=".len()..].to_string()),
+            a if a.starts_with('-') => return Err(format!("Unknown option for {command}: {a}")),
+            path => options.paths.push(path.to_string()),
+        }
+    }
+    if let Some(format) = &options.format {
+        if !matches!(format.as_str(), "human" | "json" | "sarif" | "junit") {
+            return Err(format!("Unknown format: {format}"))
+        }
+    }
+    if options.paths.is_empty() && !matches!(command, "help" | "--help" | "-h") {
+        return Err(format!("{command} expects at least one file or directory."))
+    }
+    Ok(options)
+}
+
+fn is_contract_file(path: &Path) -> bool {
+    let name = path.file_name().and_then(|n| n.to_str()).unwrap_or_default();
+    name.ends_with(".marlowe") || name.ends_with(".marlowe.json")
+}
+
+fn collect_files(paths: &[String]) -> Result<Vec<PathBuf>, String> {
+    fn walk(dir: &Path, files: &mut Vec<PathBuf>) -> Result<(), String> {
+        let entries = std::fs::read_dir(dir).map_err(|e| format!("Could not read {}: {e}", dir.display()))?;
+        let mut paths: Vec<PathBuf> = entries.filter_map(|e| Some(e.ok()?.path())).collect();
+        paths.sort();
+        for path in paths {
+            let hidden = path.file_name().and_then(|n| n.to_str()).is_some_and(|n| n.starts_with('.'));
+            if path.is_dir() && !hidden {
+                walk(&path, files)?
+            } else if is_contract_file(&path) {
+                files.push(path)
+            }
+        }
+        Ok(())
+    }
+    let mut files = vec![];
+    for path in paths.iter().map(PathBuf::from) {
+        if path.is_dir() {
+            walk(&path, &mut files)?
+        } else if path.is_file() {
+            files.push(path)
+        } else {
+            return Err(format!("No such file or directory: {}", path.display()))
+        }
+    }
+    Ok(files)
+}
+
+fn file_uri(path: &Path) -> Result<Url, String> {
+    let absolute = path.canonicalize().map_err(|e| format!("Could not read {}: {e}", path.display()))?;
+    Url::from_file_path(&absolute).map_err(|_| format!("Could not read {}", path.display()))
+}
+
+/// The diagnostics of a file, as they would be published to the editor.
+fn file_diagnostics(path: &Path, config: &Config) -> Result<(String, Vec<Diagnostic>), String> {
+    let text = std::fs::read_to_string(path).map_err(|e| format!("Could not read {}: {e}", path.display()))?;
+    let document = Document::parse_with_config(file_uri(path)?, &text, config);
+    Ok((text, document.diagnostics().to_vec()))
+}
+
+fn report(file: &SimpleFile<String, String>, uri: &Url, diagnostic: &Diagnostic) -> Report<()> {
+    let span = |range| range_to_byte_span(file, (), range).unwrap_or(0..0);
+    let mut labels = vec![Label::primary((), span(&diagnostic.range))];
+    for info in diagnostic.related_information.iter().flatten().filter(|info| &info.location.uri == uri) {
+        labels.push(Label::secondary((), span(&info.location.range)).with_message(&info.message))
+    }
+    let severity = match severity(diagnostic) {
+        DiagnosticSeverity::ERROR => Severity::Error,
+        DiagnosticSeverity::WARNING => Severity::Warning,
+        DiagnosticSeverity::INFORMATION => Severity::Note,
+        _ => Severity::Help,
+    };
+    Report::new(severity).with_message(&diagnostic.message).with_labels(labels).with_code(rule_id(diagnostic))
+}
+
+fn lint(files: &[PathBuf], config: &Config, format: &str, errors_only: bool) -> i32 {
+    let color = if std::io::stderr().is_terminal() { ColorChoice::Auto } else { ColorChoice::Never };
+    let writer = StandardStream::stderr(color);
+    let term_config = term::Config::default();
+    let mut failed = false;
+    let mut reports = vec![];
+    for path in files {
+        let (text, diagnostics) = match file_diagnostics(path, config) {
+            Ok(result) => result,
+            Err(message) => {
+                eprintln!("{message}");
+                failed = true;
+                continue
+            }
+        };
+        let diagnostics: Vec<Diagnostic> = diagnostics.into_iter()
+            .filter(|d| !errors_only || severity(d) == DiagnosticSeverity::ERROR)
+            .collect();
+        if format == "human" {
+            let uri = file_uri(path).unwrap_or_else(|_| Url::parse("file:///").unwrap());
+            let file = SimpleFile::new(path.display().to_string(), text);
+            for diagnostic in &diagnostics {
+                if let Err(e) = term::emit(&mut writer.lock(), &term_config, &file, &report(&file, &uri, diagnostic)) {
+                    eprintln!("{e}")
+                }
+            }
+        }
+        reports.push(FileReport { path: path.display().to_string(), diagnostics });
+    }
+    let count = |severity_to_count| reports.iter().flat_map(|r| &r.diagnostics).filter(|d| severity(d) == severity_to_count).count();
+    let (errors, warnings) = (count(DiagnosticSeverity::ERROR), count(DiagnosticSeverity::WARNING));
+    let output = match format {
+        "json" => {
+            let items: Vec<serde_json::Value> = reports.iter().flat_map(|report| report.diagnostics.iter().map(|d| json!({
+                "file": report.path,
+                "range": d.range,
+                "severity": severity_name(severity(d)),
+                "rule": rule_id(d),
+                "name": rule_for(d).map(|rule| rule.name),
+                "message": d.message,
+            }))).collect();
+            serde_json::to_string_pretty(&items).unwrap_or_default()
+        }
+        "sarif" => serde_json::to_string_pretty(&crate::report::sarif(&reports)).unwrap_or_default(),
+        "junit" => crate::report::junit(&reports),
+        _ => {
+            eprintln!("{errors} error(s), {warnings} warning(s) in {} file(s)", files.len());
+            String::new()
+        }
+    };
+    if !output.is_empty() {
+        // Ignore errors from closed pipes (like `| head`)
+        let _ = writeln!(std::io::stdout(), "{}", output.trim_end());
+    }
+    if failed { 2 } else if errors > 0 { 1 } else { 0 }
+}
+
+fn fmt(files: &[PathBuf], config: &Config, check: bool) -> i32 {
+    let options = &config.format;
+    let mut code = 0;
+    for path in files {
+        let formatted = std::fs::read_to_string(path).map_err(|e| format!("Could not read {}: {e}", path.display()))
+            .and_then(|text| Ok((format_document(&file_uri(path)?, &text, options)?, text)));
+        match formatted {
+            Err(message) => {
+                eprintln!("{}: {message}", path.display());
+                code = code.max(1)
+            }
+            Ok((formatted, text)) if formatted == text => {}
+            Ok(_) if check => {
+                let _ = writeln!(std::io::stdout(), "{} is not formatted", path.display());
+                code = code.max(1)
+            }
+            Ok((formatted, _)) => match std::fs::write(path, formatted) {
+                Ok(()) => { let _ = writeln!(std::io::stdout(), "Formatted {}", path.display()); }
+                Err(e) => {
+                    eprintln!("Could not write {}: {e}", path.display());
+                    code = 2
+                }
+            },
+        }
+    }
+    code
+}
@@ -0,0 +1,428 @@
+// Exports a contract as source code for other Marlowe tool chains:
+//
+// - TypeScript for the marlowe-ts-sdk: a function that returns the contract as a
+//   `Contract` from @marlowe.io/language-core-v1 (the core JSON shape, with bigints)
+// - Haskell using Language.Marlowe.Extended.V1: a function that builds the
+//   contract with the usual constructors
+//
+// TimeParam and ConstantParam become arguments of the generated function, in the
+// order they first appear in the contract, so that templates stay templates.
+// Neither language can represent holes, so contracts with holes are rejected in
+// the same way as by the JSON export.
+
+use serde_json::Value as Json;
+use marlowe_lang::types::marlowe::*;
+use crate::contract_model::parse_contract;
+use crate::core_json::{Hole, contract_to_json, describe_holes};
+use crate::term_sheet::ParameterKind;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Language {
+    TypeScript,
+    Haskell,
+}
+
+const LINE_WIDTH: usize = 100;
+
+/// Generated code before layout.
+enum Expr {
+    Ident(String),
+    Str(String),
+    Int(i64),
+    /// A constructor applied to arguments (Haskell)
+    Call(&'static str, Vec<Expr>),
+    List(Vec<Expr>),
+    /// An object literal (TypeScript)
+    Object(Vec<(String, Expr)>),
+}
+
+const TS_KEYWORDS: &[&str] = &[
+    "break", "case", "catch", "class", "const", "continue", "debugger", "default", "delete", "do", "else", "enum",
+    "export", "extends", "false", "finally", "for", "function", "if", "import", "in", "instanceof", "new", "null",
+    "return", "super", "switch", "this", "throw", "true", "try", "typeof", "var", "void", "while", "with", "let",
+];
+const HASKELL_KEYWORDS: &[&str] = &[
+    "case", "class", "data", "default", "deriving", "do", "else", "foreign", "if", "import", "in", "infix", "infixl",
+    "infixr", "instance", "let", "module", "newtype", "of", "then", "type", "where",
+];
+
+/// Turns a name like "Payment deadline" into an identifier like paymentDeadline.
+fn identifier(name: &str, capitalize: bool) -> String {
+    let mut result = String::new();
+    for word in name.split(|c: char| !c.is_alphanumeric()).filter(|w| !w.is_empty()) {
+        let mut chars = word.chars();
+        let first = chars.next().unwrap();
+        if result.is_empty() && !capitalize {
+            result.extend(first.to_lowercase());
+        } else {
+            result.extend(first.to_uppercase());
+        }
+        result.push_str(chars.as_str());
+    }
+    match result.chars().next() {
+        None => String::from(if capitalize { "Contract" } else { "contract" }),
+        Some(c) if c.is_numeric() => format!("{}{result}", if capitalize { "P" } else { "p" }),
+        _ => result,
+    }
+}
+
+/// The arguments of the generated function.
+struct Params {
+    language: Language,
+    /// Identifiers that are already taken, like the name of the function itself.
+    reserved: Vec<String>,
+    params: Vec<(ParameterKind, String, String)>,
+}
+
+impl Params {
+    fn ident(&mut self, kind: ParameterKind, name: &str) -> Expr {
+        if let Some((_, _, ident)) = self.params.iter().find(|(k, n, _)| *k == kind && n == name) {
+            return Expr::Ident(ident.clone())
+        }
+        let keywords = match self.language { Language::TypeScript => TS_KEYWORDS, Language::Haskell => HASKELL_KEYWORDS };
+        let base = identifier(name, false);
+        let mut ident = base.clone();
+        let mut counter = 1;
+        while keywords.contains(&ident.as_str()) || self.reserved.contains(&ident) || self.params.iter().any(|(_, _, i)| *i == ident) {
+            counter += 1;
+            ident = format!("{base}{counter}");
+        }
+        self.params.push((kind, name.to_string(), ident.clone()));
+        Expr::Ident(ident)
+    }
+}
+
+// -- TypeScript ---------------------------------------------------------------
+
+/// Keys of core JSON objects in the order they are usually written.
+const KEY_ORDER: &[&str] = &[
+    "when", "timeout", "timeout_continuation", "case", "if", "let", "be", "assert",
+    "from_account", "from", "to", "token", "pay", "then", "else",
+    "party", "deposits", "of_token", "into_account", "for_choice", "choose_between", "notify_if",
+    "choice_name", "choice_owner", "currency_symbol", "token_name", "amount_of_token", "in_account",
+    "add", "both", "and", "either", "or", "value", "multiply", "times", "divide", "by",
+];
+
+fn typescript_expr(json: &Json, params: &mut Params) -> Expr {
+    match json {
+        Json::Null => Expr::Ident(String::from("null")),
+        Json::Bool(b) => Expr::Ident(b.to_string()),
+        Json::Number(n) => Expr::Int(n.as_i64().unwrap_or_default()),
+        Json::String(s) => Expr::Str(s.clone()),
+        Json::Array(items) => Expr::List(items.iter().map(|i| typescript_expr(i, params)).collect()),
+        Json::Object(fields) => {
+            if let Some(Json::String(name)) = fields.get("time_param") {
+                return params.ident(ParameterKind::TimeParam, name)
+            }
+            if let Some(Json::String(name)) = fields.get("constant_param") {
+                return params.ident(ParameterKind::ConstantParam, name)
+            }
+            let mut keys: Vec<&String> = fields.keys().collect();
+            keys.sort_by_key(|k| (KEY_ORDER.iter().position(|o| o == k).unwrap_or(KEY_ORDER.len()), k.to_string()));
+            Expr::Object(keys.into_iter().map(|k| (k.clone(), typescript_expr(&fields[k], params))).collect())
+        }
+    }
+}
+
+// -- Haskell --------------------------------------------------------------------
+
+fn call(name: &'static str, args: Vec<Expr>) -> Expr {
+    Expr::Call(name, args)
+}
+
+fn opt<T>(item: &Option<T>, f: impl FnOnce(&T) -> Expr) -> Expr {
+    match item {
+        Some(item) => f(item),
+        // Holes are rejected before we get here
+        None => Expr::Ident(String::from("undefined")),
+    }
+}
+
+struct Haskell<'a> {
+    params: &'a mut Params,
+}
+
+impl Haskell<'_> {
+
+    fn contract(&mut self, contract: &Option<Box<Contract>>) -> Expr {
+        opt(contract, |c| self.contract_node(c))
+    }
+
+    fn contract_node(&mut self, contract: &Contract) -> Expr {
+        match contract {
+            Contract::Close => Expr::Ident(String::from("Close")),
+            Contract::When { when, timeout, timeout_continuation } => {
+                let cases = when.iter().map(|case| opt(case, |case| {
+                    let action = opt(&case.case, |a| self.action(a));
+                    call("Case", vec![action, self.contract(&case.then)])
+                })).collect();
+                let timeout = opt(timeout, |t| match t {
+                    Timeout::TimeConstant(n) => call("POSIXTime", vec![Expr::Int(*n)]),
+                    Timeout::TimeParam(name) => self.params.ident(ParameterKind::TimeParam, name),
+                });
+                call("When", vec![Expr::List(cases), timeout, self.contract(timeout_continuation)])
+            }
+            Contract::If { r#if, then, r#else } => {
+                let observation = opt(r#if, |o| self.observation(o));
+                call("If", vec![observation, self.contract(then), self.contract(r#else)])
+            }
+            Contract::Let { r#let, be, then } => {
+                let value = opt(be, |v| self.value(v));
+                call("Let", vec![Expr::Str(r#let.clone()), value, self.contract(then)])
+            }
+            Contract::Assert { assert, then } => {
+                let observation = opt(assert, |o| self.observation(o));
+                call("Assert", vec![observation, self.contract(then)])
+            }
+            Contract::Pay { from_account, to, token, pay, then } => {
+                let to = opt(to, |p| match p {
+                    Payee::Account(p) => call("Account", vec![party(p)]),
+                    Payee::Party(p) => call("Party", vec![party(p)]),
+                });
+                let value = opt(pay, |v| self.value(v));
+                call("Pay", vec![party(from_account), to, opt(token, token_expr), value, self.contract(then)])
+            }
+        }
+    }
+
+    fn action(&mut self, action: &Action) -> Expr {
+        match action {
+            Action::Deposit { party: from, of_token, into_account, deposits } => {
+                let value = opt(deposits, |v| self.value(v));
+                call("Deposit", vec![party(into_account), party(from), opt(of_token, token_expr), value])
+            }
+            Action::Choice { for_choice, choose_between } => {
+                let bounds = choose_between.iter().map(|b| opt(b, |Bound(from, to)| call("Bound", vec![Expr::Int(*from), Expr::Int(*to)]))).collect();
+                call("Choice", vec![opt(for_choice, choice_id), Expr::List(bounds)])
+            }
+            Action::Notify { notify_if } => call("Notify", vec![opt(notify_if, |o| self.observation(o))]),
+        }
+    }
+
+    fn boxed_value(&mut self, value: &Option<Box<Value>>) -> Expr {
+        opt(value, |v| self.value(v))
+    }
+
+    fn boxed_observation(&mut self, observation: &Option<Box<Observation>>) -> Expr {
+        opt(observation, |o| self.observation(o))
+    }
+
+    fn value(&mut self, value: &Value) -> Expr {
+        match value {
+            Value::TimeIntervalStart => Expr::Ident(String::from("TimeIntervalStart")),
+            Value::TimeIntervalEnd => Expr::Ident(String::from("TimeIntervalEnd")),
+            Value::AvailableMoney(p, t) => call("AvailableMoney", vec![party(p), opt(t, token_expr)]),
+            Value::ConstantValue(n) => call("Constant", vec![Expr::Int(*n)]),
+            Value::ConstantParam(name) => self.params.ident(ParameterKind::ConstantParam, name),
+            Value::UseValue(name) => call("UseValue", vec![Expr::Str(name.clone())]),
+            Value::MulValue(a, b) => call("MulValue", vec![self.boxed_value(a), self.boxed_value(b)]),
+            Value::DivValue(a, b) => call("DivValue", vec![self.boxed_value(a), self.boxed_value(b)]),
+            Value::SubValue(a, b) => call("SubValue", vec![self.boxed_value(a), self.boxed_value(b)]),
+            Value::AddValue(a, b) => call("AddValue", vec![self.boxed_value(a), self.boxed_value(b)]),
+            Value::NegValue(a) => call("NegValue", vec![self.boxed_value(a)]),
+            Value::ChoiceValue(c) => call("ChoiceValue", vec![opt(c, choice_id)]),
+            Value::Cond(o, a, b) => {
+                let observation = opt(o, |o| self.observation(o));
+                call("Cond", vec![observation, self.boxed_value(a), self.boxed_value(b)])
+            }
+        }
+    }
+
+    fn observation(&mut self, observation: &Observation) -> Expr {
+        match observation {
+            Observation::True => Expr::Ident(String::from("TrueObs")),
+            Observation::False => Expr::Ident(String::from("FalseObs")),
+            Observation::ValueGT { value, gt_than } => call("ValueGT", vec![self.boxed_value(value), self.boxed_value(gt_than)]),
+            Observation::ValueGE { value, ge_than } => call("ValueGE", vec![self.boxed_value(value), self.boxed_value(ge_than)]),
+            Observation::ValueLT { value, lt_than } => call("ValueLT", vec![self.boxed_value(value), self.boxed_value(lt_than)]),
+            Observation::ValueLE { value, le_than } => call("ValueLE", vec![self.boxed_value(value), self.boxed_value(le_than)]),
+            Observation::ValueEQ { value, equal_to } => call("ValueEQ", vec![self.boxed_value(value), self.boxed_value(equal_to)]),
+            Observation::ChoseSomething(c) => call("ChoseSomething", vec![opt(c, choice_id)]),
+            Observation::OrObs { either, or } => call("OrObs", vec![self.boxed_observation(either), self.boxed_observation(or)]),
+            Observation::AndObs { both, and } => call("AndObs", vec![self.boxed_observation(both), self.boxed_observation(and)]),
+            Observation::NotObs { not } => call("NotObs", vec![self.boxed_observation(not)]),
+        }
+    }
+}
+
+fn party(party: &Option<Party>) -> Expr {
+    opt(party, |p| match p {
+        Party::Role { role_token } => call("Role", vec![Expr::Str(role_token.clone())]),
+        Party::PK { pk_hash } => call("PK", vec![Expr::Str(pk_hash.clone())]),
+    })
+}
+
+fn token_expr(token: &Token) -> Expr {
+    match token {
+        Token::ADA => call("Token", vec![Expr::Str(String::new()), Expr::Str(String::new())]),
+        Token::Custom { currency_symbol, token_name } => call("Token", vec![Expr::Str(currency_symbol.clone()), Expr::Str(token_name.clone())]),
+    }
+}
+
+fn choice_id(choice: &ChoiceId) -> Expr {
+    call("ChoiceId", vec![Expr::Str(choice.choice_name.clone()), party(&choice.choice_owner)])
+}
+
+// -- Layout ---------------------------------------------------------------------
+
+fn quote(s: &str) -> String {
+    serde_json::to_string(s).unwrap_or_default()
+}
+
+fn needs_parens(expr: &Expr) -> bool {
+    match expr {
+        Expr::Call(_, args) => !args.is_empty(),
+        Expr::Int(n) => *n < 0,
+        _ => false,
+    }
+}
+
+/// The expression on a single line.
+fn flat(expr: &Expr, language: Language) -> String {
+    match (expr, language) {
+        (Expr::Ident(i), _) => i.clone(),
+        (Expr::Str(s), _) => quote(s),
+        (Expr::Int(n), Language::TypeScript) => format!("{n}n"),
+        (Expr::Int(n), Language::Haskell) => n.to_string(),
+        (Expr::Call(name, args), _) => {
+            let mut out = name.to_string();
+            for arg in args {
+                let arg_text = flat(arg, language);
+                if needs_parens(arg) { out.push_str(&format!(" ({arg_text})")) } else { out.push_str(&format!(" {arg_text}")) }
+            }
+            out
+        }
+        (Expr::List(items), _) => format!("[{}]", items.iter().map(|i| flat(i, language)).collect::<Vec<String>>().join(", ")),
+        (Expr::Object(fields), _) => {
+            let fields: Vec<String> = fields.iter().map(|(k, v)| format!("{k}: {}", flat(v, language))).collect();
+            format!("{{ {} }}", fields.join(", "))
+        }
+    }
+}
+
+/// The expression starting at the given column, broken over several lines when it does not fit.
+fn render(expr: &Expr, language: Language, indent: usize, out: &mut String) {
+    let single_line = flat(expr, language);
+    if indent + single_line.len() <= LINE_WIDTH {
+        out.push_str(&single_line);
+        return
+    }
+    let pad = |n: usize| " ".repeat(n);
+    match (expr, language) {
+        (Expr::Call(name, args), _) => {
+            out.push_str(name);
+            for arg in args {
+                out.push_str(&format!("\n{}", pad(indent + 4)));
+                if needs_parens(arg) {
+                    out.push('(');
+                    render(arg, language, indent + 5, out);
+                    out.push(')');
+                } else {
+                    render(arg, language, indent + 4, out);
+                }
+            }
+        }
+        (Expr::List(items), Language::Haskell) => {
+            for (index, item) in items.iter().enumerate() {
+                if index > 0 {
+                    out.push_str(&format!("\n{}", pad(indent)));
+                }
+                out.push_str(if index == 0 { "[ " } else { ", " });
+                render(item, language, indent + 2, out);
+            }
+            out.push_str(&format!("\n{}]", pad(indent)));
+        }
+        (Expr::List(items), Language::TypeScript) => {
+            out.push('[');
+            for item in items {
+                out.push_str(&format!("\n{}", pad(indent + 2)));
+                render(item, language, indent + 2, out);
+                out.push(',');
+            }
+            out.push_str(&format!("\n{}]", pad(indent)));
+        }
+        (Expr::Object(fields), _) => {
+            out.push('{');
+            for (key, value) in fields {
+                out.push_str(&format!("\n{}{key}: ", pad(indent + 2)));
+                render(value, language, indent + 2, out);
+                out.push(',');
+            }
+            out.push_str(&format!("\n{}}}", pad(indent)));
+        }
+        _ => out.push_str(&single_line),
+    }
+}
+
+fn kind_description(kind: ParameterKind, name: &str) -> String {
+    match kind {
+        ParameterKind::TimeParam => format!("(TimeParam {})", quote(name)),
+        ParameterKind::ConstantParam => format!("(ConstantParam {})", quote(name)),
+    }
+}
+
+/// Generates a TypeScript module that exports the contract as a function of its parameters.
+pub fn contract_to_typescript(name: &str, contract: &Contract) -> Result<String, Vec<Hole>> {
+    let json = contract_to_json(contract)?;
+    let function = identifier(name, false);
+    let mut params = Params { language: Language::TypeScript, reserved: vec![function.clone()], params: vec![] };
+    let body = typescript_expr(&json, &mut params);
+
+    let mut out = String::from("import { Contract } from \"@marlowe.io/language-core-v1\";\n\n");
+    if params.params.is_empty() {
+        out.push_str(&format!("export const {function}: Contract = "));
+        render(&body, Language::TypeScript, 0, &mut out);
+        out.push_str(";\n");
+    } else {
+        out.push_str("/**\n");
+        for (kind, name, ident) in &params.params {
+            let unit = if *kind == ParameterKind::TimeParam { "POSIX time in milliseconds" } else { "integer" };
+            out.push_str(&format!(" * @param {ident} {} ({unit})\n", kind_description(*kind, name)));
+        }
+        out.push_str(" */\n");
+        let arguments: Vec<String> = params.params.iter().map(|(_, _, ident)| format!("{ident}: bigint")).collect();
+        out.push_str(&format!("export const {function} = ({}): Contract => (", arguments.join(", ")));
+        render(&body, Language::TypeScript, 0, &mut out);
+        out.push_str(");\n");
+    }
+    Ok(out)
+}
+
+/// Generates a Haskell module that exports the contract as a function of its parameters.
+pub fn contract_to_haskell(name: &str, contract: &Contract) -> Result<String, Vec<Hole>> {
+    // Reject holes the same way as the JSON export
+    contract_to_json(contract)?;
+    let function = identifier(name, false);
+    let mut params = Params { language: Language::Haskell, reserved: vec![function.clone()], params: vec![] };
+    let body = Haskell { params: &mut params }.contract_node(contract);
+
+    let mut out = format!(
+        "{{-# LANGUAGE OverloadedStrings #-}}\nmodule {} where\n\nimport Language.Marlowe.Extended.V1\n\n",
+        identifier(name, true));
+    if !params.params.is_empty() {
+        out.push_str("-- | Parameters:\n--\n");
+        for (kind, name, ident) in &params.params {
+            out.push_str(&format!("--   * @{ident}@: {}\n", kind_description(*kind, name)));
+        }
+    }
+    let types: Vec<&str> = params.params.iter()
+        .map(|(kind, _, _)| if *kind == ParameterKind::TimeParam { "Timeout" } else { "Value" })
+        .chain(std::iter::once("Contract"))
+        .collect();
+    out.push_str(&format!("{function} :: {}\n", types.join(" -> ")));
+    let arguments: String = params.params.iter().map(|(_, _, ident)| format!(" {ident}")).collect();
+    out.push_str(&format!("{function}{arguments} =\n    "));
+    render(&body, Language::Haskell, 4, &mut out);
+    out.push('\n');
+    Ok(out)
+}
+
+/// Generates code for a DSL document. Holes are reported with their line.
+pub fn dsl_to_code(source: &str, name: &str, language: Language) -> Result<String, String> {
+    let parsed = parse_contract(source)?;
+    let result = match language {
+        Language::TypeScript => contract_to_typescript(name, &parsed.contract),
+        Language::Haskell => contract_to_haskell(name, &parsed.contract),
+    };
+    result.map_err(|holes| describe_holes(&holes, &parsed))
+}
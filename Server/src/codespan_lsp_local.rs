@@ -40,8 +40,8 @@ where
     let source = files.source(file_id)?;
     let source = source.as_ref();
 
-    let line_span = files.line_range(file_id, position.line as usize).unwrap();
-    let line_str = source.get(line_span.clone()).unwrap();
+    let line_span = files.line_range(file_id, position.line as usize)?;
+    let line_str = source.get(line_span.clone()).ok_or(Error::InvalidCharBoundary { given: line_span.start })?;
 
     let byte_offset = character_to_line_offset(line_str, position.character)?;
 
@@ -0,0 +1,294 @@
+// workspace/executeCommand handlers.
+//
+// Commands take the uri of a document as their first argument. Documents that
+// are not open in the editor are read from disk. Commands that produce a new
+// document open it as an untitled document in the editor (window/showDocument)
+// and also return its text, so that clients without support for that can still
+// do something useful with the result.
+
+use serde_json::{json, Value};
+use tower_lsp::jsonrpc::{Error, Result};
+use tower_lsp::lsp_types::*;
+use crate::{MyLSPServer, file_stem};
+use crate::codegen::Language;
+use crate::contract_model::{ParsedContract, parse_contract};
+use crate::diagram::{DiagramFormat, DiagramOptions};
+use crate::json_document::{JsonDocument, is_json_document};
+use crate::term_sheet::{ContractFact, ParameterKind};
+
+pub const TO_CORE_JSON: &str = "marlowe.toCoreJson";
+pub const FROM_CORE_JSON: &str = "marlowe.fromCoreJson";
+pub const EXPORT_DOT: &str = "marlowe.exportDot";
+pub const EXPORT_MERMAID: &str = "marlowe.exportMermaid";
+pub const TERM_SHEET: &str = "marlowe.termSheet";
+pub const INSTANTIATE: &str = "marlowe.instantiate";
+pub const EXPORT_TYPESCRIPT: &str = "marlowe.exportTypeScript";
+pub const EXPORT_HASKELL: &str = "marlowe.exportHaskell";
+pub const TO_BLOCKLY: &str = "marlowe.toBlockly";
+pub const FROM_BLOCKLY: &str = "marlowe.fromBlockly";
+pub const MERKLEIZE: &str = "marlowe.merkleize";
+pub const TO_CBOR: &str = "marlowe.toCbor";
+pub const FROM_CBOR: &str = "marlowe.fromCbor";
+
+/// All commands, as advertised in the server capabilities.
+pub fn all() -> Vec<String> {
+    [TO_CORE_JSON, FROM_CORE_JSON, EXPORT_DOT, EXPORT_MERMAID, TERM_SHEET, INSTANTIATE, EXPORT_TYPESCRIPT, EXPORT_HASKELL, TO_BLOCKLY, FROM_BLOCKLY, MERKLEIZE, TO_CBOR, FROM_CBOR].iter().map(|c| c.to_string()).collect()
+}
+
+pub fn uri_argument(params: &ExecuteCommandParams) -> Result<Url> {
+    let argument = params.arguments.first()
+        .ok_or_else(|| Error::invalid_params(format!("'{}' expects the uri of a document as its first argument.", params.command)))?;
+    let text = argument.as_str().ok_or_else(|| Error::invalid_params(format!("Expected a uri, found: {argument}")))?;
+    Url::parse(text).map_err(|e| Error::invalid_params(format!("Invalid uri '{text}': {e}")))
+}
+
+/// Optional settings passed as the second argument of a command.
+pub fn options_argument<T: serde::de::DeserializeOwned + Default>(params: &ExecuteCommandParams) -> Result<T> {
+    match params.arguments.get(1) {
+        None | Some(Value::Null) => Ok(T::default()),
+        Some(options) => serde_json::from_value(options.clone()).map_err(|e| Error::invalid_params(format!("Invalid options: {e}"))),
+    }
+}
+
+/// The range of the whole text.
+pub fn full_range(text: &str) -> Range {
+    let last_line = text.rsplit('\n').next().unwrap_or_default();
+    Range::new(Position::new(0, 0), Position::new(text.split('\n').count() as u32 - 1, last_line.encode_utf16().count() as u32))
+}
+
+/// An edit that creates (or replaces) a file with the given text.
+pub fn create_file_edit(uri: &Url, text: String) -> WorkspaceEdit {
+    create_files_edit(vec![(uri.clone(), text)])
+}
+
+/// An edit that creates (or replaces) several files.
+pub fn create_files_edit(files: Vec<(Url, String)>) -> WorkspaceEdit {
+    let operations = files.into_iter().flat_map(|(uri, text)| [
+        DocumentChangeOperation::Op(ResourceOp::Create(CreateFile {
+            uri: uri.clone(),
+            options: Some(CreateFileOptions { overwrite: Some(true), ignore_if_exists: None }),
+            annotation_id: None,
+        })),
+        DocumentChangeOperation::Edit(TextDocumentEdit {
+            text_document: OptionalVersionedTextDocumentIdentifier { uri, version: None },
+            edits: vec![OneOf::Left(TextEdit { range: Range::default(), new_text: text })],
+        }),
+    ]).collect();
+    WorkspaceEdit {
+        document_changes: Some(DocumentChanges::Operations(operations)),
+        ..Default::default()
+    }
+}
+
+impl MyLSPServer {
+
+    /// The text of an open document, or of the file on disk if it is not open.
+    pub fn document_text(&self, uri: &Url) -> Result<String> {
+        if let Some(source) = crate::get_source(&self.state.lock().unwrap(), uri) {
+            return Ok(source)
+        }
+        let path = uri.to_file_path().map_err(|_| Error::invalid_params(format!("Unknown document: {uri}")))?;
+        std::fs::read_to_string(&path).map_err(|e| Error::invalid_params(format!("Could not read {}: {e}", path.display())))
+    }
+
+    /// The JSON translation of a document, if it is a JSON document.
+    pub fn json_document(&self, uri: &Url) -> Result<Option<JsonDocument>> {
+        if !is_json_document(uri) {
+            return Ok(None)
+        }
+        let text = self.document_text(uri)?;
+        JsonDocument::parse(&text)
+            .map(Some)
+            .map_err(|(message, range)| Error::invalid_params(format!("Line {}: {message}", range.start.line + 1)))
+    }
+
+    /// The DSL source of a document. JSON documents are read through their DSL translation.
+    pub fn contract_source(&self, uri: &Url) -> Result<String> {
+        match self.json_document(uri)? {
+            Some(document) => Ok(document.dsl),
+            None => self.document_text(uri).map(|text| crate::suppression::without_comments(&text)),
+        }
+    }
+
+    /// The contract in a document.
+    pub fn parsed_contract(&self, uri: &Url) -> Result<ParsedContract> {
+        parse_contract(&self.contract_source(uri)?).map_err(Error::invalid_params)
+    }
+
+    /// Validates the DSL source of a document and returns what the validator found in it.
+    pub fn contract_facts(&self, uri: &Url, source: String) -> Result<Vec<ContractFact>> {
+        let settings = crate::get_validation_settings(&self.state.lock().unwrap(), uri);
+        let (_, validation) = crate::parse_marlowe(&source, &settings)
+            .map_err(|(message, range)| Error::invalid_params(format!("Line {}: {message}", range.start.line + 1)))?;
+        Ok(validation.facts)
+    }
+
+    /// Creates an untitled document with the given text and shows it in the editor.
+    pub async fn open_untitled(&self, name: &str, text: String) -> Result<Option<Value>> {
+        let uri = Url::parse(&format!("untitled:{name}")).map_err(|e| Error::invalid_params(e.to_string()))?;
+        match self.client.apply_edit(create_file_edit(&uri, text.clone())).await {
+            Ok(res) if res.applied => {
+                let shown = self.client.send_request::<request::ShowDocument>(ShowDocumentParams {
+                    uri: uri.clone(),
+                    external: None,
+                    take_focus: Some(true),
+                    selection: None,
+                }).await;
+                if let Err(e) = shown {
+                    self.client.log_message(MessageType::WARNING, format!("Could not show {uri}: {e}")).await
+                }
+            }
+            Ok(_) => self.client.log_message(MessageType::WARNING, format!("The editor did not create {uri}")).await,
+            Err(err) => self.client.log_message(MessageType::ERROR, err).await,
+        }
+        Ok(Some(json!({ "uri": uri, "text": text })))
+    }
+
+    pub async fn run_command(&self, params: ExecuteCommandParams) -> Result<Option<Value>> {
+        match params.command.as_str() {
+            TO_CORE_JSON => {
+                let uri = uri_argument(&params)?;
+                let json = crate::core_json::dsl_to_json(&self.contract_source(&uri)?).map_err(Error::invalid_params)?;
+                self.open_untitled(&format!("{}.json", file_stem(&uri)), json).await
+            }
+            FROM_CORE_JSON => {
+                let uri = uri_argument(&params)?;
+                let dsl = crate::core_json::json_to_dsl(&self.document_text(&uri)?).map_err(Error::invalid_params)?;
+                self.open_untitled(&format!("{}.marlowe", file_stem(&uri)), dsl).await
+            }
+            EXPORT_DOT | EXPORT_MERMAID => {
+                let uri = uri_argument(&params)?;
+                let options: DiagramOptions = options_argument(&params)?;
+                let parsed = self.parsed_contract(&uri)?;
+                let (format, extension) = if params.command == EXPORT_DOT { (DiagramFormat::Dot, "dot") } else { (DiagramFormat::Mermaid, "mmd") };
+                let diagram = crate::diagram::render(&parsed.contract, format, &options);
+                self.open_untitled(&format!("{}.{extension}", file_stem(&uri)), diagram).await
+            }
+            TERM_SHEET => {
+                let uri = uri_argument(&params)?;
+                let json_document = self.json_document(&uri)?;
+                let source = match &json_document {
+                    Some(document) => document.dsl.clone(),
+                    None => crate::suppression::without_comments(&self.document_text(&uri)?),
+                };
+                let mut facts = self.contract_facts(&uri, source)?;
+                if let Some(document) = &json_document {
+                    for fact in &mut facts {
+                        fact.range = document.to_json_range(fact.range)
+                    }
+                }
+                let zone = self.state.lock().unwrap().config.time_zone().unwrap_or_default();
+                let sheet = crate::term_sheet::render(&file_stem(&uri), &facts, zone);
+                self.open_untitled(&format!("{}.md", file_stem(&uri)), sheet).await
+            }
+            INSTANTIATE => {
+                let uri = uri_argument(&params)?;
+                let source = self.contract_source(&uri)?;
+                let used = crate::params::used_params(&self.contract_facts(&uri, source.clone())?);
+                // Contracts that are not open have not had their parameter file loaded
+                let file = self.state.lock().unwrap().param_files.get(&uri).cloned();
+                let file = file.or_else(|| crate::params::load(&uri)).ok_or_else(|| Error::invalid_params(format!(
+                    "{} has no parameter file. Expected one of: {}", file_stem(&uri),
+                    crate::params::EXTENSIONS.iter().map(|e| format!("{}.{e}", file_stem(&uri))).collect::<Vec<String>>().join(", "))))?;
+                let instantiated = crate::params::instantiate(&source, &file, &used).map_err(Error::invalid_params)?;
+                self.open_untitled(&format!("{}.instantiated.marlowe", file_stem(&uri)), instantiated).await
+            }
+            EXPORT_TYPESCRIPT | EXPORT_HASKELL => {
+                let uri = uri_argument(&params)?;
+                let (language, extension) = if params.command == EXPORT_TYPESCRIPT { (Language::TypeScript, "ts") } else { (Language::Haskell, "hs") };
+                let code = crate::codegen::dsl_to_code(&self.contract_source(&uri)?, &file_stem(&uri), language).map_err(Error::invalid_params)?;
+                self.open_untitled(&format!("{}.{extension}", file_stem(&uri)), code).await
+            }
+            TO_BLOCKLY => {
+                let uri = uri_argument(&params)?;
+                let xml = crate::blockly::dsl_to_xml(&self.contract_source(&uri)?).map_err(Error::invalid_params)?;
+                self.open_untitled(&format!("{}.xml", file_stem(&uri)), xml).await
+            }
+            FROM_BLOCKLY => {
+                // The workspace is either passed as the second argument (pasted from the
+                // Playground), or it is the text of the document itself.
+                let uri = uri_argument(&params)?;
+                let text = self.document_text(&uri).unwrap_or_default();
+                let xml = match params.arguments.get(1) {
+                    None | Some(Value::Null) => text.clone(),
+                    Some(Value::String(xml)) => xml.clone(),
+                    Some(other) => return Err(Error::invalid_params(format!("Expected the Blockly XML as a string, found: {other}"))),
+                };
+                let dsl = crate::blockly::xml_to_dsl(&xml).map_err(Error::invalid_params)?;
+                if !uri.path().ends_with(".marlowe") {
+                    return self.open_untitled(&format!("{}.marlowe", file_stem(&uri)), dsl).await
+                }
+                // Replace the contents of the .marlowe document with the contract.
+                let edit = WorkspaceEdit {
+                    changes: Some([(uri.clone(), vec![TextEdit { range: full_range(&text), new_text: dsl.clone() }])].into_iter().collect()),
+                    ..Default::default()
+                };
+                match self.client.apply_edit(edit).await {
+                    Ok(res) if res.applied => {}
+                    Ok(_) => self.client.log_message(MessageType::WARNING, format!("The editor did not update {uri}")).await,
+                    Err(err) => self.client.log_message(MessageType::ERROR, err).await,
+                }
+                Ok(Some(json!({ "uri": uri, "text": dsl })))
+            }
+            MERKLEIZE => {
+                // The merkleized contract has to be next to its continuation map, so unlike the
+                // other commands this one creates real files next to the contract.
+                let uri = uri_argument(&params)?;
+                let (contract, continuations) = crate::merkle::merkleize(&self.contract_source(&uri)?).map_err(Error::invalid_params)?;
+                let contract_uri = uri.join(&format!("{}.merkleized.marlowe.json", file_stem(&uri)));
+                let map_uri = crate::merkle::candidate(&uri).ok_or_else(|| Error::invalid_params(format!("Can not create files next to {uri}")))?;
+                let contract_uri = contract_uri.map_err(|e| Error::invalid_params(format!("Can not create files next to {uri}: {e}")))?;
+                let edit = create_files_edit(vec![(map_uri.clone(), continuations), (contract_uri.clone(), contract)]);
+                match self.client.apply_edit(edit).await {
+                    Ok(res) if res.applied => {
+                        let shown = self.client.send_request::<request::ShowDocument>(ShowDocumentParams {
+                            uri: contract_uri.clone(),
+                            external: None,
+                            take_focus: Some(true),
+                            selection: None,
+                        }).await;
+                        if let Err(e) = shown {
+                            self.client.log_message(MessageType::WARNING, format!("Could not show {contract_uri}: {e}")).await
+                        }
+                    }
+                    Ok(_) => self.client.log_message(MessageType::WARNING, format!("The editor did not create {contract_uri}")).await,
+                    Err(err) => self.client.log_message(MessageType::ERROR, err).await,
+                }
+                Ok(Some(json!({ "uri": contract_uri, "continuationMap": map_uri })))
+            }
+            TO_CBOR => {
+                let uri = uri_argument(&params)?;
+                let options: crate::datum::DatumOptions = options_argument(&params)?;
+                let parsed = self.parsed_contract(&uri)?;
+                let mut encoder = crate::plutus_data::DataEncoder::default();
+                let file = self.state.lock().unwrap().param_files.get(&uri).cloned();
+                if let Some(file) = file.or_else(|| crate::params::load(&uri)) {
+                    encoder.time_params = file.values(ParameterKind::TimeParam);
+                    encoder.constant_params = file.values(ParameterKind::ConstantParam);
+                }
+                let hex = crate::datum::encode(&parsed.contract, &mut encoder, &options).map_err(Error::invalid_params)?;
+                self.open_untitled(&format!("{}.cbor.hex", file_stem(&uri)), hex).await
+            }
+            FROM_CBOR => {
+                // Like FROM_BLOCKLY: the hex is either the second argument or the text of the document.
+                let uri = uri_argument(&params)?;
+                let hex = match params.arguments.get(1) {
+                    None | Some(Value::Null) => self.document_text(&uri)?,
+                    Some(Value::String(hex)) => hex.clone(),
+                    Some(other) => return Err(Error::invalid_params(format!("Expected the CBOR as a hex string, found: {other}"))),
+                };
+                let decoded = crate::datum::decode(&hex).map_err(Error::invalid_params)?;
+                if let Some(state) = &decoded.state {
+                    let state = serde_json::to_string_pretty(state).map_err(|e| Error::invalid_params(e.to_string()))?;
+                    self.open_untitled(&format!("{}.state.json", file_stem(&uri)), state).await?;
+                }
+                let opened = self.open_untitled(&format!("{}.marlowe", file_stem(&uri)), format!("{:#}", decoded.contract)).await?;
+                let mut result = opened.unwrap_or_else(|| json!({}));
+                result["state"] = decoded.state.unwrap_or(Value::Null);
+                result["rolesCurrency"] = json!(decoded.roles_currency);
+                Ok(Some(result))
+            }
+            command => Err(Error::invalid_params(format!("Unknown command: {command}")))
+        }
+    }
+}
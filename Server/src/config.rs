@@ -0,0 +1,246 @@
+// Configuration of the server, from two places:
+//
+// - the "marlowe" section of the editor settings (workspace/configuration),
+// - a marlowe-lsp.toml file at the root of the workspace.
+//
+// Both have the same shape. Values from the file win over the editor settings, so
+// that a project can share its configuration (the command line only reads the file).
+// The analysis settings can also still be passed as initialization options.
+//
+//   timezone = "+01:00"            # for dates in term sheets, "UTC" or an offset
+//
+//   [rules]                        # off, hint, info, warn or error, by rule id or name
+//   ML020 = "off"
+//   hole-contract = "error"
+//
+//   [format]
+//   indentWidth = 2
+//   maxLineWidth = 80
+//
+//   [analysis]
+//   minimumWhenWindow = 60000
+//   timeParams = { "Payment deadline" = 1700000000000 }
+//   sizeLimits = { maxDatumSize = 4000 }
+//   explorationLimits = { maxStates = 10000, maxTransactions = 20 }
+//
+//   [[analysis.customRules]]      # see lints.rs
+//   id = "HOUSE001"
+//   node = "PK"
+//   message = "Use roles instead of public keys."
+//
+// Both are read again when they change: the editor sends
+// workspace/didChangeConfiguration and the file is watched like parameter files.
+
+use std::collections::HashMap;
+use std::path::Path;
+#[cfg(not(target_arch = "wasm32"))]
+use std::path::PathBuf;
+use lsp_types::{Diagnostic, DiagnosticSeverity, NumberOrString};
+use serde_json::Value as Json;
+#[cfg(not(target_arch = "wasm32"))]
+use tower_lsp::lsp_types::{ConfigurationItem, MessageType};
+#[cfg(not(target_arch = "wasm32"))]
+use crate::MyLSPServer;
+use crate::format::FormatOptions;
+use crate::rules::{self, LintRule};
+use crate::term_sheet::TimeZone;
+
+pub const FILE_NAME: &str = "marlowe-lsp.toml";
+
+/// The section of the editor settings.
+pub const SECTION: &str = "marlowe";
+
+/// The configured severity of a rule, or off.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RuleLevel {
+    Off,
+    Hint,
+    #[serde(alias = "information")]
+    Info,
+    #[serde(alias = "warning")]
+    Warn,
+    Error,
+}
+
+impl RuleLevel {
+    /// The severity of diagnostics, None when off.
+    pub fn severity(self) -> Option<DiagnosticSeverity> {
+        match self {
+            RuleLevel::Off => None,
+            RuleLevel::Hint => Some(DiagnosticSeverity::HINT),
+            RuleLevel::Info => Some(DiagnosticSeverity::INFORMATION),
+            RuleLevel::Warn => Some(DiagnosticSeverity::WARNING),
+            RuleLevel::Error => Some(DiagnosticSeverity::ERROR),
+        }
+    }
+}
+
+/// The configuration of the server, see [`Config::load`].
+#[derive(Clone, Debug, Default, serde::Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct Config {
+    /// Severities by rule id (ML020) or name (undefined-choice).
+    pub rules: HashMap<String, RuleLevel>,
+    /// For dates in term sheets, "UTC" or an offset like "+02:00".
+    pub timezone: Option<String>,
+    pub format: FormatOptions,
+    pub analysis: crate::ValidationSettings,
+}
+
+impl Config {
+
+    /// Reads the configuration from the editor settings and the file in the workspace root, which wins.
+    /// Initialization options are the oldest way to pass analysis settings, they come first.
+    /// Problems are returned as messages, the parts of the configuration without problems are used.
+    pub fn load(initialization_options: Option<&Json>, settings: Option<&Json>, root: Option<&Path>) -> (Config, Vec<String>) {
+        let mut problems = vec![];
+        let mut merged = Json::Object(Default::default());
+        if let Some(options) = initialization_options.filter(|o| o.is_object()) {
+            merged["analysis"] = options.clone();
+        }
+        if let Some(settings) = settings.filter(|s| s.is_object()) {
+            merge(&mut merged, settings.clone());
+        }
+        if let Some(path) = root.map(|root| root.join(FILE_NAME)).filter(|path| path.is_file()) {
+            match std::fs::read_to_string(&path).map_err(|e| e.to_string()).and_then(|text| toml::from_str::<Json>(&text).map_err(|e| e.to_string())) {
+                Ok(file) => merge(&mut merged, file),
+                Err(e) => problems.push(format!("{}: {e}", path.display())),
+            }
+        }
+        let config = match serde_json::from_value::<Config>(merged.clone()) {
+            Ok(config) => config,
+            Err(e) => {
+                problems.push(format!("Invalid configuration: {e}"));
+                // Use what can be used
+                let section = |name: &str| merged.get(name).cloned().unwrap_or(Json::Null);
+                Config {
+                    rules: serde_json::from_value(section("rules")).unwrap_or_default(),
+                    timezone: serde_json::from_value(section("timezone")).unwrap_or_default(),
+                    format: serde_json::from_value(section("format")).unwrap_or_default(),
+                    analysis: serde_json::from_value(section("analysis")).unwrap_or_default(),
+                }
+            }
+        };
+        for key in config.rules.keys() {
+            if rule_by_key(key).is_none() {
+                problems.push(format!("Unknown rule in the configuration: {key}"))
+            }
+        }
+        if let Err(e) = config.time_zone() {
+            problems.push(e)
+        }
+        problems.extend(crate::lints::problems(&config.analysis.custom_rules));
+        (config, problems)
+    }
+
+    /// Adds a lint of the application to the ones that contracts are validated with, see [`Lint`](crate::Lint).
+    pub fn with_lint(mut self, lint: impl crate::Lint + 'static) -> Config {
+        self.analysis.extra_lints.push(std::sync::Arc::new(lint));
+        self
+    }
+
+    pub fn time_zone(&self) -> Result<TimeZone, String> {
+        match &self.timezone {
+            None => Ok(TimeZone::default()),
+            Some(name) => TimeZone::parse(name),
+        }
+    }
+
+    fn level(&self, rule: &LintRule) -> Option<RuleLevel> {
+        self.rules.iter().find(|(key, _)| rule_by_key(key).is_some_and(|r| r.id == rule.id)).map(|(_, level)| *level)
+    }
+
+    /// Changes the severity of diagnostics as configured, and removes the ones that are turned off.
+    /// Syntax errors (ML000) are always reported.
+    pub fn apply(&self, diagnostics: &mut Vec<Diagnostic>) {
+        if self.rules.is_empty() {
+            return
+        }
+        diagnostics.retain_mut(|diagnostic| {
+            let rule = match &diagnostic.code {
+                Some(NumberOrString::String(code)) if code != "ML000" => rules::by_id(code),
+                _ => None,
+            };
+            match rule.and_then(|rule| self.level(rule)) {
+                None => true,
+                Some(level) => {
+                    diagnostic.severity = level.severity();
+                    diagnostic.severity.is_some()
+                }
+            }
+        })
+    }
+}
+
+/// Rules can be configured by id (ML020) or by name (undefined-choice).
+fn rule_by_key(key: &str) -> Option<&'static LintRule> {
+    rules::by_id(&key.to_uppercase()).or_else(|| rules::RULES.iter().find(|rule| rule.name == key))
+}
+
+/// Merges `other` into `base`, tables are merged key by key. Nulls (unset editor settings) are skipped.
+fn merge(base: &mut Json, other: Json) {
+    match (base, other) {
+        (Json::Object(base), Json::Object(other)) => {
+            for (key, value) in other.into_iter().filter(|(_, value)| !value.is_null()) {
+                match base.get_mut(&key) {
+                    Some(existing) => merge(existing, value),
+                    None => { base.insert(key, value); }
+                }
+            }
+        }
+        (base, other) => *base = other,
+    }
+}
+
+/// The workspace root of a client, from the workspace folders or the root uri.
+#[cfg(not(target_arch = "wasm32"))]
+pub fn workspace_root(params: &lsp_types::InitializeParams) -> Option<PathBuf> {
+    #[allow(deprecated)]
+    let uri = params.workspace_folders.as_ref().and_then(|folders| folders.first()).map(|folder| &folder.uri).or(params.root_uri.as_ref())?;
+    uri.to_file_path().ok()
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl MyLSPServer {
+
+    /// Asks the editor for its settings, if it supports that.
+    pub async fn pull_editor_settings(&self) {
+        if !self.state.lock().unwrap().pull_configuration {
+            return
+        }
+        let item = ConfigurationItem { scope_uri: None, section: Some(SECTION.to_string()) };
+        match self.client.configuration(vec![item]).await {
+            Ok(mut values) => self.state.lock().unwrap().editor_settings = values.pop(),
+            Err(e) => self.client.log_message(MessageType::WARNING, format!("Could not read the editor settings: {e}")).await
+        }
+    }
+
+    /// Reads the configuration again, problems with it are shown in the editor.
+    pub async fn reload_config(&self) {
+        let problems = {
+            let mut state = self.state.lock().unwrap();
+            let (config, problems) = Config::load(state.initialization_options.as_ref(), state.editor_settings.as_ref(), state.workspace_root.as_deref());
+            state.config = config;
+            problems
+        };
+        for problem in problems {
+            self.client.show_message(MessageType::WARNING, problem).await
+        }
+    }
+
+    /// Validates every open document again and publishes the results.
+    pub async fn revalidate_all(&self) {
+        let mut results = vec![];
+        {
+            let mut state = self.state.lock().unwrap();
+            let documents: Vec<lsp_types::Url> = state.sources.keys().cloned().collect();
+            for document in documents {
+                crate::revalidate(&mut state, &document, &mut results)
+            }
+        }
+        for (uri, diagnostics) in results {
+            self.client.publish_diagnostics(uri, diagnostics, None).await;
+        }
+    }
+}
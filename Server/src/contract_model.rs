@@ -0,0 +1,402 @@
+// Builds typed marlowe_lang contracts from the pest parse tree.
+//
+// marlowe_lang::parsing::deserialization does not (yet) handle every rule
+// of its own grammar (AndObs, OrObs, NotObs, ValueLE, ChoseSomething..),
+// and it throws away all position information, so we do the conversion ourselves.
+// While walking the tree we also record where each contract node and each case
+// lives in the document, keyed by its path from the root contract.
+
+use std::collections::HashMap;
+use lsp_types::{Position, Range};
+use marlowe_lang::parsing::{MarloweParser, Rule};
+use marlowe_lang::types::marlowe::*;
+use pest::Parser;
+use pest::iterators::{Pair, Pairs};
+
+/// The position of a contract node in the tree, as a list of child indexes.
+///
+/// - When: case `i` continues at index `i`, the timeout continuation at `cases.len()`
+/// - If: then = 0, else = 1
+/// - Pay, Let, Assert: continuation = 0
+pub type ContractPath = Vec<usize>;
+
+#[derive(Debug)]
+pub struct ParsedContract {
+    pub contract: Contract,
+    /// Ranges of every contract node (including holes) by path.
+    pub contract_ranges: HashMap<ContractPath, Range>,
+    /// Ranges of every case, keyed by the path of the case continuation.
+    pub case_ranges: HashMap<ContractPath, Range>,
+}
+
+/// marlowe_lang does not accept whitespace before a contract, which is where the comments at the
+/// top of a document end up (see suppression). Returns the rest of the source and where it starts.
+pub fn skip_leading_whitespace(source: &str) -> (&str, Position) {
+    let contract = source.trim_start();
+    let leading = &source[..source.len() - contract.len()];
+    let line = leading.matches('\n').count() as u32;
+    let character = leading.rsplit('\n').next().unwrap_or_default().chars().count() as u32;
+    (contract, Position::new(line, character))
+}
+
+/// Moves a range in a contract from skip_leading_whitespace to where it is in the document.
+/// `first_line` is the number of the first line in the range (ranges of semantic tokens start at 1).
+pub fn shift_range(range: Range, offset: Position, first_line: u32) -> Range {
+    let shift = |p: Position| match p.line == first_line {
+        true => Position::new(p.line + offset.line, p.character + offset.character),
+        false => Position::new(p.line + offset.line, p.character),
+    };
+    Range::new(shift(range.start), shift(range.end))
+}
+
+pub fn parse_contract(source: &str) -> Result<ParsedContract, String> {
+    let (source, offset) = skip_leading_whitespace(source);
+    let mut pairs = MarloweParser::parse(Rule::MainContract, source).map_err(|e| format!("{e:#}"))?;
+    let root = pairs.next().ok_or_else(|| String::from("The document does not contain a contract."))?;
+    let mut builder = Builder { contract_ranges: HashMap::new(), case_ranges: HashMap::new() };
+    let shift = |ranges: HashMap<ContractPath, Range>| ranges.into_iter().map(|(path, range)| (path, shift_range(range, offset, 0))).collect();
+    match builder.contract(root, &vec![])? {
+        Some(contract) => Ok(ParsedContract {
+            contract,
+            contract_ranges: shift(builder.contract_ranges),
+            case_ranges: shift(builder.case_ranges),
+        }),
+        None => Err(String::from("The root contract is a hole.")),
+    }
+}
+
+/// Returns the continuation at the given child index of a contract node.
+pub fn child_at(contract: &Contract, index: usize) -> Option<&Contract> {
+    match contract {
+        Contract::Close => None,
+        Contract::When { when, timeout_continuation, .. } => {
+            if index < when.len() {
+                when[index].as_ref()?.then.as_deref()
+            } else if index == when.len() {
+                timeout_continuation.as_deref()
+            } else {
+                None
+            }
+        }
+        Contract::If { then, r#else, .. } => match index {
+            0 => then.as_deref(),
+            1 => r#else.as_deref(),
+            _ => None,
+        },
+        Contract::Pay { then, .. } |
+        Contract::Let { then, .. } |
+        Contract::Assert { then, .. } => if index == 0 { then.as_deref() } else { None },
+    }
+}
+
+pub fn child_count(contract: &Contract) -> usize {
+    match contract {
+        Contract::Close => 0,
+        Contract::When { when, .. } => when.len() + 1,
+        Contract::If { .. } => 2,
+        Contract::Pay { .. } | Contract::Let { .. } | Contract::Assert { .. } => 1,
+    }
+}
+
+/// Visits every contract node (depth first, in document order) together with its path.
+pub fn walk_contracts<'a>(root: &'a Contract, f: &mut dyn FnMut(&ContractPath, &'a Contract)) {
+    fn walk<'a>(node: &'a Contract, path: &mut ContractPath, f: &mut dyn FnMut(&ContractPath, &'a Contract)) {
+        f(path, node);
+        for index in 0..child_count(node) {
+            if let Some(child) = child_at(node, index) {
+                path.push(index);
+                walk(child, path, f);
+                path.pop();
+            }
+        }
+    }
+    walk(root, &mut vec![], f)
+}
+
+/// Follows a path from the root contract. Returns None if the path leads into a hole.
+pub fn node_at<'a>(root: &'a Contract, path: &[usize]) -> Option<&'a Contract> {
+    let mut current = root;
+    for index in path {
+        current = child_at(current, *index)?;
+    }
+    Some(current)
+}
+
+struct Builder {
+    contract_ranges: HashMap<ContractPath, Range>,
+    case_ranges: HashMap<ContractPath, Range>,
+}
+
+fn next<'a>(pairs: &mut Pairs<'a, Rule>, parent: &str) -> Result<Pair<'a, Rule>, String> {
+    pairs.next().ok_or_else(|| format!("Unexpected end of '{parent}' node."))
+}
+
+fn unexpected<T>(pair: &Pair<Rule>, expected: &str) -> Result<T, String> {
+    Err(format!("Expected {expected}, found {:?}: '{}'", pair.as_rule(), pair.as_str()))
+}
+
+impl Builder {
+
+    fn contract(&mut self, pair: Pair<Rule>, path: &ContractPath) -> Result<Option<Contract>, String> {
+
+        if pair.as_rule() == Rule::Contract {
+            // The top level contract node only wraps the actual contract
+            let mut inner = pair.into_inner();
+            return self.contract(next(&mut inner, "Contract")?, path)
+        }
+
+        self.contract_ranges.insert(path.clone(), crate::get_range(pair.clone()));
+
+        let rule = pair.as_rule();
+        let mut inner = pair.clone().into_inner();
+        let child = |i: usize| { let mut p = path.clone(); p.push(i); p };
+
+        let contract = match rule {
+            Rule::ContractHole => return Ok(None),
+            Rule::Close => Contract::Close,
+            Rule::When => {
+                let case_list = next(&mut inner, "When")?;
+                let timeout_pair = next(&mut inner, "When")?;
+                let continuation = next(&mut inner, "When")?;
+                let mut when = vec![];
+                for (i, case) in case_list.into_inner().enumerate() {
+                    let case_path = child(i);
+                    match case.as_rule() {
+                        Rule::CaseHole => when.push(None),
+                        Rule::Case => {
+                            self.case_ranges.insert(case_path.clone(), crate::get_range(case.clone()));
+                            let mut case_inner = case.into_inner();
+                            let action = action(next(&mut case_inner, "Case")?)?;
+                            let then = self.contract(next(&mut case_inner, "Case")?, &case_path)?;
+                            when.push(Some(Case { case: action, then: then.map(Box::new) }))
+                        }
+                        _ => return unexpected(&case, "a Case")
+                    }
+                }
+                let timeout_continuation = self.contract(continuation, &child(when.len()))?;
+                Contract::When {
+                    when,
+                    timeout: timeout(timeout_pair)?,
+                    timeout_continuation: timeout_continuation.map(Box::new)
+                }
+            }
+            Rule::If => {
+                let observation = observation(next(&mut inner, "If")?)?;
+                let then = self.contract(next(&mut inner, "If")?, &child(0))?;
+                let r#else = self.contract(next(&mut inner, "If")?, &child(1))?;
+                Contract::If { r#if: observation, then: then.map(Box::new), r#else: r#else.map(Box::new) }
+            }
+            Rule::Let => {
+                let name = next(&mut inner, "Let")?.as_str().to_string();
+                let be = value(next(&mut inner, "Let")?)?;
+                let then = self.contract(next(&mut inner, "Let")?, &child(0))?;
+                Contract::Let { r#let: name, be: be.map(Box::new), then: then.map(Box::new) }
+            }
+            Rule::Assert => {
+                let assert = observation(next(&mut inner, "Assert")?)?;
+                let then = self.contract(next(&mut inner, "Assert")?, &child(0))?;
+                Contract::Assert { assert, then: then.map(Box::new) }
+            }
+            Rule::Pay => {
+                let from_account = party(next(&mut inner, "Pay")?)?;
+                let to = payee(next(&mut inner, "Pay")?)?;
+                let token = token(next(&mut inner, "Pay")?)?;
+                let pay = value(next(&mut inner, "Pay")?)?;
+                let then = self.contract(next(&mut inner, "Pay")?, &child(0))?;
+                Contract::Pay { from_account, to, token, pay, then: then.map(Box::new) }
+            }
+            _ => return unexpected(&pair, "a Contract")
+        };
+        Ok(Some(contract))
+    }
+}
+
+fn number(pair: Pair<Rule>) -> Result<i64, String> {
+    pair.as_str().trim().parse::<i64>().map_err(|e| format!("'{}' is not a valid number: {e:?}", pair.as_str()))
+}
+
+fn timeout(pair: Pair<Rule>) -> Result<Option<Timeout>, String> {
+    match pair.as_rule() {
+        Rule::TimeoutHole => Ok(None),
+        Rule::TimeConstant | Rule::Number => Ok(Some(Timeout::TimeConstant(number(pair)?))),
+        Rule::TimeParam => Ok(Some(Timeout::TimeParam(next(&mut pair.into_inner(), "TimeParam")?.as_str().to_string()))),
+        _ => unexpected(&pair, "a Timeout")
+    }
+}
+
+fn party(pair: Pair<Rule>) -> Result<Option<Party>, String> {
+    match pair.as_rule() {
+        Rule::PartyHole | Rule::FromPartyHole | Rule::RoleHole | Rule::PubkeyHole => Ok(None),
+        Rule::Role => Ok(Some(Party::Role { role_token: next(&mut pair.into_inner(), "Role")?.as_str().to_string() })),
+        Rule::PK => {
+            let key = next(&mut pair.into_inner(), "PK")?;
+            Ok(Some(Party::PK { pk_hash: key.as_str().trim_matches('"').to_string() }))
+        }
+        _ => unexpected(&pair, "a Party")
+    }
+}
+
+fn payee(pair: Pair<Rule>) -> Result<Option<Payee>, String> {
+    match pair.as_rule() {
+        Rule::PayeeHole => Ok(None),
+        Rule::PayeeAccount => Ok(Some(Payee::Account(party(next(&mut pair.into_inner(), "Account")?)?))),
+        Rule::PayeeParty => Ok(Some(Payee::Party(party(next(&mut pair.into_inner(), "Party")?)?))),
+        _ => unexpected(&pair, "a Payee")
+    }
+}
+
+fn token(pair: Pair<Rule>) -> Result<Option<Token>, String> {
+    match pair.as_rule() {
+        Rule::TokenHole => Ok(None),
+        Rule::ADA => Ok(Some(Token::ADA)),
+        Rule::Currency => {
+            let mut inner = pair.into_inner();
+            let currency_symbol = next(&mut inner, "Token")?.as_str().to_string();
+            let token_name = next(&mut inner, "Token")?.as_str().to_string();
+            // (Token "" "") is parsed as a currency by the marlowe grammar, but it is just ada.
+            if currency_symbol.is_empty() && token_name.is_empty() {
+                Ok(Some(Token::ADA))
+            } else {
+                Ok(Some(Token::Custom { currency_symbol, token_name }))
+            }
+        }
+        _ => unexpected(&pair, "a Token")
+    }
+}
+
+fn choice_id(pair: Pair<Rule>) -> Result<ChoiceId, String> {
+    if pair.as_rule() != Rule::ChoiceId {
+        return unexpected(&pair, "a ChoiceId")
+    }
+    let mut inner = pair.into_inner();
+    let choice_name = next(&mut inner, "ChoiceId")?.as_str().to_string();
+    let choice_owner = party(next(&mut inner, "ChoiceId")?)?;
+    Ok(ChoiceId { choice_name, choice_owner })
+}
+
+fn bound(pair: Pair<Rule>) -> Result<Option<Bound>, String> {
+    match pair.as_rule() {
+        Rule::BoundHole => Ok(None),
+        Rule::Bound => {
+            let mut inner = pair.into_inner();
+            let from = number(next(&mut inner, "Bound")?)?;
+            let to = number(next(&mut inner, "Bound")?)?;
+            Ok(Some(Bound(from, to)))
+        }
+        _ => unexpected(&pair, "a Bound")
+    }
+}
+
+fn action(pair: Pair<Rule>) -> Result<Option<Action>, String> {
+    let rule = pair.as_rule();
+    let mut inner = pair.clone().into_inner();
+    match rule {
+        Rule::ActionHole => Ok(None),
+        Rule::Deposit => {
+            let into_account = party(next(&mut inner, "Deposit")?)?;
+            let from = party(next(&mut inner, "Deposit")?)?;
+            let of_token = token(next(&mut inner, "Deposit")?)?;
+            let deposits = value(next(&mut inner, "Deposit")?)?;
+            Ok(Some(Action::Deposit { party: from, of_token, into_account, deposits }))
+        }
+        Rule::Choice => {
+            let for_choice = choice_id(next(&mut inner, "Choice")?)?;
+            let mut choose_between = vec![];
+            for b in next(&mut inner, "Choice")?.into_inner() {
+                choose_between.push(bound(b)?)
+            }
+            Ok(Some(Action::Choice { for_choice: Some(for_choice), choose_between }))
+        }
+        Rule::Notify => Ok(Some(Action::Notify { notify_if: observation(next(&mut inner, "Notify")?)? })),
+        _ => unexpected(&pair, "an Action")
+    }
+}
+
+fn boxed_value(pair: Pair<Rule>) -> Result<Option<Box<Value>>, String> {
+    Ok(value(pair)?.map(Box::new))
+}
+
+fn boxed_observation(pair: Pair<Rule>) -> Result<Option<Box<Observation>>, String> {
+    Ok(observation(pair)?.map(Box::new))
+}
+
+fn value(pair: Pair<Rule>) -> Result<Option<Value>, String> {
+    let rule = pair.as_rule();
+    let mut inner = pair.clone().into_inner();
+    let name = format!("{rule:?}");
+    let v = match rule {
+        Rule::ValueHole => return Ok(None),
+        Rule::TimeIntervalStart => Value::TimeIntervalStart,
+        Rule::TimeIntervalEnd => Value::TimeIntervalEnd,
+        Rule::Constant => Value::ConstantValue(number(next(&mut inner, &name)?)?),
+        Rule::ConstantParam => Value::ConstantParam(next(&mut inner, &name)?.as_str().to_string()),
+        Rule::UseValue => Value::UseValue(next(&mut inner, &name)?.as_str().to_string()),
+        Rule::AvailableMoney => {
+            let owner = party(next(&mut inner, &name)?)?;
+            Value::AvailableMoney(owner, token(next(&mut inner, &name)?)?)
+        }
+        Rule::ChoiceValue => Value::ChoiceValue(Some(choice_id(next(&mut inner, &name)?)?)),
+        Rule::NegValue => Value::NegValue(boxed_value(next(&mut inner, &name)?)?),
+        Rule::AddValue | Rule::SubValue | Rule::MulValue | Rule::DivValue => {
+            let a = boxed_value(next(&mut inner, &name)?)?;
+            let b = boxed_value(next(&mut inner, &name)?)?;
+            match rule {
+                Rule::AddValue => Value::AddValue(a, b),
+                Rule::SubValue => Value::SubValue(a, b),
+                Rule::MulValue => Value::MulValue(a, b),
+                _ => Value::DivValue(a, b),
+            }
+        }
+        Rule::Cond => {
+            let obs = observation(next(&mut inner, &name)?)?;
+            let a = boxed_value(next(&mut inner, &name)?)?;
+            let b = boxed_value(next(&mut inner, &name)?)?;
+            Value::Cond(obs, a, b)
+        }
+        _ => return unexpected(&pair, "a Value")
+    };
+    Ok(Some(v))
+}
+
+fn observation(pair: Pair<Rule>) -> Result<Option<Observation>, String> {
+    let rule = pair.as_rule();
+    let mut inner = pair.clone().into_inner();
+    let name = format!("{rule:?}");
+    let o = match rule {
+        Rule::ObservationHole => return Ok(None),
+        Rule::TrueObs => Observation::True,
+        Rule::FalseObs => Observation::False,
+        Rule::ValueGT | Rule::ValueGE | Rule::ValueLT | Rule::ValueLE | Rule::ValueEQ => {
+            let a = boxed_value(next(&mut inner, &name)?)?;
+            let b = boxed_value(next(&mut inner, &name)?)?;
+            match rule {
+                Rule::ValueGT => Observation::ValueGT { value: a, gt_than: b },
+                Rule::ValueGE => Observation::ValueGE { value: a, ge_than: b },
+                Rule::ValueLT => Observation::ValueLT { value: a, lt_than: b },
+                Rule::ValueLE => Observation::ValueLE { value: a, le_than: b },
+                _ => Observation::ValueEQ { value: a, equal_to: b },
+            }
+        }
+        Rule::AndObs => {
+            let both = boxed_observation(next(&mut inner, &name)?)?;
+            Observation::AndObs { both, and: boxed_observation(next(&mut inner, &name)?)? }
+        }
+        Rule::OrObs => {
+            let either = boxed_observation(next(&mut inner, &name)?)?;
+            Observation::OrObs { either, or: boxed_observation(next(&mut inner, &name)?)? }
+        }
+        Rule::NotObs => Observation::NotObs { not: boxed_observation(next(&mut inner, &name)?)? },
+        Rule::ChoseSomething => {
+            // ChoseSomething is an atomic rule so pest does not give us its children,
+            // we have to parse the inner ChoiceId ourselves.
+            let text = pair.as_str();
+            let start = text.find("ChoseSomething").map(|i| i + "ChoseSomething".len()).unwrap_or(0);
+            let inner_text = text[start..].trim();
+            let inner_text = inner_text.strip_suffix(')').unwrap_or(inner_text).trim();
+            let mut parsed = MarloweParser::parse(Rule::ChoiceId, inner_text).map_err(|e| format!("{e:#}"))?;
+            Observation::ChoseSomething(Some(choice_id(next(&mut parsed, "ChoseSomething")?)?))
+        }
+        _ => return unexpected(&pair, "an Observation")
+    };
+    Ok(Some(o))
+}
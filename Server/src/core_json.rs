@@ -0,0 +1,390 @@
+// Conversion between the Marlowe DSL and the Marlowe "core" JSON format
+// used by the Marlowe runtime and most off-chain tooling.
+//
+// The field names follow the ToJSON/FromJSON instances in
+// Language.Marlowe.Core.V1.Semantics.Types, and TimeParam/ConstantParam use the
+// extended format from Language.Marlowe.Extended.V1 ({"time_param": ".."} and
+// {"constant_param": ".."}) so that templates survive the round trip.
+//
+// marlowe_lang has its own json serializer, but it stops at the first hole,
+// it cannot serialize parameters and it writes bounds the wrong way around,
+// so we do the conversion ourselves on top of the contract model.
+// JSON has no way of representing holes, so every hole in the contract is
+// reported as an error.
+//
+// Reading JSON goes through json_document, which is also what the editor uses
+// for .marlowe.json documents.
+
+use serde_json::{json, Value as Json};
+use marlowe_lang::types::marlowe::*;
+use crate::contract_model::{ContractPath, ParsedContract, parse_contract};
+use crate::json_document::JsonDocument;
+
+/// A hole that prevents the contract from being serialized.
+#[derive(Clone, Debug)]
+pub struct Hole {
+    /// Path of the contract node that contains the hole.
+    pub path: ContractPath,
+    pub message: String,
+}
+
+struct Serializer {
+    path: ContractPath,
+    holes: Vec<Hole>,
+    /// When merkleizing: the continuations that were replaced by their hash, in Marlowe JSON.
+    continuations: Option<Continuations>,
+}
+
+impl Serializer {
+
+    fn hole(&mut self, kind: &str) -> Json {
+        self.holes.push(Hole { path: self.path.clone(), message: format!("Found a hole of type '{kind}'. Holes can not be represented in Marlowe JSON.") });
+        Json::Null
+    }
+
+    fn opt<T>(&mut self, item: &Option<T>, kind: &str, f: impl FnOnce(&mut Self, &T) -> Json) -> Json {
+        match item {
+            Some(item) => f(self, item),
+            None => self.hole(kind),
+        }
+    }
+
+    fn child(&mut self, index: usize, contract: &Option<Box<Contract>>) -> Json {
+        self.path.push(index);
+        let result = self.opt(contract, "Contract", |s, c| s.contract(c));
+        self.path.pop();
+        result
+    }
+
+    fn contract(&mut self, contract: &Contract) -> Json {
+        match contract {
+            Contract::Close => json!("close"),
+            Contract::When { when, timeout, timeout_continuation } => {
+                let cases: Vec<Json> = when.iter().enumerate().map(|(i, case)| match case {
+                    Some(Case { case, then: Some(then) }) if self.continuations.is_some() => {
+                        let hash = crate::merkle::continuation_hash(then);
+                        let action = self.opt(case, "Action", |s, a| s.action(a));
+                        self.path.push(i);
+                        let continuation = self.contract(then);
+                        self.path.pop();
+                        let continuations = self.continuations.get_or_insert_with(Vec::new);
+                        if !continuations.iter().any(|(h, _)| *h == hash) {
+                            continuations.push((hash.clone(), continuation));
+                        }
+                        json!({ "case": action, "merkleized_then": hash })
+                    }
+                    Some(case) => json!({
+                        "case": self.opt(&case.case, "Action", |s, a| s.action(a)),
+                        "then": self.child(i, &case.then),
+                    }),
+                    None => self.hole("Case"),
+                }).collect();
+                json!({
+                    "when": cases,
+                    "timeout": self.opt(timeout, "Timeout", |_, t| timeout_json(t)),
+                    "timeout_continuation": self.child(when.len(), timeout_continuation),
+                })
+            }
+            Contract::If { r#if, then, r#else } => json!({
+                "if": self.opt(r#if, "Observation", |s, o| s.observation(o)),
+                "then": self.child(0, then),
+                "else": self.child(1, r#else),
+            }),
+            Contract::Let { r#let, be, then } => json!({
+                "let": r#let,
+                "be": self.opt(be, "Value", |s, v| s.value(v)),
+                "then": self.child(0, then),
+            }),
+            Contract::Assert { assert, then } => json!({
+                "assert": self.opt(assert, "Observation", |s, o| s.observation(o)),
+                "then": self.child(0, then),
+            }),
+            Contract::Pay { from_account, to, token, pay, then } => json!({
+                "from_account": self.opt(from_account, "Party", |_, p| party_json(p)),
+                "to": self.opt(to, "Payee", |s, p| s.payee(p)),
+                "token": self.opt(token, "Token", |_, t| token_json(t)),
+                "pay": self.opt(pay, "Value", |s, v| s.value(v)),
+                "then": self.child(0, then),
+            }),
+        }
+    }
+
+    fn party(&mut self, party: &Option<Party>) -> Json {
+        self.opt(party, "Party", |_, p| party_json(p))
+    }
+
+    fn payee(&mut self, payee: &Payee) -> Json {
+        match payee {
+            Payee::Account(p) => json!({ "account": self.party(p) }),
+            Payee::Party(p) => json!({ "party": self.party(p) }),
+        }
+    }
+
+    fn choice_id(&mut self, choice_id: &ChoiceId) -> Json {
+        json!({
+            "choice_name": choice_id.choice_name,
+            "choice_owner": self.party(&choice_id.choice_owner),
+        })
+    }
+
+    fn action(&mut self, action: &Action) -> Json {
+        match action {
+            Action::Deposit { party, of_token, into_account, deposits } => json!({
+                "into_account": self.party(into_account),
+                "party": self.party(party),
+                "of_token": self.opt(of_token, "Token", |_, t| token_json(t)),
+                "deposits": self.opt(deposits, "Value", |s, v| s.value(v)),
+            }),
+            Action::Choice { for_choice, choose_between } => {
+                let bounds: Vec<Json> = choose_between.iter()
+                    .map(|b| self.opt(b, "Bound", |_, Bound(from, to)| json!({ "from": from, "to": to })))
+                    .collect();
+                json!({
+                    "for_choice": self.opt(for_choice, "ChoiceId", |s, c| s.choice_id(c)),
+                    "choose_between": bounds,
+                })
+            }
+            Action::Notify { notify_if } => json!({
+                "notify_if": self.opt(notify_if, "Observation", |s, o| s.observation(o)),
+            }),
+        }
+    }
+
+    fn boxed_value(&mut self, value: &Option<Box<Value>>) -> Json {
+        self.opt(value, "Value", |s, v| s.value(v))
+    }
+
+    fn boxed_observation(&mut self, observation: &Option<Box<Observation>>) -> Json {
+        self.opt(observation, "Observation", |s, o| s.observation(o))
+    }
+
+    fn value(&mut self, value: &Value) -> Json {
+        match value {
+            Value::TimeIntervalStart => json!("time_interval_start"),
+            Value::TimeIntervalEnd => json!("time_interval_end"),
+            Value::AvailableMoney(party, token) => json!({
+                "amount_of_token": self.opt(token, "Token", |_, t| token_json(t)),
+                "in_account": self.party(party),
+            }),
+            Value::ConstantValue(n) => json!(n),
+            Value::ConstantParam(name) => json!({ "constant_param": name }),
+            Value::UseValue(name) => json!({ "use_value": name }),
+            Value::NegValue(a) => json!({ "negate": self.boxed_value(a) }),
+            Value::AddValue(a, b) => json!({ "add": self.boxed_value(a), "and": self.boxed_value(b) }),
+            Value::SubValue(a, b) => json!({ "value": self.boxed_value(a), "minus": self.boxed_value(b) }),
+            Value::MulValue(a, b) => json!({ "multiply": self.boxed_value(a), "times": self.boxed_value(b) }),
+            Value::DivValue(a, b) => json!({ "divide": self.boxed_value(a), "by": self.boxed_value(b) }),
+            Value::ChoiceValue(c) => json!({ "value_of_choice": self.opt(c, "ChoiceId", |s, c| s.choice_id(c)) }),
+            Value::Cond(o, a, b) => json!({
+                "if": self.opt(o, "Observation", |s, o| s.observation(o)),
+                "then": self.boxed_value(a),
+                "else": self.boxed_value(b),
+            }),
+        }
+    }
+
+    fn observation(&mut self, observation: &Observation) -> Json {
+        match observation {
+            Observation::True => json!(true),
+            Observation::False => json!(false),
+            Observation::AndObs { both, and } => json!({ "both": self.boxed_observation(both), "and": self.boxed_observation(and) }),
+            Observation::OrObs { either, or } => json!({ "either": self.boxed_observation(either), "or": self.boxed_observation(or) }),
+            Observation::NotObs { not } => json!({ "not": self.boxed_observation(not) }),
+            Observation::ChoseSomething(c) => json!({ "chose_something_for": self.opt(c, "ChoiceId", |s, c| s.choice_id(c)) }),
+            Observation::ValueGE { value, ge_than } => json!({ "value": self.boxed_value(value), "ge_than": self.boxed_value(ge_than) }),
+            Observation::ValueGT { value, gt_than } => json!({ "value": self.boxed_value(value), "gt_than": self.boxed_value(gt_than) }),
+            Observation::ValueLT { value, lt_than } => json!({ "value": self.boxed_value(value), "lt_than": self.boxed_value(lt_than) }),
+            Observation::ValueLE { value, le_than } => json!({ "value": self.boxed_value(value), "le_than": self.boxed_value(le_than) }),
+            Observation::ValueEQ { value, equal_to } => json!({ "value": self.boxed_value(value), "equal_to": self.boxed_value(equal_to) }),
+        }
+    }
+}
+
+fn timeout_json(timeout: &Timeout) -> Json {
+    match timeout {
+        Timeout::TimeConstant(n) => json!(n),
+        Timeout::TimeParam(name) => json!({ "time_param": name }),
+    }
+}
+
+fn party_json(party: &Party) -> Json {
+    match party {
+        Party::Role { role_token } => json!({ "role_token": role_token }),
+        Party::PK { pk_hash } => json!({ "pk_hash": pk_hash }),
+    }
+}
+
+fn token_json(token: &Token) -> Json {
+    match token {
+        Token::ADA => json!({ "currency_symbol": "", "token_name": "" }),
+        Token::Custom { currency_symbol, token_name } => json!({ "currency_symbol": currency_symbol, "token_name": token_name }),
+    }
+}
+
+/// Serializes a contract to Marlowe JSON. Fails with every hole in the contract if there are any.
+pub fn contract_to_json(contract: &Contract) -> Result<Json, Vec<Hole>> {
+    let mut serializer = Serializer { path: vec![], holes: vec![], continuations: None };
+    let json = serializer.contract(contract);
+    if serializer.holes.is_empty() { Ok(json) } else { Err(serializer.holes) }
+}
+
+/// Merkleized continuations in Marlowe JSON, by hash.
+pub type Continuations = Vec<(String, Json)>;
+
+/// Serializes a contract to Marlowe JSON with every case continuation replaced by its hash.
+/// Returns the merkleized contract and the continuations by hash.
+pub fn contract_to_merkleized_json(contract: &Contract) -> Result<(Json, Continuations), Vec<Hole>> {
+    let mut serializer = Serializer { path: vec![], holes: vec![], continuations: Some(vec![]) };
+    let json = serializer.contract(contract);
+    if serializer.holes.is_empty() { Ok((json, serializer.continuations.unwrap_or_default())) } else { Err(serializer.holes) }
+}
+
+/// Describes holes with the line of the contract node they belong to.
+pub fn describe_holes(holes: &[Hole], parsed: &ParsedContract) -> String {
+    holes.iter().map(|hole| match parsed.contract_ranges.get(&hole.path) {
+        Some(range) => format!("Line {}: {}", range.start.line + 1, hole.message),
+        None => hole.message.clone(),
+    }).collect::<Vec<String>>().join("\n")
+}
+
+/// Converts a DSL document to pretty printed Marlowe JSON.
+pub fn dsl_to_json(source: &str) -> Result<String, String> {
+    let parsed = parse_contract(source)?;
+    match contract_to_json(&parsed.contract) {
+        Ok(json) => serde_json::to_string_pretty(&json).map_err(|e| e.to_string()),
+        Err(holes) => Err(describe_holes(&holes, &parsed)),
+    }
+}
+
+/// Reads a contract from a Marlowe JSON document.
+pub fn json_to_contract(source: &str) -> Result<Contract, String> {
+    let document = JsonDocument::parse(source)
+        .map_err(|(message, range)| format!("Line {}: {message}", range.start.line + 1))?;
+    Ok(parse_contract(&document.dsl)?.contract)
+}
+
+/// Converts a Marlowe JSON document to the DSL.
+pub fn json_to_dsl(source: &str) -> Result<String, String> {
+    Ok(format!("{:#}", json_to_contract(source)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PK: &str = "(PK \"0000000000000000000000000000000000000000000000000000000000000000\")";
+
+    // DSL to JSON and back gives the same contract, and the same JSON again
+    fn round_trip(dsl: &str) {
+        let json = dsl_to_json(dsl).unwrap_or_else(|e| panic!("{dsl}\n{e}"));
+        let back = json_to_dsl(&json).unwrap_or_else(|e| panic!("{json}\n{e}"));
+        assert_eq!(back, format!("{:#}", parse_contract(dsl).unwrap().contract), "{json}");
+        assert_eq!(dsl_to_json(&back).unwrap(), json);
+    }
+
+    fn with_value(value: &str) -> String {
+        format!("Let \"x\" {value} Close")
+    }
+
+    fn with_observation(observation: &str) -> String {
+        format!("Assert {observation} Close")
+    }
+
+    fn holes(dsl: &str) -> Vec<(ContractPath, String)> {
+        match contract_to_json(&parse_contract(dsl).unwrap().contract) {
+            Ok(json) => panic!("{dsl} has no holes: {json}"),
+            Err(holes) => holes.into_iter().map(|hole| (hole.path, hole.message)).collect(),
+        }
+    }
+
+    fn assert_hole(dsl: &str, path: &[usize], kind: &str) {
+        let holes = holes(dsl);
+        assert_eq!(holes.len(), 1, "{holes:?}");
+        assert_eq!(holes[0].0, path, "{dsl}");
+        assert!(holes[0].1.contains(&format!("'{kind}'")), "{}", holes[0].1);
+    }
+
+    #[test]
+    fn contracts_round_trip() {
+        round_trip("Close");
+        round_trip("When [] 100 Close");
+        round_trip("When [] (TimeParam \"deadline\") Close");
+        round_trip("If TrueObs Close (Let \"x\" (Constant 1) Close)");
+        round_trip("Let \"x\" (Constant 1) (Assert (ValueGT (UseValue \"x\") (Constant 0)) Close)");
+        round_trip("Assert FalseObs Close");
+        round_trip("Pay (Role \"a\") (Party (Role \"b\")) (Token \"\" \"\") (Constant 1) Close");
+        round_trip(&format!("Pay {PK} (Account (Role \"b\")) (Token \"abc\" \"coin\") (Constant 1) Close"));
+    }
+
+    #[test]
+    fn actions_round_trip() {
+        round_trip(&format!("When [Case (Deposit (Role \"a\") {PK} (Token \"\" \"\") (Constant 10)) Close] 100 Close"));
+        round_trip("When [Case (Choice (ChoiceId \"price\" (Role \"oracle\")) [Bound 0 10, Bound 20 30]) Close] 100 Close");
+        round_trip("When [Case (Notify TrueObs) Close, Case (Notify FalseObs) Close] 100 Close");
+    }
+
+    #[test]
+    fn values_round_trip() {
+        for value in [
+            "(Constant -5)",
+            "(ConstantParam \"amount\")",
+            "(AvailableMoney (Role \"a\") (Token \"\" \"\"))",
+            "(Cond TrueObs (Constant 1) (Constant 2))",
+            "(ChoiceValue (ChoiceId \"price\" (Role \"oracle\")))",
+            "(MulValue (Constant 2) (Constant 3))",
+            "(DivValue (Constant 6) (Constant 3))",
+            "(SubValue (Constant 3) (Constant 2))",
+            "(AddValue (Constant 1) (Constant 2))",
+            "(NegValue (Constant 1))",
+            "(UseValue \"y\")",
+            "TimeIntervalStart",
+            "TimeIntervalEnd",
+        ] {
+            round_trip(&with_value(value));
+        }
+    }
+
+    #[test]
+    fn observations_round_trip() {
+        for observation in [
+            "TrueObs",
+            "FalseObs",
+            "(ValueEQ (Constant 1) (Constant 1))",
+            "(ValueLE (Constant 1) (Constant 2))",
+            "(ValueLT (Constant 1) (Constant 2))",
+            "(ValueGT (Constant 2) (Constant 1))",
+            "(ValueGE (Constant 2) (Constant 1))",
+            "(OrObs TrueObs FalseObs)",
+            "(NotObs TrueObs)",
+            "(AndObs TrueObs FalseObs)",
+            "(ChoseSomething (ChoiceId \"price\" (Role \"oracle\")))",
+        ] {
+            round_trip(&with_observation(observation));
+        }
+    }
+
+    #[test]
+    fn holes_are_errors_with_their_path() {
+        assert_hole("If TrueObs Close ?contract", &[1], "Contract");
+        assert_hole("When [Case (Notify TrueObs) (Let \"x\" (Constant 1) ?then)] 10 Close", &[0, 0], "Contract");
+        assert_hole("When [] 10 ?timeout_continuation", &[0], "Contract");
+        assert_hole(&with_value("?value"), &[], "Value");
+        assert_hole("If TrueObs (Pay ?party (Party (Role \"b\")) (Token \"\" \"\") (Constant 1) Close) Close", &[0], "Party");
+        assert_hole("Pay (Role \"a\") ?payee (Token \"\" \"\") (Constant 1) Close", &[], "Payee");
+        assert_hole("Pay (Role \"a\") (Party (Role \"b\")) ?token (Constant 1) Close", &[], "Token");
+        assert_hole(&with_observation("?observation"), &[], "Observation");
+        assert_hole("When [] ?timeout Close", &[], "Timeout");
+        assert_hole("When [Case ?action Close] 10 Close", &[], "Action");
+        assert_hole("When [?case] 10 Close", &[], "Case");
+        assert_hole("When [Case (Choice (ChoiceId \"c\" (Role \"a\")) [?bound]) Close] 10 Close", &[], "Bound");
+    }
+
+    #[test]
+    fn every_hole_is_reported_with_its_line() {
+        let dsl = "When\n    [Case (Deposit ?party (Role \"b\") (Token \"\" \"\") ?amount) Close]\n    10\n    (If TrueObs ?then Close)";
+        assert_eq!(holes(dsl).len(), 3);
+        let message = dsl_to_json(dsl).unwrap_err();
+        assert_eq!(message.lines().count(), 3, "{message}");
+        assert!(message.lines().last().unwrap().starts_with("Line 4: Found a hole of type 'Contract'"), "{message}");
+    }
+}
@@ -0,0 +1,372 @@
+// The Marlowe datum, as stored on chain by the Marlowe validator:
+//
+//   MarloweData { marloweParams = MarloweParams rolesCurrency, marloweState = State, marloweContract = Contract }
+//
+// encoded as Plutus Data CBOR (see plutus_data). Contracts can be serialized on
+// their own, or together with a State to get the full datum. The State is given
+// in Marlowe JSON, the same format the Marlowe runtime uses:
+//
+//   {
+//       "accounts": [ [ [ { "role_token": "Buyer" }, { "currency_symbol": "", "token_name": "" } ], 1000000 ] ],
+//       "choices": [ [ { "choice_name": "Price", "choice_owner": { "role_token": "Seller" } }, 5 ] ],
+//       "boundValues": [ [ "x", 3 ] ],
+//       "minTime": 1700000000000
+//   }
+//
+// Decoding accepts either a contract or a full datum, and gives the contract
+// back as DSL text together with the State in the same JSON format.
+// MerkleizedCase has no DSL representation, so merkleized contracts can not be decoded.
+
+use serde::Deserialize;
+use serde_json::{json, Value as Json};
+use marlowe_lang::types::marlowe::*;
+use crate::plutus_data::{DataEncoder, PlutusData, hex_bytes, to_hex};
+
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct DatumOptions {
+    /// An initial State in Marlowe JSON. Without it only the contract is serialized.
+    pub state: Option<Json>,
+    /// Currency symbol of the role tokens, as hex. Only used together with a State.
+    pub roles_currency: Option<String>,
+}
+
+/// A datum or contract decoded from CBOR.
+#[derive(Debug)]
+pub struct DecodedDatum {
+    pub contract: Contract,
+    pub state: Option<Json>,
+    pub roles_currency: Option<String>,
+}
+
+fn constr(index: u64, fields: Vec<PlutusData>) -> PlutusData {
+    PlutusData::Constr(index, fields)
+}
+
+fn text(s: &str) -> PlutusData {
+    PlutusData::Bytes(s.as_bytes().to_vec())
+}
+
+/// Serializes a contract, and the full datum if there is a State, to hex CBOR.
+/// Fails if the contract has holes or parameters without a value.
+pub fn encode(contract: &Contract, encoder: &mut DataEncoder, options: &DatumOptions) -> Result<String, String> {
+    let contract = encoder.contract(contract);
+    if !encoder.missing.is_empty() {
+        let missing: Vec<&str> = encoder.missing.iter().map(String::as_str).collect();
+        return Err(format!("The contract can not be serialized, it has {}.", missing.join(", ")))
+    }
+    let data = match &options.state {
+        None => contract,
+        Some(state) => {
+            let params = constr(0, vec![PlutusData::Bytes(hex_bytes(options.roles_currency.as_deref().unwrap_or("")))]);
+            constr(0, vec![params, state_data(state)?, contract])
+        }
+    };
+    Ok(data.to_hex())
+}
+
+fn entries<'a>(state: &'a Json, field: &str) -> Result<Vec<(&'a Json, &'a Json)>, String> {
+    let items = match state.get(field) {
+        None | Some(Json::Null) => return Ok(vec![]),
+        Some(Json::Array(items)) => items,
+        Some(other) => return Err(format!("Expected a list of pairs in '{field}', found: {other}")),
+    };
+    items.iter().map(|item| match item.as_array().map(Vec::as_slice) {
+        Some([key, value]) => Ok((key, value)),
+        _ => Err(format!("Expected a pair in '{field}', found: {item}")),
+    }).collect()
+}
+
+fn integer(json: &Json) -> Result<PlutusData, String> {
+    json.as_i64().map(|n| PlutusData::Integer(n as i128)).ok_or_else(|| format!("Expected an integer, found: {json}"))
+}
+
+fn string<'a>(json: &'a Json, field: &str) -> Result<&'a str, String> {
+    json.get(field).and_then(Json::as_str).ok_or_else(|| format!("Expected a string in '{field}', found: {json}"))
+}
+
+fn party_data(json: &Json) -> Result<PlutusData, String> {
+    if let Some(role) = json.get("role_token").and_then(Json::as_str) {
+        Ok(constr(1, vec![text(role)]))
+    } else if let Some(pk_hash) = json.get("pk_hash").and_then(Json::as_str) {
+        Ok(constr(0, vec![PlutusData::Bytes(hex_bytes(pk_hash))]))
+    } else {
+        Err(format!("Expected a party (role_token or pk_hash), found: {json}"))
+    }
+}
+
+fn token_data(json: &Json) -> Result<PlutusData, String> {
+    let currency_symbol = string(json, "currency_symbol")?;
+    Ok(constr(0, vec![PlutusData::Bytes(hex_bytes(currency_symbol)), text(string(json, "token_name")?)]))
+}
+
+/// Encodes a State in Marlowe JSON.
+pub fn state_data(state: &Json) -> Result<PlutusData, String> {
+    let accounts = entries(state, "accounts")?.into_iter().map(|(key, amount)| {
+        let (party, token) = match key.as_array().map(Vec::as_slice) {
+            Some([party, token]) => (party, token),
+            _ => return Err(format!("Expected an account owner and a token, found: {key}")),
+        };
+        Ok((constr(0, vec![party_data(party)?, token_data(token)?]), integer(amount)?))
+    }).collect::<Result<_, String>>()?;
+    let choices = entries(state, "choices")?.into_iter().map(|(choice_id, chosen)| {
+        let owner = choice_id.get("choice_owner").ok_or_else(|| format!("Expected a choice_owner in: {choice_id}"))?;
+        Ok((constr(0, vec![text(string(choice_id, "choice_name")?), party_data(owner)?]), integer(chosen)?))
+    }).collect::<Result<_, String>>()?;
+    let bound_values = entries(state, "boundValues")?.into_iter().map(|(name, value)| {
+        let name = name.as_str().ok_or_else(|| format!("Expected the name of a bound value, found: {name}"))?;
+        Ok((text(name), integer(value)?))
+    }).collect::<Result<_, String>>()?;
+    let min_time = match state.get("minTime") {
+        None | Some(Json::Null) => PlutusData::Integer(0),
+        Some(min_time) => integer(min_time)?,
+    };
+    Ok(constr(0, vec![PlutusData::Map(accounts), PlutusData::Map(choices), PlutusData::Map(bound_values), min_time]))
+}
+
+/// Reads a contract or a full datum from hex CBOR.
+pub fn decode(hex: &str) -> Result<DecodedDatum, String> {
+    match PlutusData::from_hex(hex)? {
+        // A contract is never a constructor 0 with fields (that is Close)
+        PlutusData::Constr(0, fields) if fields.len() == 3 => {
+            let [params, state, contract]: [PlutusData; 3] = fields.try_into().unwrap();
+            let roles_currency = match params {
+                PlutusData::Constr(0, fields) => match fields.as_slice() {
+                    [PlutusData::Bytes(currency)] => to_hex(currency),
+                    _ => return Err(unexpected("MarloweParams", &PlutusData::Constr(0, fields))),
+                },
+                other => return Err(unexpected("MarloweParams", &other)),
+            };
+            Ok(DecodedDatum { contract: decode_contract(&contract)?, state: Some(decode_state(&state)?), roles_currency: Some(roles_currency) })
+        }
+        data => Ok(DecodedDatum { contract: decode_contract(&data)?, state: None, roles_currency: None }),
+    }
+}
+
+fn unexpected(expected: &str, found: &PlutusData) -> String {
+    let found = match found {
+        PlutusData::Constr(index, fields) => format!("constructor {index} with {} fields", fields.len()),
+        PlutusData::Map(_) => String::from("a map"),
+        PlutusData::List(_) => String::from("a list"),
+        PlutusData::Integer(n) => format!("the integer {n}"),
+        PlutusData::Bytes(bytes) => format!("the bytes {}", to_hex(bytes)),
+    };
+    format!("Expected {expected}, found {found}.")
+}
+
+fn decode_integer(data: &PlutusData) -> Result<i64, String> {
+    match data {
+        PlutusData::Integer(n) => i64::try_from(*n).map_err(|_| format!("The integer {n} is too large for the Marlowe DSL.")),
+        other => Err(unexpected("an integer", other)),
+    }
+}
+
+fn decode_text(data: &PlutusData) -> Result<String, String> {
+    match data {
+        PlutusData::Bytes(bytes) => String::from_utf8(bytes.clone()).map_err(|_| format!("Expected text, found the bytes {}.", to_hex(bytes))),
+        other => Err(unexpected("text", other)),
+    }
+}
+
+fn decode_hex(data: &PlutusData) -> Result<String, String> {
+    match data {
+        PlutusData::Bytes(bytes) => Ok(to_hex(bytes)),
+        other => Err(unexpected("bytes", other)),
+    }
+}
+
+fn boxed<T>(result: Result<T, String>) -> Result<Option<Box<T>>, String> {
+    result.map(|item| Some(Box::new(item)))
+}
+
+fn decode_contract(data: &PlutusData) -> Result<Contract, String> {
+    let PlutusData::Constr(index, fields) = data else { return Err(unexpected("a Contract", data)) };
+    match (index, fields.as_slice()) {
+        (0, []) => Ok(Contract::Close),
+        (1, [from_account, to, token, pay, then]) => Ok(Contract::Pay {
+            from_account: Some(decode_party(from_account)?),
+            to: Some(decode_payee(to)?),
+            token: Some(decode_token(token)?),
+            pay: Some(decode_value(pay)?),
+            then: boxed(decode_contract(then))?,
+        }),
+        (2, [observation, then, r#else]) => Ok(Contract::If {
+            r#if: Some(decode_observation(observation)?),
+            then: boxed(decode_contract(then))?,
+            r#else: boxed(decode_contract(r#else))?,
+        }),
+        (3, [PlutusData::List(cases), timeout, timeout_continuation]) => Ok(Contract::When {
+            when: cases.iter().map(|case| decode_case(case).map(Some)).collect::<Result<_, String>>()?,
+            timeout: Some(Timeout::TimeConstant(decode_integer(timeout)?)),
+            timeout_continuation: boxed(decode_contract(timeout_continuation))?,
+        }),
+        (4, [name, be, then]) => Ok(Contract::Let {
+            r#let: decode_text(name)?,
+            be: boxed(decode_value(be))?,
+            then: boxed(decode_contract(then))?,
+        }),
+        (5, [observation, then]) => Ok(Contract::Assert {
+            assert: Some(decode_observation(observation)?),
+            then: boxed(decode_contract(then))?,
+        }),
+        _ => Err(unexpected("a Contract", data)),
+    }
+}
+
+fn decode_case(data: &PlutusData) -> Result<Case, String> {
+    match data {
+        PlutusData::Constr(0, fields) if fields.len() == 2 => Ok(Case {
+            case: Some(decode_action(&fields[0])?),
+            then: boxed(decode_contract(&fields[1]))?,
+        }),
+        PlutusData::Constr(1, fields) if fields.len() == 2 => Err(format!(
+            "Found a merkleized case with the continuation {}. Merkleized contracts can not be shown in the DSL.", decode_hex(&fields[1])?)),
+        _ => Err(unexpected("a Case", data)),
+    }
+}
+
+fn decode_action(data: &PlutusData) -> Result<Action, String> {
+    let PlutusData::Constr(index, fields) = data else { return Err(unexpected("an Action", data)) };
+    match (index, fields.as_slice()) {
+        (0, [into_account, party, token, deposits]) => Ok(Action::Deposit {
+            into_account: Some(decode_party(into_account)?),
+            party: Some(decode_party(party)?),
+            of_token: Some(decode_token(token)?),
+            deposits: Some(decode_value(deposits)?),
+        }),
+        (1, [choice_id, PlutusData::List(bounds)]) => Ok(Action::Choice {
+            for_choice: Some(decode_choice_id(choice_id)?),
+            choose_between: bounds.iter().map(|bound| match bound {
+                PlutusData::Constr(0, fields) if fields.len() == 2 => Ok(Some(Bound(decode_integer(&fields[0])?, decode_integer(&fields[1])?))),
+                other => Err(unexpected("a Bound", other)),
+            }).collect::<Result<_, String>>()?,
+        }),
+        (2, [observation]) => Ok(Action::Notify { notify_if: Some(decode_observation(observation)?) }),
+        _ => Err(unexpected("an Action", data)),
+    }
+}
+
+fn decode_party(data: &PlutusData) -> Result<Party, String> {
+    match data {
+        // The DSL only accepts upper case public key hashes
+        PlutusData::Constr(0, fields) if fields.len() == 1 => Ok(Party::PK { pk_hash: decode_hex(&fields[0])?.to_uppercase() }),
+        PlutusData::Constr(1, fields) if fields.len() == 1 => Ok(Party::Role { role_token: decode_text(&fields[0])? }),
+        _ => Err(unexpected("a Party", data)),
+    }
+}
+
+fn decode_payee(data: &PlutusData) -> Result<Payee, String> {
+    match data {
+        PlutusData::Constr(0, fields) if fields.len() == 1 => Ok(Payee::Account(Some(decode_party(&fields[0])?))),
+        PlutusData::Constr(1, fields) if fields.len() == 1 => Ok(Payee::Party(Some(decode_party(&fields[0])?))),
+        _ => Err(unexpected("a Payee", data)),
+    }
+}
+
+fn decode_token(data: &PlutusData) -> Result<Token, String> {
+    match data {
+        PlutusData::Constr(0, fields) if fields.len() == 2 => {
+            let currency_symbol = decode_hex(&fields[0])?;
+            let token_name = decode_text(&fields[1])?;
+            if currency_symbol.is_empty() && token_name.is_empty() {
+                Ok(Token::ADA)
+            } else {
+                Ok(Token::Custom { currency_symbol, token_name })
+            }
+        }
+        _ => Err(unexpected("a Token", data)),
+    }
+}
+
+fn decode_choice_id(data: &PlutusData) -> Result<ChoiceId, String> {
+    match data {
+        PlutusData::Constr(0, fields) if fields.len() == 2 => Ok(ChoiceId {
+            choice_name: decode_text(&fields[0])?,
+            choice_owner: Some(decode_party(&fields[1])?),
+        }),
+        _ => Err(unexpected("a ChoiceId", data)),
+    }
+}
+
+fn decode_value(data: &PlutusData) -> Result<Value, String> {
+    let PlutusData::Constr(index, fields) = data else { return Err(unexpected("a Value", data)) };
+    let value = |data| boxed(decode_value(data));
+    match (index, fields.as_slice()) {
+        (0, [party, token]) => Ok(Value::AvailableMoney(Some(decode_party(party)?), Some(decode_token(token)?))),
+        (1, [n]) => Ok(Value::ConstantValue(decode_integer(n)?)),
+        (2, [a]) => Ok(Value::NegValue(value(a)?)),
+        (3, [a, b]) => Ok(Value::AddValue(value(a)?, value(b)?)),
+        (4, [a, b]) => Ok(Value::SubValue(value(a)?, value(b)?)),
+        (5, [a, b]) => Ok(Value::MulValue(value(a)?, value(b)?)),
+        (6, [a, b]) => Ok(Value::DivValue(value(a)?, value(b)?)),
+        (7, [choice_id]) => Ok(Value::ChoiceValue(Some(decode_choice_id(choice_id)?))),
+        (8, []) => Ok(Value::TimeIntervalStart),
+        (9, []) => Ok(Value::TimeIntervalEnd),
+        (10, [name]) => Ok(Value::UseValue(decode_text(name)?)),
+        (11, [observation, a, b]) => Ok(Value::Cond(Some(decode_observation(observation)?), value(a)?, value(b)?)),
+        _ => Err(unexpected("a Value", data)),
+    }
+}
+
+fn decode_observation(data: &PlutusData) -> Result<Observation, String> {
+    let PlutusData::Constr(index, fields) = data else { return Err(unexpected("an Observation", data)) };
+    let observation = |data| boxed(decode_observation(data));
+    let value = |data| boxed(decode_value(data));
+    match (index, fields.as_slice()) {
+        (0, [a, b]) => Ok(Observation::AndObs { both: observation(a)?, and: observation(b)? }),
+        (1, [a, b]) => Ok(Observation::OrObs { either: observation(a)?, or: observation(b)? }),
+        (2, [a]) => Ok(Observation::NotObs { not: observation(a)? }),
+        (3, [choice_id]) => Ok(Observation::ChoseSomething(Some(decode_choice_id(choice_id)?))),
+        (4, [a, b]) => Ok(Observation::ValueGE { value: value(a)?, ge_than: value(b)? }),
+        (5, [a, b]) => Ok(Observation::ValueGT { value: value(a)?, gt_than: value(b)? }),
+        (6, [a, b]) => Ok(Observation::ValueLT { value: value(a)?, lt_than: value(b)? }),
+        (7, [a, b]) => Ok(Observation::ValueLE { value: value(a)?, le_than: value(b)? }),
+        (8, [a, b]) => Ok(Observation::ValueEQ { value: value(a)?, equal_to: value(b)? }),
+        (9, []) => Ok(Observation::True),
+        (10, []) => Ok(Observation::False),
+        _ => Err(unexpected("an Observation", data)),
+    }
+}
+
+fn party_json(data: &PlutusData) -> Result<Json, String> {
+    Ok(match decode_party(data)? {
+        Party::Role { role_token } => json!({ "role_token": role_token }),
+        Party::PK { pk_hash } => json!({ "pk_hash": pk_hash.to_lowercase() }),
+    })
+}
+
+fn token_json(data: &PlutusData) -> Result<Json, String> {
+    Ok(match decode_token(data)? {
+        Token::ADA => json!({ "currency_symbol": "", "token_name": "" }),
+        Token::Custom { currency_symbol, token_name } => json!({ "currency_symbol": currency_symbol, "token_name": token_name }),
+    })
+}
+
+/// Decodes a State to Marlowe JSON.
+pub fn decode_state(data: &PlutusData) -> Result<Json, String> {
+    let fields = match data {
+        PlutusData::Constr(0, fields) if fields.len() == 4 => fields,
+        _ => return Err(unexpected("a State", data)),
+    };
+    let map = |data: &PlutusData, f: &dyn Fn(&PlutusData, &PlutusData) -> Result<Json, String>| match data {
+        PlutusData::Map(entries) => entries.iter().map(|(key, value)| f(key, value)).collect::<Result<Vec<Json>, String>>(),
+        other => Err(unexpected("a map", other)),
+    };
+    let accounts = map(&fields[0], &|key, amount| match key {
+        PlutusData::Constr(0, account) if account.len() == 2 =>
+            Ok(json!([[party_json(&account[0])?, token_json(&account[1])?], decode_integer(amount)?])),
+        other => Err(unexpected("an account owner and a token", other)),
+    })?;
+    let choices = map(&fields[1], &|choice_id, chosen| match choice_id {
+        PlutusData::Constr(0, choice) if choice.len() == 2 =>
+            Ok(json!([{ "choice_name": decode_text(&choice[0])?, "choice_owner": party_json(&choice[1])? }, decode_integer(chosen)?])),
+        other => Err(unexpected("a ChoiceId", other)),
+    })?;
+    let bound_values = map(&fields[2], &|name, value| Ok(json!([decode_text(name)?, decode_integer(value)?])))?;
+    Ok(json!({
+        "accounts": accounts,
+        "choices": choices,
+        "boundValues": bound_values,
+        "minTime": decode_integer(&fields[3])?,
+    }))
+}
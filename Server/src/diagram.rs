@@ -0,0 +1,229 @@
+// Renders a contract as a flowchart, either as Graphviz DOT or as Mermaid.
+//
+// - When: a box with its timeout, one edge per case labeled with the action,
+//   and a dashed edge to the timeout continuation
+// - If: a diamond with "true" and "false" edges
+// - Pay, Let and Assert: boxes describing what they do
+// - Close: a rounded node
+//
+// With `collapse_repeated_subtrees`, identical subtrees are only drawn once and
+// every occurrence points at the same nodes. With `merkleized_continuations`,
+// case continuations are drawn behind a link node with the hash of the
+// continuation, the same way they would be referenced from a merkleized contract.
+
+use std::collections::HashMap;
+use serde::Deserialize;
+use marlowe_lang::types::marlowe::*;
+use crate::contract_model::{child_at, child_count};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DiagramFormat {
+    Dot,
+    Mermaid,
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct DiagramOptions {
+    pub collapse_repeated_subtrees: bool,
+    pub merkleized_continuations: bool,
+}
+
+#[derive(Clone, Copy)]
+enum Shape {
+    Box,
+    Diamond,
+    Rounded,
+    Link,
+    Hole,
+}
+
+struct Edge {
+    from: usize,
+    to: usize,
+    label: String,
+    dashed: bool,
+}
+
+#[derive(Default)]
+struct Graph {
+    nodes: Vec<(Shape, String)>,
+    edges: Vec<Edge>,
+    /// Node ids by the DSL text of the subtree they render, used when collapsing.
+    rendered: HashMap<String, usize>,
+}
+
+fn show<T: std::fmt::Display>(item: &Option<T>, hole: &str) -> String {
+    match item {
+        Some(item) => item.to_string(),
+        None => hole.to_string(),
+    }
+}
+
+/// A short description of an action, used as the label of case edges.
+pub fn describe_action(action: &Option<Action>) -> String {
+    match action {
+        None => String::from("?action"),
+        Some(Action::Deposit { party, of_token, into_account, deposits }) => format!(
+            "Deposit {} {} from {} into the account of {}",
+            show(deposits, "?value"), show(of_token, "?token"), show(party, "?party"), show(into_account, "?party")),
+        Some(Action::Choice { for_choice, choose_between }) => {
+            let bounds: Vec<String> = choose_between.iter().map(|b| match b {
+                Some(Bound(from, to)) => format!("{from}..{to}"),
+                None => String::from("?bound"),
+            }).collect();
+            match for_choice {
+                Some(choice) => format!("{} chooses \"{}\" in [{}]", show(&choice.choice_owner, "?party"), choice.choice_name, bounds.join(", ")),
+                None => format!("?choiceId in [{}]", bounds.join(", ")),
+            }
+        }
+        Some(Action::Notify { notify_if }) => format!("Notify if {}", show(notify_if, "?observation")),
+    }
+}
+
+/// Identifies a continuation by its content, so that identical continuations get the same id.
+pub fn continuation_hash(contract: &Contract) -> String {
+    use std::hash::{Hash, Hasher};
+    let mut hasher = std::collections::hash_map::DefaultHasher::new();
+    contract.to_string().hash(&mut hasher);
+    format!("{:016x}", hasher.finish())
+}
+
+impl Graph {
+
+    fn node(&mut self, shape: Shape, label: String) -> usize {
+        self.nodes.push((shape, label));
+        self.nodes.len() - 1
+    }
+
+    fn edge(&mut self, from: usize, to: usize, label: &str, dashed: bool) {
+        self.edges.push(Edge { from, to, label: label.to_string(), dashed })
+    }
+
+    fn contract(&mut self, contract: Option<&Contract>, options: &DiagramOptions) -> usize {
+        let contract = match contract {
+            Some(contract) => contract,
+            None => return self.node(Shape::Hole, String::from("?contract")),
+        };
+
+        let key = contract.to_string();
+        if options.collapse_repeated_subtrees {
+            if let Some(id) = self.rendered.get(&key) {
+                return *id
+            }
+        }
+
+        let (shape, label) = match contract {
+            Contract::Close => (Shape::Rounded, String::from("Close")),
+            Contract::When { timeout, .. } =>
+                (Shape::Box, format!("When\ntimeout: {}", show(timeout, "?timeout"))),
+            Contract::If { r#if, .. } => (Shape::Diamond, show(r#if, "?observation")),
+            Contract::Pay { from_account, to, token, pay, .. } =>
+                (Shape::Box, format!("Pay {} {}\nfrom {}\nto {}", show(pay, "?value"), show(token, "?token"), show(from_account, "?party"), show(to, "?payee"))),
+            Contract::Let { r#let, be, .. } => (Shape::Box, format!("Let \"{}\" = {}", r#let, show(be, "?value"))),
+            Contract::Assert { assert, .. } => (Shape::Box, format!("Assert {}", show(assert, "?observation"))),
+        };
+        let id = self.node(shape, label);
+        if options.collapse_repeated_subtrees {
+            self.rendered.insert(key, id);
+        }
+
+        match contract {
+            Contract::When { when, timeout, timeout_continuation } => {
+                for case in when {
+                    match case {
+                        Some(case) => {
+                            let label = describe_action(&case.case);
+                            let target = match (&case.then, options.merkleized_continuations) {
+                                (Some(then), true) => {
+                                    let link = self.node(Shape::Link, format!("merkleized continuation\n{}", continuation_hash(then)));
+                                    let continuation = self.contract(Some(then), options);
+                                    self.edge(link, continuation, "", true);
+                                    link
+                                }
+                                (then, _) => self.contract(then.as_deref(), options),
+                            };
+                            self.edge(id, target, &label, false)
+                        }
+                        None => {
+                            let hole = self.node(Shape::Hole, String::from("?case"));
+                            self.edge(id, hole, "", false)
+                        }
+                    }
+                }
+                let continuation = self.contract(timeout_continuation.as_deref(), options);
+                self.edge(id, continuation, &format!("after {}", show(timeout, "?timeout")), true)
+            }
+            Contract::If { then, r#else, .. } => {
+                let then = self.contract(then.as_deref(), options);
+                self.edge(id, then, "true", false);
+                let r#else = self.contract(r#else.as_deref(), options);
+                self.edge(id, r#else, "false", false)
+            }
+            _ => {
+                for index in 0..child_count(contract) {
+                    let child = self.contract(child_at(contract, index), options);
+                    self.edge(id, child, "", false)
+                }
+            }
+        }
+        id
+    }
+
+    fn to_dot(&self) -> String {
+        let escape = |s: &str| s.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n");
+        let mut out = String::from("digraph contract {\n    node [fontname=\"Helvetica\"];\n    edge [fontname=\"Helvetica\"];\n");
+        for (id, (shape, label)) in self.nodes.iter().enumerate() {
+            let attributes = match shape {
+                Shape::Box => "shape=box",
+                Shape::Diamond => "shape=diamond",
+                Shape::Rounded => "shape=box, style=rounded",
+                Shape::Link => "shape=note, style=dashed",
+                Shape::Hole => "shape=box, style=dashed, color=red",
+            };
+            out.push_str(&format!("    n{id} [{attributes}, label=\"{}\"];\n", escape(label)));
+        }
+        for e in &self.edges {
+            let style = if e.dashed { ", style=dashed" } else { "" };
+            out.push_str(&format!("    n{} -> n{} [label=\"{}\"{style}];\n", e.from, e.to, escape(&e.label)));
+        }
+        out.push_str("}\n");
+        out
+    }
+
+    fn to_mermaid(&self) -> String {
+        let escape = |s: &str| s.replace('"', "#quot;").replace('|', "#124;").replace('\n', "<br/>");
+        let mut out = String::from("flowchart TD\n");
+        for (id, (shape, label)) in self.nodes.iter().enumerate() {
+            let label = escape(label);
+            let node = match shape {
+                Shape::Box => format!("n{id}[\"{label}\"]"),
+                Shape::Diamond => format!("n{id}{{\"{label}\"}}"),
+                Shape::Rounded => format!("n{id}([\"{label}\"])"),
+                Shape::Link => format!("n{id}[/\"{label}\"/]"),
+                Shape::Hole => format!("n{id}[\"{label}\"]:::hole"),
+            };
+            out.push_str(&format!("    {node}\n"));
+        }
+        for e in &self.edges {
+            let arrow = if e.dashed { "-.->" } else { "-->" };
+            if e.label.is_empty() {
+                out.push_str(&format!("    n{} {arrow} n{}\n", e.from, e.to));
+            } else {
+                out.push_str(&format!("    n{} {arrow}|\"{}\"| n{}\n", e.from, escape(&e.label), e.to));
+            }
+        }
+        out.push_str("    classDef hole stroke:#f00,stroke-dasharray:4\n");
+        out
+    }
+}
+
+/// Renders a contract as a flowchart.
+pub fn render(contract: &Contract, format: DiagramFormat, options: &DiagramOptions) -> String {
+    let mut graph = Graph::default();
+    graph.contract(Some(contract), options);
+    match format {
+        DiagramFormat::Dot => graph.to_dot(),
+        DiagramFormat::Mermaid => graph.to_mermaid(),
+    }
+}
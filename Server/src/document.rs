@@ -0,0 +1,125 @@
+// The public API of the library: one Marlowe document, analysed the same way as
+// a document that is open in the editor.
+
+use lsp_types::{Diagnostic, DocumentSymbol, Hover, Position, SemanticTokens, Url};
+use crate::codegen::Language;
+use crate::config::Config;
+use crate::State;
+
+/// A Marlowe contract, in the DSL (`.marlowe`) or as JSON (`.marlowe.json`), parsed and
+/// validated the same way as by the language server.
+///
+/// ```
+/// use marlowe_lsp::Document;
+/// use marlowe_lsp::lsp_types::{NumberOrString, Url};
+///
+/// let uri = Url::parse("file:///contracts/escrow.marlowe").unwrap();
+/// let document = Document::parse(uri, "When [] 100 ?refund");
+/// let problem = &document.diagnostics()[0];
+/// assert_eq!(problem.code, Some(NumberOrString::String("ML011".into())));
+/// assert!(document.format().is_ok());
+/// ```
+#[derive(Debug)]
+pub struct Document {
+    uri: Url,
+    text: String,
+    config: Config,
+    state: State,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl Document {
+
+    /// Parses and validates a document with the default configuration.
+    ///
+    /// The uri tells DSL documents from JSON documents by its extension. For `file:` uris,
+    /// the parameter file and the continuation map next to the document are read as well.
+    /// A document that can not be parsed is still a Document, with a syntax error (ML000)
+    /// as its diagnostic.
+    pub fn parse(uri: Url, text: &str) -> Document {
+        Document::parse_with_config(uri, text, &Config::default())
+    }
+
+    /// Parses and validates a document with a configuration, for example one read from a
+    /// `marlowe-lsp.toml` with [`Config::load`].
+    pub fn parse_with_config(uri: Url, text: &str, config: &Config) -> Document {
+        let mut state = crate::open_document(&uri, text, config);
+        let diagnostics = crate::get_diagnostics(&mut state, &uri);
+        Document { uri, text: text.to_string(), config: config.clone(), state, diagnostics }
+    }
+
+    pub fn uri(&self) -> &Url {
+        &self.uri
+    }
+
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    /// Errors and warnings, as the language server publishes them: every diagnostic has
+    /// the id of its rule (see [`RULES`](crate::RULES)) as its code, suppression comments
+    /// and the severities of the configuration are applied.
+    pub fn diagnostics(&self) -> &[Diagnostic] {
+        &self.diagnostics
+    }
+
+    /// The outline of the contract (When, Case, Pay..), None if it can not be parsed.
+    pub fn symbols(&self) -> Option<Vec<DocumentSymbol>> {
+        crate::get_document_symbols(&self.state, &self.uri)
+    }
+
+    /// The document formatted with the format options of the configuration, an error if it
+    /// can not be parsed. Comments are kept.
+    pub fn format(&self) -> Result<String, String> {
+        crate::format::format_document(&self.uri, &self.text, &self.config.format)
+    }
+
+    /// What the construct at a position means, like the hover in the editor.
+    pub fn hover(&self, position: Position) -> Option<Hover> {
+        crate::get_hover(&self.state, &self.uri, position)
+    }
+
+    /// Semantic tokens of a DSL document, see [`semantic_token_legend`](crate::semantic_token_legend).
+    /// None for JSON documents.
+    pub fn semantic_tokens(&self) -> Option<SemanticTokens> {
+        crate::get_semantic_tokens(&self.state, &self.uri)
+    }
+
+    /// The contract as a TypeScript module for the marlowe-ts-sdk, with its TimeParams and
+    /// ConstantParams as arguments. The name of the generated function comes from the file
+    /// name. An error if the document can not be parsed or has holes.
+    ///
+    /// ```
+    /// use marlowe_lsp::Document;
+    /// use marlowe_lsp::lsp_types::Url;
+    ///
+    /// let uri = Url::parse("file:///contracts/escrow.marlowe").unwrap();
+    /// let document = Document::parse(uri, "When [] (TimeParam \"deadline\") Close");
+    /// let code = document.to_typescript().unwrap();
+    /// assert!(code.contains("export const escrow = (deadline: bigint): Contract =>"));
+    /// ```
+    pub fn to_typescript(&self) -> Result<String, String> {
+        self.to_code(Language::TypeScript)
+    }
+
+    /// The contract as a Haskell module using Language.Marlowe.Extended.V1, like
+    /// [`to_typescript`](Document::to_typescript).
+    ///
+    /// ```
+    /// use marlowe_lsp::Document;
+    /// use marlowe_lsp::lsp_types::Url;
+    ///
+    /// let uri = Url::parse("file:///contracts/escrow.marlowe").unwrap();
+    /// let document = Document::parse(uri, "When [] (TimeParam \"deadline\") Close");
+    /// let code = document.to_haskell().unwrap();
+    /// assert!(code.contains("escrow :: Timeout -> Contract"));
+    /// ```
+    pub fn to_haskell(&self) -> Result<String, String> {
+        self.to_code(Language::Haskell)
+    }
+
+    fn to_code(&self, language: Language) -> Result<String, String> {
+        let source = crate::get_contract_source(&self.state, &self.uri).unwrap_or_default();
+        crate::codegen::dsl_to_code(&source, &crate::file_stem(&self.uri), language)
+    }
+}
@@ -0,0 +1,262 @@
+// Bounded exploration of the execution paths of a contract, similar to the
+// "Static analysis" in the Marlowe Playground.
+//
+// Starting from the root, every When is a branch point: each case that can fire
+// (deposits, notifications that are true, choices using the lowest and highest
+// value of each bound) and the timeout. Each branch is computed with the interpreter,
+// and for every warning it produces (failed asserts, partial payments, non-positive
+// deposits..) we keep the shortest trace of inputs that leads to it.
+//
+// TimeParams without a value get made up values in the order they appear in the
+// contract, so that nested timeouts come after the timeouts of their parents.
+// Paths that depend on a ConstantParam without a value are not explored.
+//
+// Many paths lead to the same When with the same state (deposits in a different
+// order, a choice of the low and the high bound that both end up in the same
+// branch..). Those are only explored once, so the limits are spent on distinct paths.
+
+use std::collections::{HashMap, HashSet, VecDeque};
+use lsp_types::{Diagnostic, DiagnosticRelatedInformation, DiagnosticSeverity, Location, NumberOrString, Range, Url};
+use marlowe_lang::types::marlowe::{Contract, Timeout};
+use crate::contract_model::*;
+use crate::interpreter::*;
+
+#[derive(Clone, Copy, Debug, Hash, serde::Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct ExplorationLimits {
+    /// Maximum number of contract states to visit.
+    pub max_states: usize,
+    /// Maximum number of transactions along a single path.
+    pub max_transactions: usize,
+}
+
+impl Default for ExplorationLimits {
+    fn default() -> Self {
+        ExplorationLimits { max_states: 5000, max_transactions: 50 }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct TraceStep {
+    pub description: String,
+    /// The case or When that this step is about.
+    pub range: Option<Range>,
+}
+
+#[derive(Clone, Debug)]
+pub struct Counterexample {
+    pub warning: Warning,
+    /// Where the warning happens in the document.
+    pub range: Option<Range>,
+    pub trace: Vec<TraceStep>,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct ExplorationResult {
+    pub counterexamples: Vec<Counterexample>,
+    pub explored_states: usize,
+    /// Branches that could not be computed (holes, parameters without values..)
+    pub incomplete_paths: usize,
+    /// True if the limits were reached before all paths had been explored.
+    pub exhausted: bool,
+}
+
+struct PathState {
+    path: ContractPath,
+    state: MarloweState,
+    time: i64,
+    trace: Vec<TraceStep>,
+}
+
+fn with_synthetic_time_params(contract: &Contract, params: &HashMap<String, i64>) -> HashMap<String, i64> {
+    let mut result = params.clone();
+    let mut next_value = 1000;
+    walk_contracts(contract, &mut |_, node| {
+        if let Contract::When { timeout: Some(Timeout::TimeParam(name)), .. } = node {
+            if !result.contains_key(name) {
+                result.insert(name.clone(), next_value);
+                next_value += 1000;
+            }
+        }
+    });
+    result
+}
+
+fn report(
+    parsed: &ParsedContract,
+    output: &TransactionOutput,
+    trace: &[TraceStep],
+    reported: &mut HashSet<(WarningKind, ContractPath)>,
+    result: &mut ExplorationResult,
+) {
+    for warning in &output.warnings {
+        if !reported.insert((warning.kind, warning.path.clone())) {
+            continue
+        }
+        let range = match warning.kind {
+            WarningKind::NonPositiveDeposit => parsed.case_ranges.get(&warning.path),
+            _ => parsed.contract_ranges.get(&warning.path),
+        };
+        result.counterexamples.push(Counterexample {
+            warning: warning.clone(),
+            range: range.copied(),
+            trace: trace.to_vec(),
+        })
+    }
+}
+
+/// Inputs to try for a possible action, together with a description of each.
+fn inputs_for(action: &PossibleAction) -> Vec<(Input, String)> {
+    match &action.input {
+        Some(Input::Choice { choice_name, choice_owner, .. }) => {
+            let mut values: Vec<i64> = action.bounds.iter()
+                .filter(|(low, high)| low <= high)
+                .flat_map(|(low, high)| [*low, *high])
+                .collect();
+            values.sort_unstable();
+            values.dedup();
+            values.into_iter().map(|v| (
+                Input::Choice { choice_name: choice_name.clone(), choice_owner: choice_owner.clone(), chosen_num: v },
+                format!("{choice_owner} chooses {v} for \"{choice_name}\""),
+            )).collect()
+        }
+        Some(input) => vec![(input.clone(), action.description.clone())],
+        None => vec![],
+    }
+}
+
+/// Explores the contract breadth first, so the first trace found for a warning is also the shortest.
+pub fn explore(parsed: &ParsedContract, params: &HashMap<String, i64>, limits: ExplorationLimits) -> ExplorationResult {
+
+    let root = &parsed.contract;
+    let params = with_synthetic_time_params(root, params);
+    let env_at = |time: i64| Environment { time_interval: (time, time), params: params.clone() };
+
+    let mut result = ExplorationResult::default();
+    let mut reported = HashSet::new();
+    let mut queue = VecDeque::new();
+    let mut seen: HashSet<(ContractPath, MarloweState, i64)> = HashSet::new();
+    // Queues a state that no shorter path has led to
+    let mut enqueue = |queue: &mut VecDeque<PathState>, next: PathState| {
+        if seen.insert((next.path.clone(), next.state.clone(), next.time)) {
+            queue.push_back(next)
+        }
+    };
+
+    let mut initial_state = MarloweState::default();
+    match compute_transaction(root, vec![], &env_at(0), &mut initial_state, None) {
+        Ok((path, output)) => {
+            report(parsed, &output, &[], &mut reported, &mut result);
+            enqueue(&mut queue, PathState { path, state: initial_state, time: 0, trace: vec![] });
+        }
+        Err(_) => result.incomplete_paths += 1,
+    }
+
+    while let Some(current) = queue.pop_front() {
+
+        if result.explored_states >= limits.max_states {
+            result.exhausted = true;
+            break
+        }
+        result.explored_states += 1;
+
+        let timeout = match node_at(root, &current.path) {
+            Some(Contract::When { timeout, .. }) => timeout,
+            _ => continue, // closed
+        };
+
+        if current.trace.len() >= limits.max_transactions {
+            result.exhausted = true;
+            continue
+        }
+
+        let env = env_at(current.time);
+
+        for action in possible_actions(root, &current.path, &env, &current.state) {
+            if !action.enabled {
+                continue
+            }
+            let mut case_path = current.path.clone();
+            case_path.push(action.case_index);
+            let inputs = inputs_for(&action);
+            if inputs.is_empty() {
+                result.incomplete_paths += 1;
+            }
+            for (input, description) in inputs {
+                let mut state = current.state.clone();
+                match compute_transaction(root, current.path.clone(), &env, &mut state, Some(&input)) {
+                    Ok((path, output)) => {
+                        let mut trace = current.trace.clone();
+                        trace.push(TraceStep { description, range: parsed.case_ranges.get(&case_path).copied() });
+                        report(parsed, &output, &trace, &mut reported, &mut result);
+                        enqueue(&mut queue, PathState { path, state, time: current.time, trace });
+                    }
+                    Err(_) => result.incomplete_paths += 1,
+                }
+            }
+        }
+
+        // The timeout branch
+        match timeout_value(timeout, &env) {
+            Ok(t) => {
+                let mut state = current.state.clone();
+                let time = t.max(current.time);
+                match compute_transaction(root, current.path.clone(), &env_at(time), &mut state, None) {
+                    Ok((path, output)) => {
+                        let mut trace = current.trace.clone();
+                        trace.push(TraceStep {
+                            description: format!("Wait until the timeout ({t}) of this When has passed"),
+                            range: parsed.contract_ranges.get(&current.path).copied(),
+                        });
+                        report(parsed, &output, &trace, &mut reported, &mut result);
+                        enqueue(&mut queue, PathState { path, state, time, trace });
+                    }
+                    Err(_) => result.incomplete_paths += 1,
+                }
+            }
+            Err(_) => result.incomplete_paths += 1,
+        }
+    }
+
+    result
+}
+
+/// The id of the rule (see rules) that a warning of the interpreter is reported under.
+fn rule_id(kind: WarningKind) -> &'static str {
+    match kind {
+        WarningKind::NonPositiveDeposit => "ML040",
+        WarningKind::NonPositivePay => "ML041",
+        WarningKind::PartialPay => "ML042",
+        WarningKind::Shadowing => "ML043",
+        WarningKind::AssertionFailed => "ML044",
+    }
+}
+
+pub fn to_diagnostics(result: &ExplorationResult, uri: &Url) -> Vec<Diagnostic> {
+    result.counterexamples.iter().filter_map(|c| {
+        let range = c.range?;
+        let steps: Vec<DiagnosticRelatedInformation> = c.trace.iter().enumerate().filter_map(|(i, step)| {
+            Some(DiagnosticRelatedInformation {
+                location: Location { uri: uri.clone(), range: step.range? },
+                message: format!("Step {}: {}", i + 1, step.description),
+            })
+        }).collect();
+        let message = if c.trace.is_empty() {
+            format!("{} This happens without any input.", c.warning.message)
+        } else {
+            format!("{} This can happen after {} step(s): {}.", c.warning.message, c.trace.len(),
+                c.trace.iter().map(|s| s.description.clone()).collect::<Vec<String>>().join(", then "))
+        };
+        Some(Diagnostic {
+            range,
+            severity: Some(DiagnosticSeverity::WARNING),
+            code: Some(NumberOrString::String(rule_id(c.warning.kind).to_string())),
+            code_description: None,
+            source: None,
+            message,
+            related_information: if steps.is_empty() { None } else { Some(steps) },
+            tags: None,
+            data: None,
+        })
+    }).collect()
+}
@@ -0,0 +1,257 @@
+// Formatting of Marlowe documents.
+//
+// DSL contracts are formatted from the marlowe_lang parse tree, so that holes
+// keep their names. Every node is printed on one line if it fits, otherwise its
+// children go on their own lines, one level deeper:
+//
+//   When
+//       [
+//           (Case
+//               (Deposit (Role "Seller") (Role "Buyer") (Token "" "") (Constant 100))
+//               Close)]
+//       1700000000000
+//       Close
+//
+// Closing brackets and commas stay at the end of the line they close. Comments
+// are put on their own line, before the line where the node they belong to starts.
+//
+// JSON documents are printed with one field or item per line, keeping the
+// order of fields and numbers as they were written.
+
+use marlowe_lang::parsing::{MarloweParser, Rule};
+use pest::Parser;
+use pest::iterators::Pair;
+use crate::json_document::{JsonNode, JsonValue, parse_json};
+use crate::suppression::{Comment, comments, without_comments};
+
+#[derive(Clone, Debug, serde::Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct FormatOptions {
+    /// Spaces per level of indentation.
+    pub indent_width: usize,
+    /// Nodes that fit in this many characters are kept on one line.
+    pub max_line_width: usize,
+}
+
+impl Default for FormatOptions {
+    fn default() -> Self {
+        FormatOptions { indent_width: 4, max_line_width: 100 }
+    }
+}
+
+/// Collapses whitespace outside of string literals, and removes it inside brackets and before commas.
+fn normalize(text: &str) -> String {
+    let mut out = String::new();
+    let mut in_string = false;
+    let mut escaped = false;
+    let mut pending_space = false;
+    for c in text.chars() {
+        if in_string {
+            out.push(c);
+            match c {
+                _ if escaped => escaped = false,
+                '\\' => escaped = true,
+                '"' => in_string = false,
+                _ => {}
+            }
+            continue
+        }
+        if c.is_whitespace() {
+            pending_space = true;
+            continue
+        }
+        if pending_space && !out.is_empty() && !out.ends_with(['(', '[']) && !matches!(c, ')' | ']' | ',') {
+            out.push(' ')
+        }
+        pending_space = false;
+        out.push(c);
+        if c == ',' {
+            pending_space = true
+        }
+        if c == '"' {
+            in_string = true
+        }
+    }
+    out
+}
+
+/// Children that can go on their own line. Strings are part of the text around them.
+fn children<'i>(pair: &Pair<'i, Rule>) -> Vec<Pair<'i, Rule>> {
+    pair.clone().into_inner().filter(|p| p.as_rule() != Rule::string).collect()
+}
+
+struct Formatter<'a> {
+    options: &'a FormatOptions,
+    lines: Vec<String>,
+}
+
+impl Formatter<'_> {
+
+    fn push_line(&mut self, depth: usize, text: String) {
+        self.lines.push(format!("{}{text}", " ".repeat(depth * self.options.indent_width)))
+    }
+
+    fn append(&mut self, text: &str) {
+        if let Some(last) = self.lines.last_mut() {
+            last.push_str(text)
+        }
+    }
+
+    /// Prints a node on a new line, after `prefix` (like an opening bracket that belongs to the parent).
+    fn node(&mut self, pair: &Pair<Rule>, depth: usize, prefix: &str, suffix_width: usize) {
+        let flat = normalize(pair.as_str());
+        let inner = children(pair);
+        let width = depth * self.options.indent_width + prefix.chars().count() + flat.chars().count() + suffix_width;
+        if inner.is_empty() || width <= self.options.max_line_width {
+            return self.push_line(depth, format!("{prefix}{flat}"))
+        }
+        // Nodes that only wrap another one, like Contract
+        if inner.len() == 1 && inner[0].as_span() == pair.as_span() {
+            return self.node(&inner[0], depth, prefix, suffix_width)
+        }
+        let text = pair.as_str();
+        let start = pair.as_span().start();
+        let gap = |from: usize, to: usize| normalize(&text[from - start..to - start]);
+        let header = gap(start, inner[0].as_span().start());
+        let mut child_depth = depth + 1;
+        if header.is_empty() {
+            child_depth = depth
+        } else {
+            self.push_line(depth, format!("{prefix}{header}"))
+        }
+        let mut child_prefix = if header.is_empty() { prefix.to_string() } else { String::new() };
+        for (i, child) in inner.iter().enumerate() {
+            let end = match inner.get(i + 1) {
+                Some(next) => next.as_span().start(),
+                None => pair.as_span().end(),
+            };
+            let after = gap(child.as_span().end(), end);
+            // Closing brackets and commas stay on the line of the child, the rest goes before the next child.
+            let closing_length = after.find(|c: char| !matches!(c, ')' | ']' | ',' | ' ')).unwrap_or(after.len());
+            let (closing, opening) = after.split_at(closing_length);
+            let closing = closing.trim_end();
+            let last = i + 1 == inner.len();
+            let suffix = closing.chars().count() + if last { suffix_width } else { 0 };
+            self.node(child, child_depth, &child_prefix, suffix);
+            self.append(closing);
+            child_prefix = match opening.trim() {
+                "" => String::new(),
+                o if o.ends_with(['(', '[']) => o.to_string(),
+                o => format!("{o} "),
+            };
+        }
+    }
+}
+
+/// The number of characters other than whitespace before a byte offset.
+/// Formatting only changes whitespace, so this identifies the same place before and after.
+fn visible_characters_before(text: &str, offset: usize) -> usize {
+    text[..offset].chars().filter(|c| !c.is_whitespace()).count()
+}
+
+/// Puts the comments of the source back into the formatted contract (which has none).
+fn restore_comments(formatted: &str, source: &str, comments: &[Comment]) -> String {
+    let lines: Vec<&str> = formatted.lines().collect();
+    // The line that every visible character of the formatted text is on
+    let line_of: Vec<usize> = lines.iter().enumerate()
+        .flat_map(|(i, line)| line.chars().filter(|c| !c.is_whitespace()).map(move |_| i))
+        .collect();
+    let mut before: Vec<Vec<&str>> = vec![vec![]; lines.len()];
+    let mut trailing = vec![];
+    let blanked = without_comments(source);
+    for comment in comments {
+        let line = comment.target_start.and_then(|start| line_of.get(visible_characters_before(&blanked, start)));
+        match line {
+            Some(&line) => before[line].push(&comment.text),
+            None => trailing.push(comment.text.as_str()),
+        }
+    }
+    let mut out = String::new();
+    for (line, comments) in lines.iter().zip(before) {
+        let indentation = &line[..line.len() - line.trim_start().len()];
+        for comment in comments {
+            out.push_str(&format!("{indentation}{comment}\n"))
+        }
+        out.push_str(line);
+        out.push('\n')
+    }
+    for comment in trailing {
+        out.push_str(&format!("{comment}\n"))
+    }
+    out
+}
+
+/// Formats a DSL contract. Fails if the contract can not be parsed.
+pub fn format_dsl(source: &str, options: &FormatOptions) -> Result<String, String> {
+    let comments = comments(source);
+    if !comments.is_empty() {
+        let formatted = format_dsl(without_comments(source).trim_start(), options)?;
+        return Ok(restore_comments(&formatted, source, &comments))
+    }
+    let mut pairs = MarloweParser::parse(Rule::MainContract, source).map_err(|e| format!("{e:#}"))?;
+    let root = pairs.next().ok_or_else(|| String::from("The document does not contain a contract."))?;
+    let mut formatter = Formatter { options, lines: vec![] };
+    formatter.node(&root, 0, "", 0);
+    let formatted = formatter.lines.join("\n") + "\n";
+    // Only whitespace may change
+    if normalize(&formatted) != normalize(source.trim()) {
+        return Err(String::from("The contract could not be formatted without changing it."))
+    }
+    Ok(formatted)
+}
+
+fn json_string(s: &str) -> String {
+    serde_json::to_string(s).unwrap_or_default()
+}
+
+fn print_json(node: &JsonNode, depth: usize, indent: &str, out: &mut String) {
+    let padding = indent.repeat(depth + 1);
+    match &node.value {
+        JsonValue::Null => out.push_str("null"),
+        JsonValue::Bool(b) => out.push_str(&b.to_string()),
+        JsonValue::Number(n) => out.push_str(n),
+        JsonValue::String(s) => out.push_str(&json_string(s)),
+        JsonValue::Array(items) if items.is_empty() => out.push_str("[]"),
+        JsonValue::Object(fields) if fields.is_empty() => out.push_str("{}"),
+        JsonValue::Array(items) => {
+            out.push('[');
+            for (i, item) in items.iter().enumerate() {
+                out.push_str(if i == 0 { "\n" } else { ",\n" });
+                out.push_str(&padding);
+                print_json(item, depth + 1, indent, out)
+            }
+            out.push('\n');
+            out.push_str(&indent.repeat(depth));
+            out.push(']')
+        }
+        JsonValue::Object(fields) => {
+            out.push('{');
+            for (i, (key, value)) in fields.iter().enumerate() {
+                out.push_str(if i == 0 { "\n" } else { ",\n" });
+                out.push_str(&format!("{padding}{}: ", json_string(key)));
+                print_json(value, depth + 1, indent, out)
+            }
+            out.push('\n');
+            out.push_str(&indent.repeat(depth));
+            out.push('}')
+        }
+    }
+}
+
+/// Formats a JSON document. Fails if it is not valid JSON.
+pub fn format_json(source: &str, options: &FormatOptions) -> Result<String, String> {
+    let root = parse_json(source).map_err(|(message, range)| format!("Line {}: {message}", range.start.line + 1))?;
+    let mut out = String::new();
+    print_json(&root, 0, &" ".repeat(options.indent_width), &mut out);
+    out.push('\n');
+    Ok(out)
+}
+
+/// Formats a document, as DSL or JSON depending on its uri.
+pub fn format_document(uri: &lsp_types::Url, source: &str, options: &FormatOptions) -> Result<String, String> {
+    if crate::json_document::is_json_document(uri) {
+        format_json(source, options)
+    } else {
+        format_dsl(source, options)
+    }
+}
@@ -0,0 +1,438 @@
+// A small interpreter for the Marlowe semantics, used to step through contracts
+// the same way as the Marlowe Playground simulator does.
+//
+// The contract itself is never modified: every continuation in Marlowe is a sub-tree
+// of the original contract, so we only keep track of where we are (a ContractPath)
+// together with the Marlowe state (accounts, choices and bound values).
+//
+// Parties and tokens are identified by their Marlowe DSL representation,
+// for example (Role "Seller") and (Token "" "").
+
+use std::collections::{BTreeMap, HashMap};
+use marlowe_lang::types::marlowe::*;
+use serde::Serialize;
+use crate::contract_model::{ContractPath, node_at};
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct MarloweState {
+    /// (account owner, token) -> amount
+    pub accounts: BTreeMap<(String, String), i64>,
+    /// (choice name, choice owner) -> chosen number
+    pub choices: BTreeMap<(String, String), i64>,
+    pub bound_values: BTreeMap<String, i64>,
+    pub min_time: i64,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Environment {
+    /// Inclusive start and end of the transaction interval.
+    pub time_interval: (i64, i64),
+    /// Values for TimeParam and ConstantParam.
+    pub params: HashMap<String, i64>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum Input {
+    #[serde(rename_all = "camelCase")]
+    Deposit { into_account: String, party: String, token: String, amount: i64 },
+    #[serde(rename_all = "camelCase")]
+    Choice { choice_name: String, choice_owner: String, chosen_num: i64 },
+    Notify,
+}
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Payment {
+    pub from_account: String,
+    pub to: String,
+    pub token: String,
+    pub amount: i64,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize)]
+pub enum WarningKind {
+    NonPositiveDeposit,
+    NonPositivePay,
+    PartialPay,
+    Shadowing,
+    AssertionFailed,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Warning {
+    pub kind: WarningKind,
+    /// For deposits this is the path of the case continuation, otherwise the node itself.
+    pub path: ContractPath,
+    pub message: String,
+}
+
+/// Everything that happened while computing a transaction.
+#[derive(Clone, Debug, Default)]
+pub struct TransactionOutput {
+    pub payments: Vec<Payment>,
+    pub warnings: Vec<Warning>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PossibleAction {
+    pub case_index: usize,
+    pub description: String,
+    /// The input that would trigger this case. For choices, the chosen number
+    /// is set to the lowest allowed value and has to be replaced by the caller.
+    pub input: Option<Input>,
+    pub bounds: Vec<(i64, i64)>,
+    /// False if the action can currently not be applied (a notify with a false observation,
+    /// a choice without any bounds, or an action that could not be evaluated).
+    pub enabled: bool,
+}
+
+pub fn party_key(party: &Option<Party>) -> Result<String, String> {
+    match party {
+        Some(p) => Ok(format!("{p}")),
+        None => Err(String::from("Cannot evaluate a hole of type 'Party'.")),
+    }
+}
+
+pub fn token_key(token: &Option<Token>) -> Result<String, String> {
+    match token {
+        Some(t) => Ok(format!("{t}")),
+        None => Err(String::from("Cannot evaluate a hole of type 'Token'.")),
+    }
+}
+
+fn choice_key(choice: &Option<ChoiceId>) -> Result<(String, String), String> {
+    match choice {
+        Some(c) => Ok((c.choice_name.clone(), party_key(&c.choice_owner)?)),
+        None => Err(String::from("Cannot evaluate a hole of type 'ChoiceId'.")),
+    }
+}
+
+pub fn timeout_value(timeout: &Option<Timeout>, env: &Environment) -> Result<i64, String> {
+    match timeout {
+        Some(Timeout::TimeConstant(t)) => Ok(*t),
+        Some(Timeout::TimeParam(name)) => env.params.get(name).copied()
+            .ok_or_else(|| format!("No value has been provided for the parameter (TimeParam \"{name}\").")),
+        None => Err(String::from("Cannot evaluate a hole of type 'Timeout'.")),
+    }
+}
+
+fn eval_boxed(env: &Environment, state: &MarloweState, value: &Option<Box<Value>>) -> Result<i64, String> {
+    match value {
+        Some(v) => eval_value(env, state, v),
+        None => Err(String::from("Cannot evaluate a hole of type 'Value'.")),
+    }
+}
+
+pub fn eval_value(env: &Environment, state: &MarloweState, value: &Value) -> Result<i64, String> {
+    Ok(match value {
+        Value::TimeIntervalStart => env.time_interval.0,
+        Value::TimeIntervalEnd => env.time_interval.1,
+        Value::AvailableMoney(owner, token) => {
+            let key = (party_key(owner)?, token_key(token)?);
+            *state.accounts.get(&key).unwrap_or(&0)
+        }
+        Value::ConstantValue(n) => *n,
+        Value::ConstantParam(name) => *env.params.get(name)
+            .ok_or_else(|| format!("No value has been provided for the parameter (ConstantParam \"{name}\")."))?,
+        Value::UseValue(name) => *state.bound_values.get(name).unwrap_or(&0),
+        Value::NegValue(a) => eval_boxed(env, state, a)?.saturating_neg(),
+        Value::AddValue(a, b) => eval_boxed(env, state, a)?.saturating_add(eval_boxed(env, state, b)?),
+        Value::SubValue(a, b) => eval_boxed(env, state, a)?.saturating_sub(eval_boxed(env, state, b)?),
+        Value::MulValue(a, b) => eval_boxed(env, state, a)?.saturating_mul(eval_boxed(env, state, b)?),
+        // Marlowe rounds towards zero and division by zero is defined as zero.
+        Value::DivValue(a, b) => eval_boxed(env, state, a)?.checked_div(eval_boxed(env, state, b)?).unwrap_or(0),
+        Value::ChoiceValue(choice) => *state.choices.get(&choice_key(choice)?).unwrap_or(&0),
+        Value::Cond(obs, a, b) => {
+            let condition = match obs {
+                Some(o) => eval_observation(env, state, o)?,
+                None => return Err(String::from("Cannot evaluate a hole of type 'Observation'.")),
+            };
+            if condition { eval_boxed(env, state, a)? } else { eval_boxed(env, state, b)? }
+        }
+    })
+}
+
+fn eval_boxed_observation(env: &Environment, state: &MarloweState, obs: &Option<Box<Observation>>) -> Result<bool, String> {
+    match obs {
+        Some(o) => eval_observation(env, state, o),
+        None => Err(String::from("Cannot evaluate a hole of type 'Observation'.")),
+    }
+}
+
+pub fn eval_observation(env: &Environment, state: &MarloweState, obs: &Observation) -> Result<bool, String> {
+    Ok(match obs {
+        Observation::True => true,
+        Observation::False => false,
+        Observation::AndObs { both, and } => eval_boxed_observation(env, state, both)? && eval_boxed_observation(env, state, and)?,
+        Observation::OrObs { either, or } => eval_boxed_observation(env, state, either)? || eval_boxed_observation(env, state, or)?,
+        Observation::NotObs { not } => !eval_boxed_observation(env, state, not)?,
+        Observation::ChoseSomething(choice) => state.choices.contains_key(&choice_key(choice)?),
+        Observation::ValueGE { value, ge_than } => eval_boxed(env, state, value)? >= eval_boxed(env, state, ge_than)?,
+        Observation::ValueGT { value, gt_than } => eval_boxed(env, state, value)? > eval_boxed(env, state, gt_than)?,
+        Observation::ValueLT { value, lt_than } => eval_boxed(env, state, value)? < eval_boxed(env, state, lt_than)?,
+        Observation::ValueLE { value, le_than } => eval_boxed(env, state, value)? <= eval_boxed(env, state, le_than)?,
+        Observation::ValueEQ { value, equal_to } => eval_boxed(env, state, value)? == eval_boxed(env, state, equal_to)?,
+    })
+}
+
+fn eval_optional(env: &Environment, state: &MarloweState, value: &Option<Value>) -> Result<i64, String> {
+    match value {
+        Some(v) => eval_value(env, state, v),
+        None => Err(String::from("Cannot evaluate a hole of type 'Value'.")),
+    }
+}
+
+fn eval_optional_observation(env: &Environment, state: &MarloweState, obs: &Option<Observation>) -> Result<bool, String> {
+    match obs {
+        Some(o) => eval_observation(env, state, o),
+        None => Err(String::from("Cannot evaluate a hole of type 'Observation'.")),
+    }
+}
+
+/// Saturates like the evaluation of values, so large deposits cannot overflow the balance.
+fn add_money(state: &mut MarloweState, key: (String, String), amount: i64) {
+    if amount > 0 {
+        let balance = state.accounts.entry(key).or_insert(0);
+        *balance = balance.saturating_add(amount);
+    }
+}
+
+fn hole_at(path: &[usize]) -> String {
+    format!("The contract execution reached a hole of type 'Contract' (path {path:?}).")
+}
+
+/// Reduces the contract at `path` until it either waits for input in a When or has been closed.
+/// Returns the path of the When or Close node where the contract stopped.
+pub fn reduce_until_quiescent(
+    root: &Contract,
+    path: ContractPath,
+    env: &Environment,
+    state: &mut MarloweState,
+    output: &mut TransactionOutput,
+) -> Result<ContractPath, String> {
+
+    let mut path = path;
+    state.min_time = state.min_time.max(env.time_interval.0);
+
+    loop {
+        let node = node_at(root, &path).ok_or_else(|| hole_at(&path))?;
+        match node {
+            Contract::Close => {
+                // Closing the contract refunds all accounts to their owners
+                for ((owner, token), amount) in std::mem::take(&mut state.accounts) {
+                    if amount > 0 {
+                        output.payments.push(Payment { from_account: owner.clone(), to: owner, token, amount })
+                    }
+                }
+                return Ok(path)
+            }
+            Contract::When { when, timeout, .. } => {
+                let timeout = timeout_value(timeout, env)?;
+                if timeout <= env.time_interval.0 {
+                    path.push(when.len());
+                } else if timeout <= env.time_interval.1 {
+                    return Err(format!("The transaction interval ({}, {}) contains the timeout {timeout}, so it is ambiguous whether the When has timed out.", env.time_interval.0, env.time_interval.1))
+                } else {
+                    return Ok(path)
+                }
+            }
+            Contract::If { r#if, .. } => {
+                let branch = if eval_optional_observation(env, state, r#if)? { 0 } else { 1 };
+                path.push(branch);
+            }
+            Contract::Assert { assert, .. } => {
+                if !eval_optional_observation(env, state, assert)? {
+                    output.warnings.push(Warning {
+                        kind: WarningKind::AssertionFailed,
+                        path: path.clone(),
+                        message: String::from("Assertion failed."),
+                    })
+                }
+                path.push(0);
+            }
+            Contract::Let { r#let, be, .. } => {
+                let new_value = eval_boxed(env, state, be)?;
+                if let Some(old_value) = state.bound_values.insert(r#let.clone(), new_value) {
+                    output.warnings.push(Warning {
+                        kind: WarningKind::Shadowing,
+                        path: path.clone(),
+                        message: format!("The value \"{}\" was shadowed: it was {old_value} and is now {new_value}.", r#let),
+                    })
+                }
+                path.push(0);
+            }
+            Contract::Pay { from_account, to, token, pay, .. } => {
+                let from = party_key(from_account)?;
+                let token = token_key(token)?;
+                let (to_key, is_account) = match to {
+                    Some(Payee::Account(p)) => (party_key(p)?, true),
+                    Some(Payee::Party(p)) => (party_key(p)?, false),
+                    None => return Err(String::from("Cannot evaluate a hole of type 'Payee'.")),
+                };
+                let amount = eval_optional(env, state, pay)?;
+                if amount <= 0 {
+                    output.warnings.push(Warning {
+                        kind: WarningKind::NonPositivePay,
+                        path: path.clone(),
+                        message: format!("The contract tries to pay a non-positive amount ({amount}) of {token} from {from} to {to_key}."),
+                    })
+                } else {
+                    let key = (from.clone(), token.clone());
+                    let balance = *state.accounts.get(&key).unwrap_or(&0);
+                    let paid = balance.min(amount);
+                    if paid < amount {
+                        output.warnings.push(Warning {
+                            kind: WarningKind::PartialPay,
+                            path: path.clone(),
+                            message: format!("Partial payment: the contract should pay {amount} of {token} from {from} to {to_key}, but the account only has {balance}."),
+                        })
+                    }
+                    if paid > 0 {
+                        let remaining = balance - paid;
+                        if remaining > 0 { state.accounts.insert(key, remaining); } else { state.accounts.remove(&key); }
+                        if is_account {
+                            add_money(state, (to_key, token), paid)
+                        } else {
+                            output.payments.push(Payment { from_account: from, to: to_key, token, amount: paid })
+                        }
+                    }
+                }
+                path.push(0);
+            }
+        }
+    }
+}
+
+/// Lists the actions of the When contract at `path`.
+pub fn possible_actions(root: &Contract, path: &[usize], env: &Environment, state: &MarloweState) -> Vec<PossibleAction> {
+    let cases = match node_at(root, path) {
+        Some(Contract::When { when, .. }) => when,
+        _ => return vec![],
+    };
+    let mut result = vec![];
+    for (case_index, case) in cases.iter().enumerate() {
+        let action = match case.as_ref().and_then(|c| c.case.as_ref()) {
+            Some(a) => a,
+            None => continue,
+        };
+        let described: Result<PossibleAction, String> = (|| {
+            Ok(match action {
+                Action::Deposit { party, of_token, into_account, deposits } => {
+                    let (party, token, into_account) = (party_key(party)?, token_key(of_token)?, party_key(into_account)?);
+                    let amount = eval_optional(env, state, deposits)?;
+                    PossibleAction {
+                        case_index,
+                        description: format!("{party} deposits {amount} of {token} into the account of {into_account}"),
+                        input: Some(Input::Deposit { into_account, party, token, amount }),
+                        bounds: vec![],
+                        enabled: true,
+                    }
+                }
+                Action::Choice { for_choice, choose_between } => {
+                    let (choice_name, choice_owner) = choice_key(for_choice)?;
+                    let bounds: Vec<(i64, i64)> = choose_between.iter().flatten().map(|b| (b.0, b.1)).collect();
+                    PossibleAction {
+                        case_index,
+                        description: format!("{choice_owner} chooses a value for \"{choice_name}\""),
+                        input: bounds.first().map(|b| Input::Choice { choice_name, choice_owner, chosen_num: b.0 }),
+                        enabled: bounds.iter().any(|b| b.0 <= b.1),
+                        bounds,
+                    }
+                }
+                Action::Notify { notify_if } => PossibleAction {
+                    case_index,
+                    description: String::from("Notify"),
+                    input: Some(Input::Notify),
+                    bounds: vec![],
+                    enabled: eval_optional_observation(env, state, notify_if)?,
+                },
+            })
+        })();
+        match described {
+            Ok(a) => result.push(a),
+            Err(e) => result.push(PossibleAction { case_index, description: e, input: None, bounds: vec![], enabled: false }),
+        }
+    }
+    result
+}
+
+/// Applies an input to the When contract at `path`. Like Marlowe itself,
+/// the first case that accepts the input wins. Returns the path of the case continuation.
+pub fn apply_input(
+    root: &Contract,
+    path: &[usize],
+    env: &Environment,
+    state: &mut MarloweState,
+    input: &Input,
+    output: &mut TransactionOutput,
+) -> Result<ContractPath, String> {
+    let cases = match node_at(root, path) {
+        Some(Contract::When { when, .. }) => when,
+        _ => return Err(String::from("The contract is not waiting for any input.")),
+    };
+    for (case_index, case) in cases.iter().enumerate() {
+        let action = match case.as_ref().and_then(|c| c.case.as_ref()) {
+            Some(a) => a,
+            None => continue,
+        };
+        let mut case_path = path.to_vec();
+        case_path.push(case_index);
+        match (action, input) {
+            (Action::Deposit { party, of_token, into_account, deposits },
+             Input::Deposit { into_account: input_account, party: input_party, token: input_token, amount }) => {
+                if party_key(into_account)? == *input_account
+                    && party_key(party)? == *input_party
+                    && token_key(of_token)? == *input_token
+                    && eval_optional(env, state, deposits)? == *amount {
+                    if *amount <= 0 {
+                        output.warnings.push(Warning {
+                            kind: WarningKind::NonPositiveDeposit,
+                            path: case_path.clone(),
+                            message: format!("{input_party} deposits a non-positive amount ({amount}) of {input_token} into the account of {input_account}."),
+                        })
+                    }
+                    add_money(state, (input_account.clone(), input_token.clone()), *amount);
+                    return Ok(case_path)
+                }
+            }
+            (Action::Choice { for_choice, choose_between },
+             Input::Choice { choice_name, choice_owner, chosen_num }) => {
+                let in_bounds = choose_between.iter().flatten().any(|b| b.0 <= *chosen_num && *chosen_num <= b.1);
+                if choice_key(for_choice)? == (choice_name.clone(), choice_owner.clone()) && in_bounds {
+                    state.choices.insert((choice_name.clone(), choice_owner.clone()), *chosen_num);
+                    return Ok(case_path)
+                }
+            }
+            (Action::Notify { notify_if }, Input::Notify) => {
+                if eval_optional_observation(env, state, notify_if)? {
+                    return Ok(case_path)
+                }
+            }
+            _ => {}
+        }
+    }
+    Err(String::from("No case of the current When accepts this input."))
+}
+
+/// Computes a full transaction: reduces the contract, applies the input (if any)
+/// and reduces again until the contract is waiting for input or has been closed.
+pub fn compute_transaction(
+    root: &Contract,
+    path: ContractPath,
+    env: &Environment,
+    state: &mut MarloweState,
+    input: Option<&Input>,
+) -> Result<(ContractPath, TransactionOutput), String> {
+    let mut output = TransactionOutput::default();
+    let path = reduce_until_quiescent(root, path, env, state, &mut output)?;
+    let path = match input {
+        Some(i) => {
+            let continuation = apply_input(root, &path, env, state, i, &mut output)?;
+            reduce_until_quiescent(root, continuation, env, state, &mut output)?
+        }
+        None => path,
+    };
+    Ok((path, output))
+}
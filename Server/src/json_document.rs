@@ -0,0 +1,586 @@
+// Support for contracts written in Marlowe JSON (.marlowe.json files).
+//
+// Instead of teaching every feature of the server about a second syntax, a JSON
+// document is translated to the Marlowe DSL, and everything else (validation,
+// path analysis, hover, outline..) runs on that translation. While translating
+// we record which part of the DSL text came from which JSON node, so that ranges
+// in the DSL can be mapped back to the JSON document and the other way around.
+//
+// The generated DSL is always a single line, so a DSL position is just a
+// character offset.
+//
+// Merkleized cases ("merkleized_then") are printed as normal cases, with the
+// continuation taken from a continuation map (see merkle.rs). Everything that
+// comes from the map is mapped back to the hash in the document.
+
+use std::collections::HashMap;
+use lsp_types::{Position, Range};
+
+/// Marlowe JSON documents are recognised by their file extension. Other JSON files next to
+/// contracts, like parameter files and continuation maps, are not contracts.
+pub fn is_json_document(uri: &lsp_types::Url) -> bool {
+    uri.path().ends_with(".marlowe.json")
+}
+
+#[derive(Debug)]
+pub enum JsonValue {
+    Null,
+    Bool(bool),
+    /// Numbers are kept as written, since Marlowe uses arbitrary precision integers.
+    Number(String),
+    String(String),
+    Array(Vec<JsonNode>),
+    Object(Vec<(String, JsonNode)>),
+}
+
+/// A JSON value together with its range in the document.
+#[derive(Debug)]
+pub struct JsonNode {
+    pub value: JsonValue,
+    pub range: Range,
+}
+
+impl JsonNode {
+    pub fn get(&self, key: &str) -> Option<&JsonNode> {
+        match &self.value {
+            JsonValue::Object(fields) => fields.iter().find(|(k, _)| k == key).map(|(_, v)| v),
+            _ => None,
+        }
+    }
+
+    fn field(&self, key: &str) -> Result<&JsonNode, (String, Range)> {
+        self.get(key).ok_or_else(|| (format!("Missing field '{key}'."), self.range))
+    }
+
+    fn has(&self, key: &str) -> bool {
+        self.get(key).is_some()
+    }
+}
+
+struct JsonParser {
+    chars: Vec<char>,
+    index: usize,
+    position: Position,
+}
+
+impl JsonParser {
+
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.index).copied()
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.index += 1;
+        if c == '\n' {
+            self.position = Position { line: self.position.line + 1, character: 0 };
+        } else {
+            self.position.character += 1;
+        }
+        Some(c)
+    }
+
+    fn skip_whitespace(&mut self) {
+        while matches!(self.peek(), Some(c) if c.is_whitespace()) {
+            self.bump();
+        }
+    }
+
+    fn error<T>(&self, message: &str) -> Result<T, (String, Range)> {
+        Err((message.to_string(), Range::new(self.position, self.position)))
+    }
+
+    fn expect(&mut self, c: char) -> Result<(), (String, Range)> {
+        if self.peek() == Some(c) {
+            self.bump();
+            Ok(())
+        } else {
+            self.error(&format!("Expected '{c}'."))
+        }
+    }
+
+    fn literal(&mut self, word: &str) -> Result<(), (String, Range)> {
+        for c in word.chars() {
+            self.expect(c)?;
+        }
+        Ok(())
+    }
+
+    fn string(&mut self) -> Result<String, (String, Range)> {
+        self.expect('"')?;
+        let mut result = String::new();
+        loop {
+            match self.bump() {
+                None => return self.error("Unterminated string."),
+                Some('"') => return Ok(result),
+                Some('\\') => match self.bump() {
+                    Some('n') => result.push('\n'),
+                    Some('t') => result.push('\t'),
+                    Some('r') => result.push('\r'),
+                    Some('b') => result.push('\u{8}'),
+                    Some('f') => result.push('\u{c}'),
+                    Some('u') => {
+                        let hex: String = (0..4).filter_map(|_| self.bump()).collect();
+                        match u32::from_str_radix(&hex, 16).ok().and_then(char::from_u32) {
+                            Some(c) => result.push(c),
+                            None => return self.error(&format!("Invalid unicode escape: \\u{hex}")),
+                        }
+                    }
+                    Some(c) => result.push(c),
+                    None => return self.error("Unterminated string."),
+                },
+                Some(c) => result.push(c),
+            }
+        }
+    }
+
+    fn value(&mut self) -> Result<JsonNode, (String, Range)> {
+        self.skip_whitespace();
+        let start = self.position;
+        let value = match self.peek() {
+            Some('{') => {
+                self.bump();
+                let mut fields = vec![];
+                self.skip_whitespace();
+                if self.peek() == Some('}') {
+                    self.bump();
+                } else {
+                    loop {
+                        self.skip_whitespace();
+                        let key = self.string()?;
+                        self.skip_whitespace();
+                        self.expect(':')?;
+                        let value = self.value()?;
+                        fields.push((key, value));
+                        self.skip_whitespace();
+                        match self.bump() {
+                            Some(',') => continue,
+                            Some('}') => break,
+                            _ => return self.error("Expected ',' or '}'."),
+                        }
+                    }
+                }
+                JsonValue::Object(fields)
+            }
+            Some('[') => {
+                self.bump();
+                let mut items = vec![];
+                self.skip_whitespace();
+                if self.peek() == Some(']') {
+                    self.bump();
+                } else {
+                    loop {
+                        items.push(self.value()?);
+                        self.skip_whitespace();
+                        match self.bump() {
+                            Some(',') => continue,
+                            Some(']') => break,
+                            _ => return self.error("Expected ',' or ']'."),
+                        }
+                    }
+                }
+                JsonValue::Array(items)
+            }
+            Some('"') => JsonValue::String(self.string()?),
+            Some('t') => { self.literal("true")?; JsonValue::Bool(true) }
+            Some('f') => { self.literal("false")?; JsonValue::Bool(false) }
+            Some('n') => { self.literal("null")?; JsonValue::Null }
+            Some(c) if c == '-' || c.is_ascii_digit() => {
+                let mut number = String::new();
+                while let Some(c) = self.peek().filter(|c| c.is_ascii_digit() || "-+.eE".contains(*c)) {
+                    number.push(c);
+                    self.bump();
+                }
+                JsonValue::Number(number)
+            }
+            _ => return self.error("Expected a JSON value."),
+        };
+        Ok(JsonNode { value, range: Range::new(start, self.position) })
+    }
+}
+
+/// Parses a JSON document, keeping the range of every value.
+pub fn parse_json(source: &str) -> Result<JsonNode, (String, Range)> {
+    let mut parser = JsonParser { chars: source.chars().collect(), index: 0, position: Position::default() };
+    let root = parser.value()?;
+    parser.skip_whitespace();
+    if parser.peek().is_some() {
+        return parser.error("Unexpected content after the end of the contract.")
+    }
+    Ok(root)
+}
+
+/// A merkleized case in the document, resolved from the continuation map.
+#[derive(Debug)]
+pub struct MerkleizedCase {
+    pub hash: String,
+    /// The range of the case and of the hash in the JSON document.
+    pub case_range: Range,
+    pub hash_range: Range,
+    /// The character span of the continuation in the DSL translation.
+    pub dsl_span: (u32, u32),
+}
+
+#[derive(Debug)]
+pub struct JsonDocument {
+    /// The contract translated to the Marlowe DSL.
+    pub dsl: String,
+    /// Character spans of the DSL text and the range of the JSON node they came from.
+    spans: Vec<(u32, u32, Range)>,
+    root_range: Range,
+    pub merkleized_cases: Vec<MerkleizedCase>,
+}
+
+type Error = (String, Range);
+
+/// An argument of a DSL constructor, and the function that prints it.
+type Argument<'a, 'm> = (&'a JsonNode, fn(&mut Printer<'m>, &JsonNode) -> Result<(), Error>);
+
+// The DSL only has PK parties with a 32 byte hash, written in upper case
+fn pk_hash(node: &JsonNode) -> Option<String> {
+    match &node.value {
+        JsonValue::String(hash) if hash.len() == 64 && hash.chars().all(|c| c.is_ascii_hexdigit()) => Some(hash.to_uppercase()),
+        _ => None,
+    }
+}
+
+struct Printer<'a> {
+    dsl: String,
+    length: u32,
+    spans: Vec<(u32, u32, Range)>,
+    /// The root contract is not wrapped in parentheses.
+    at_root: bool,
+    continuations: Option<&'a HashMap<String, JsonNode>>,
+    /// While printing a continuation from the map: the range of its hash in the document.
+    origin: Option<Range>,
+    /// Hashes of the continuations being printed, to detect continuations that contain themselves.
+    expanding: Vec<String>,
+    merkleized_cases: Vec<MerkleizedCase>,
+}
+
+impl<'a> Printer<'a> {
+
+    fn text(&mut self, text: &str) {
+        self.dsl.push_str(text);
+        self.length += text.chars().count() as u32;
+    }
+
+    /// Prints a node and remembers where it ended up in the DSL.
+    fn node(&mut self, node: &JsonNode, f: impl FnOnce(&mut Self, &JsonNode) -> Result<(), Error>) -> Result<(), Error> {
+        let start = self.length;
+        f(self, node)?;
+        self.spans.push((start, self.length, self.origin.unwrap_or(node.range)));
+        Ok(())
+    }
+
+    fn string(&mut self, node: &JsonNode) -> Result<(), Error> {
+        match &node.value {
+            JsonValue::String(s) if !s.contains('"') => self.node(node, |p, _| { p.text(&format!("\"{s}\"")); Ok(()) }),
+            JsonValue::String(_) => Err((String::from("Strings in Marlowe contracts can not contain quotes."), node.range)),
+            _ => Err((String::from("Expected a string."), node.range)),
+        }
+    }
+
+    fn pk_hash(&mut self, node: &JsonNode) -> Result<(), Error> {
+        let hash = pk_hash(node).ok_or_else(|| (String::from("Expected a hash of 64 hexadecimal digits."), node.range))?;
+        self.node(node, |p, _| { p.text(&format!("\"{hash}\"")); Ok(()) })
+    }
+
+    fn integer(&mut self, node: &JsonNode) -> Result<(), Error> {
+        match &node.value {
+            JsonValue::Number(n) if n.trim_start_matches('-').chars().all(|c| c.is_ascii_digit()) && !n.is_empty() =>
+                self.node(node, |p, _| { p.text(n); Ok(()) }),
+            _ => Err((String::from("Expected an integer."), node.range)),
+        }
+    }
+
+    /// Prints "(Name a b c)" where each argument is printed by its own function.
+    fn call(&mut self, node: &JsonNode, name: &str, args: Vec<Argument<'_, 'a>>) -> Result<(), Error> {
+        self.call_wrapped(node, name, args, true)
+    }
+
+    fn call_wrapped(&mut self, node: &JsonNode, name: &str, args: Vec<Argument<'_, 'a>>, wrap: bool) -> Result<(), Error> {
+        self.node(node, |p, _| {
+            if wrap { p.text("(") }
+            p.text(name);
+            for (arg, f) in args {
+                p.text(" ");
+                f(p, arg)?;
+            }
+            if wrap { p.text(")") }
+            Ok(())
+        })
+    }
+
+    fn contract(&mut self, node: &JsonNode) -> Result<(), Error> {
+        let wrap = !std::mem::replace(&mut self.at_root, false);
+        if matches!(&node.value, JsonValue::String(s) if s == "close") {
+            return self.node(node, |p, _| { p.text("Close"); Ok(()) })
+        }
+        if let Some(cases) = node.get("when") {
+            let items = match &cases.value {
+                JsonValue::Array(items) => items,
+                _ => return Err((String::from("Expected a list of cases."), cases.range)),
+            };
+            let timeout = node.field("timeout")?;
+            let continuation = node.field("timeout_continuation")?;
+            return self.node(node, |p, _| {
+                p.text(if wrap { "(When [" } else { "When [" });
+                p.node(cases, |p, _| {
+                    for (i, case) in items.iter().enumerate() {
+                        if i > 0 { p.text(", ") }
+                        p.case(case)?;
+                    }
+                    Ok(())
+                })?;
+                p.text("] ");
+                p.timeout(timeout)?;
+                p.text(" ");
+                p.contract(continuation)?;
+                if wrap { p.text(")") }
+                Ok(())
+            })
+        }
+        if node.has("if") {
+            self.call_wrapped(node, "If", vec![(node.field("if")?, Self::observation), (node.field("then")?, Self::contract), (node.field("else")?, Self::contract)], wrap)
+        } else if node.has("let") {
+            self.call_wrapped(node, "Let", vec![(node.field("let")?, Self::string), (node.field("be")?, Self::value), (node.field("then")?, Self::contract)], wrap)
+        } else if node.has("assert") {
+            self.call_wrapped(node, "Assert", vec![(node.field("assert")?, Self::observation), (node.field("then")?, Self::contract)], wrap)
+        } else if node.has("pay") {
+            self.call_wrapped(node, "Pay", vec![
+                (node.field("from_account")?, Self::party),
+                (node.field("to")?, Self::payee),
+                (node.field("token")?, Self::token),
+                (node.field("pay")?, Self::value),
+                (node.field("then")?, Self::contract),
+            ], wrap)
+        } else {
+            Err((String::from("Expected a contract."), node.range))
+        }
+    }
+
+    fn case(&mut self, node: &JsonNode) -> Result<(), Error> {
+        if let Some(hash_node) = node.get("merkleized_then") {
+            return self.merkleized_case(node, hash_node)
+        }
+        self.call(node, "Case", vec![(node.field("case")?, Self::action), (node.field("then")?, Self::contract)])
+    }
+
+    fn merkleized_case(&mut self, node: &JsonNode, hash_node: &JsonNode) -> Result<(), Error> {
+        let hash = match &hash_node.value {
+            JsonValue::String(hash) => hash.clone(),
+            _ => return Err((String::from("Expected the hash of a continuation."), hash_node.range)),
+        };
+        let origin = self.origin.unwrap_or(hash_node.range);
+        let continuation = match self.continuations {
+            None => return Err((String::from("Merkleized cases are resolved from a continuation map next to the contract, but there is none."), origin)),
+            Some(map) => map.get(&hash).ok_or_else(|| (format!("The continuation {hash} is not in the continuation map."), origin))?,
+        };
+        if self.expanding.contains(&hash) {
+            return Err((format!("The continuation {hash} contains itself."), origin))
+        }
+        let action = node.field("case")?;
+        self.node(node, |p, _| {
+            p.text("(Case ");
+            p.action(action)?;
+            p.text(" ");
+            let start = p.length;
+            let outer = p.origin.replace(origin);
+            p.expanding.push(hash.clone());
+            let result = p.contract(continuation);
+            p.expanding.pop();
+            p.origin = outer;
+            if outer.is_none() {
+                // Problems in the map are reported on the hash that refers to them.
+                result.map_err(|(message, _)| (format!("In continuation {hash}: {message}"), origin))?;
+                p.merkleized_cases.push(MerkleizedCase { hash, case_range: node.range, hash_range: origin, dsl_span: (start, p.length) });
+            } else {
+                result?;
+            }
+            p.text(")");
+            Ok(())
+        })
+    }
+
+    fn timeout(&mut self, node: &JsonNode) -> Result<(), Error> {
+        match node.get("time_param") {
+            Some(name) => self.call(node, "TimeParam", vec![(name, Self::string)]),
+            None => self.integer(node),
+        }
+    }
+
+    fn party(&mut self, node: &JsonNode) -> Result<(), Error> {
+        if let Some(role) = node.get("role_token") {
+            self.call(node, "Role", vec![(role, Self::string)])
+        } else if let Some(pk) = node.get("pk_hash").filter(|pk| pk_hash(pk).is_some()) {
+            self.call(node, "PK", vec![(pk, Self::pk_hash)])
+        } else if node.has("pk_hash") {
+            Err((String::from("Unsupported party: the Marlowe DSL only has PK parties with a hash of 64 hexadecimal digits."), node.range))
+        } else if node.has("address") {
+            Err((String::from("Unsupported party: addresses can not be written in the Marlowe DSL, use a role or a PK."), node.range))
+        } else {
+            Err((String::from("Expected a party."), node.range))
+        }
+    }
+
+    fn payee(&mut self, node: &JsonNode) -> Result<(), Error> {
+        match node.get("account") {
+            Some(account) => self.call(node, "Account", vec![(account, Self::party)]),
+            None => self.call(node, "Party", vec![(node.field("party")?, Self::party)]),
+        }
+    }
+
+    fn token(&mut self, node: &JsonNode) -> Result<(), Error> {
+        self.call(node, "Token", vec![(node.field("currency_symbol")?, Self::string), (node.field("token_name")?, Self::string)])
+    }
+
+    fn choice_id(&mut self, node: &JsonNode) -> Result<(), Error> {
+        self.call(node, "ChoiceId", vec![(node.field("choice_name")?, Self::string), (node.field("choice_owner")?, Self::party)])
+    }
+
+    fn bound(&mut self, node: &JsonNode) -> Result<(), Error> {
+        self.call(node, "Bound", vec![(node.field("from")?, Self::integer), (node.field("to")?, Self::integer)])
+    }
+
+    fn action(&mut self, node: &JsonNode) -> Result<(), Error> {
+        if node.has("deposits") {
+            self.call(node, "Deposit", vec![
+                (node.field("into_account")?, Self::party),
+                (node.field("party")?, Self::party),
+                (node.field("of_token")?, Self::token),
+                (node.field("deposits")?, Self::value),
+            ])
+        } else if let Some(choice) = node.get("for_choice") {
+            let bounds = node.field("choose_between")?;
+            let items = match &bounds.value {
+                JsonValue::Array(items) => items,
+                _ => return Err((String::from("Expected a list of bounds."), bounds.range)),
+            };
+            self.node(node, |p, _| {
+                p.text("(Choice ");
+                p.choice_id(choice)?;
+                p.text(" [");
+                p.node(bounds, |p, _| {
+                    for (i, bound) in items.iter().enumerate() {
+                        if i > 0 { p.text(", ") }
+                        p.bound(bound)?;
+                    }
+                    Ok(())
+                })?;
+                p.text("])");
+                Ok(())
+            })
+        } else if let Some(observation) = node.get("notify_if") {
+            self.call(node, "Notify", vec![(observation, Self::observation)])
+        } else {
+            Err((String::from("Expected an action."), node.range))
+        }
+    }
+
+    fn value(&mut self, node: &JsonNode) -> Result<(), Error> {
+        match &node.value {
+            JsonValue::Number(_) => return self.node(node, |p, n| { p.text("(Constant "); p.integer(n)?; p.text(")"); Ok(()) }),
+            JsonValue::String(s) if s == "time_interval_start" => return self.node(node, |p, _| { p.text("TimeIntervalStart"); Ok(()) }),
+            JsonValue::String(s) if s == "time_interval_end" => return self.node(node, |p, _| { p.text("TimeIntervalEnd"); Ok(()) }),
+            _ => {}
+        }
+        if node.has("amount_of_token") {
+            self.call(node, "AvailableMoney", vec![(node.field("in_account")?, Self::party), (node.field("amount_of_token")?, Self::token)])
+        } else if let Some(name) = node.get("constant_param") {
+            self.call(node, "ConstantParam", vec![(name, Self::string)])
+        } else if let Some(name) = node.get("use_value") {
+            self.call(node, "UseValue", vec![(name, Self::string)])
+        } else if let Some(a) = node.get("negate") {
+            self.call(node, "NegValue", vec![(a, Self::value)])
+        } else if let Some(a) = node.get("add") {
+            self.call(node, "AddValue", vec![(a, Self::value), (node.field("and")?, Self::value)])
+        } else if let Some(b) = node.get("minus") {
+            self.call(node, "SubValue", vec![(node.field("value")?, Self::value), (b, Self::value)])
+        } else if let Some(a) = node.get("multiply") {
+            self.call(node, "MulValue", vec![(a, Self::value), (node.field("times")?, Self::value)])
+        } else if let Some(a) = node.get("divide") {
+            self.call(node, "DivValue", vec![(a, Self::value), (node.field("by")?, Self::value)])
+        } else if let Some(c) = node.get("value_of_choice") {
+            self.call(node, "ChoiceValue", vec![(c, Self::choice_id)])
+        } else if let Some(o) = node.get("if") {
+            self.call(node, "Cond", vec![(o, Self::observation), (node.field("then")?, Self::value), (node.field("else")?, Self::value)])
+        } else {
+            Err((String::from("Expected a value."), node.range))
+        }
+    }
+
+    fn observation(&mut self, node: &JsonNode) -> Result<(), Error> {
+        match &node.value {
+            JsonValue::Bool(true) => return self.node(node, |p, _| { p.text("TrueObs"); Ok(()) }),
+            JsonValue::Bool(false) => return self.node(node, |p, _| { p.text("FalseObs"); Ok(()) }),
+            _ => {}
+        }
+        if let Some(a) = node.get("both") {
+            self.call(node, "AndObs", vec![(a, Self::observation), (node.field("and")?, Self::observation)])
+        } else if let Some(a) = node.get("either") {
+            self.call(node, "OrObs", vec![(a, Self::observation), (node.field("or")?, Self::observation)])
+        } else if let Some(a) = node.get("not") {
+            self.call(node, "NotObs", vec![(a, Self::observation)])
+        } else if let Some(c) = node.get("chose_something_for") {
+            self.call(node, "ChoseSomething", vec![(c, Self::choice_id)])
+        } else {
+            let comparison = [("ge_than", "ValueGE"), ("gt_than", "ValueGT"), ("lt_than", "ValueLT"), ("le_than", "ValueLE"), ("equal_to", "ValueEQ")]
+                .into_iter().find(|(key, _)| node.has(key));
+            match comparison {
+                Some((key, name)) => self.call(node, name, vec![(node.field("value")?, Self::value), (node.field(key)?, Self::value)]),
+                None => Err((String::from("Expected an observation."), node.range)),
+            }
+        }
+    }
+}
+
+impl JsonDocument {
+
+    pub fn parse(source: &str) -> Result<JsonDocument, (String, Range)> {
+        JsonDocument::parse_with_continuations(source, None)
+    }
+
+    /// Parses a document whose merkleized cases are resolved from the given continuations.
+    pub fn parse_with_continuations(source: &str, continuations: Option<&HashMap<String, JsonNode>>) -> Result<JsonDocument, (String, Range)> {
+        let root = parse_json(source)?;
+        let mut printer = Printer {
+            dsl: String::new(),
+            length: 0,
+            spans: vec![],
+            at_root: true,
+            continuations,
+            origin: None,
+            expanding: vec![],
+            merkleized_cases: vec![],
+        };
+        printer.contract(&root)?;
+        Ok(JsonDocument { dsl: printer.dsl, spans: printer.spans, root_range: root.range, merkleized_cases: printer.merkleized_cases })
+    }
+
+    /// The DSL of a resolved merkleized continuation.
+    pub fn continuation_dsl(&self, case: &MerkleizedCase) -> String {
+        let (start, end) = case.dsl_span;
+        self.dsl.chars().skip(start as usize).take((end - start) as usize).collect()
+    }
+
+    /// Maps a range in the generated DSL to the range of the smallest JSON node that produced it.
+    pub fn to_json_range(&self, dsl_range: Range) -> Range {
+        let (start, end) = (dsl_range.start.character, dsl_range.end.character.max(dsl_range.start.character));
+        self.spans.iter()
+            .filter(|(s, e, _)| *s <= start && end <= *e)
+            .min_by_key(|(s, e, _)| e - s)
+            .map(|(_, _, range)| *range)
+            .unwrap_or(self.root_range)
+    }
+
+    /// Maps a position in the JSON document to a position inside of the DSL produced by the
+    /// smallest JSON node that contains it.
+    pub fn to_dsl_position(&self, position: Position) -> Option<Position> {
+        let contains = |r: &Range| r.start <= position && position < r.end;
+        self.spans.iter()
+            .filter(|(_, _, range)| contains(range))
+            .min_by_key(|(s, e, _)| e - s)
+            .map(|(s, e, _)| Position { line: 0, character: if e - s > 1 { s + 1 } else { *s } })
+    }
+}
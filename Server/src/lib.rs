@@ -0,0 +1,1383 @@
+//! Language support for [Marlowe](https://marlowe.iohk.io) contracts: parsing,
+//! validation, formatting and the rest of what the editor shows.
+//!
+//! The language server and the `marlowe_lsp` command line are built on this library,
+//! so a [`Document`] gets exactly the diagnostics that the editor shows for it:
+//!
+//! ```
+//! use marlowe_lsp::{Config, Document};
+//! use marlowe_lsp::lsp_types::Url;
+//!
+//! let (config, _problems) = Config::load(None, None, Some(std::path::Path::new(".")));
+//! let uri = Url::parse("file:///contracts/swap.marlowe").unwrap();
+//! let document = Document::parse_with_config(uri, "When [] 100 Close", &config);
+//! for diagnostic in document.diagnostics() {
+//!     println!("{:?} {}", diagnostic.code, diagnostic.message);
+//! }
+//! ```
+//!
+//! The parsers can also be used on their own through [`LSParse`], which is implemented
+//! for the rules of the Marlowe grammar (`marlowe_lang::parsing::Rule`) and of the
+//! S-expression grammar ([`sex::Rule`]).
+
+// The language server (server.rs) and the command line (cli.rs) are only built for
+// native targets. The WebAssembly build for browser editors (wasm.rs) has no tokio, no
+// network and no file system.
+
+// Some helpers and imports are only used by the server
+#![cfg_attr(target_arch = "wasm32", allow(dead_code, unused_imports))]
+
+mod blockly;
+#[cfg(not(target_arch = "wasm32"))]
+mod cli;
+mod codegen;
+mod codespan_lsp_local;
+#[cfg(not(target_arch = "wasm32"))]
+mod commands;
+mod config;
+mod contract_model;
+mod core_json;
+mod datum;
+mod diagram;
+mod document;
+mod format;
+mod json_document;
+mod lints;
+mod merkle;
+mod outline;
+mod params;
+mod plutus_data;
+mod report;
+mod rules;
+mod explorer;
+mod interpreter;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod server;
+mod simulation;
+mod size_estimate;
+mod suppression;
+mod term_sheet;
+#[cfg(not(target_arch = "wasm32"))]
+mod transport;
+#[cfg(target_arch = "wasm32")]
+mod wasm;
+
+pub use lsp_types;
+pub use config::{Config, RuleLevel};
+pub use document::Document;
+pub use explorer::ExplorationLimits;
+pub use format::FormatOptions;
+pub use lints::{CustomRule, Lint, Node as LintNode};
+pub use rules::{LintRule, RULES};
+pub use size_estimate::SizeLimits;
+
+use codespan::FileId;
+use codespan_lsp_local::{range_to_byte_span};
+use marlowe_lang::{parsing::Rule};
+use regex::{Regex};
+use std::{collections::HashMap, hash::Hash};
+use serde_json::Value;
+use lsp_types::*;
+use line_col::LineColLookup;
+use lsp_types::{SemanticToken, Range};
+use pest_derive::Parser;
+
+pub mod sex {
+    use super::*;
+    #[derive(Parser)]
+    #[grammar = "../sex.grammars"]
+    pub struct SexParser;
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+#[derive(Debug)]
+struct MyLSPServer {
+    client: tower_lsp::Client,
+    state: std::sync::Mutex<State>
+}
+
+#[derive(Debug)]
+struct State {
+    sources: HashMap<Url, FileId>,
+    sexpression_asts: HashMap<Url, (Vec<(Range,sex::Rule,SemanticToken)>,ContractValidationResult)>,
+    marlowe_asts:     HashMap<Url, (Vec<(Range,marlowe_lang::parsing::Rule,SemanticToken)>,ContractValidationResult)>,
+    files: codespan::Files<String>,
+    // Files can not be removed from codespan, closed documents keep their (emptied) file
+    // and get it back when they are opened again
+    closed_files: HashMap<Url, FileId>,
+    marlowe_parser_error: Option<(String,Range)>,
+    sexpression_parser_error: Option<(String,Range)>,
+    simulations: HashMap<Url, simulation::SimulationSession>,
+    // With the analysis_key of what they were computed from, see update_asts
+    path_analysis: HashMap<Url, (u64, explorer::ExplorationResult)>,
+    // See config.rs, read again when the editor settings or marlowe-lsp.toml change
+    config: config::Config,
+    initialization_options: Option<Value>,
+    editor_settings: Option<Value>,
+    // Whether the client answers workspace/configuration requests
+    pull_configuration: bool,
+    workspace_root: Option<std::path::PathBuf>,
+    json_documents: HashMap<Url, json_document::JsonDocument>,
+    // Why a JSON document could not be read, by the uri of the document
+    json_parser_errors: HashMap<Url,(String,Range)>,
+    // Parameter files of the open contracts, by the uri of the contract
+    param_files: HashMap<Url, params::ParamFile>,
+    // With the analysis_key of what they were computed from, like path_analysis
+    size_estimates: HashMap<Url, (u64, size_estimate::SizeEstimate)>,
+    // Continuation maps of the open merkleized contracts, by the uri of the contract
+    continuation_maps: HashMap<Url, merkle::ContinuationMap>,
+    // Suppression comments of the open DSL documents
+    suppressions: HashMap<Url, suppression::Suppressions>
+}
+
+impl State {
+    fn new() -> State {
+        State {
+            files: codespan::Files::new(),
+            closed_files: HashMap::new(),
+            sources: HashMap::new(),
+            sexpression_asts: HashMap::new(),
+            marlowe_asts: HashMap::new(),
+            marlowe_parser_error: None,
+            sexpression_parser_error: None,
+            simulations: HashMap::new(),
+            path_analysis: HashMap::new(),
+            config: config::Config::default(),
+            initialization_options: None,
+            editor_settings: None,
+            pull_configuration: false,
+            workspace_root: None,
+            json_documents: HashMap::new(),
+            json_parser_errors: HashMap::new(),
+            param_files: HashMap::new(),
+            size_estimates: HashMap::new(),
+            continuation_maps: HashMap::new(),
+            suppressions: HashMap::new()
+        }
+    }
+}
+
+// TODO:
+
+// Add support for get_diagnostics function to return 
+// suggestions for auto_resolving things like when a value
+// can be simplified from MulVal(2,2) into just Constant 4..
+
+// Add support for ?party/?payee?/?contract/ etc.. holes
+// such that we can autosuggest items to use,
+// so that we can handle copy-pasted contracts from playground.
+
+/// The hover text at a position: what the construct there means, and its estimated
+/// on-chain size when it is a When.
+fn get_hover(state: &State, uri: &Url, position_in_document: Position) -> Option<Hover> {
+    // For JSON documents we look at the same spot in the DSL translation
+    if let Some(description) = state.json_documents.get(uri).and_then(|document| merkle::describe_at(document, state.continuation_maps.get(uri), position_in_document)) {
+        return Some(Hover {
+            contents: HoverContents::Markup(MarkupContent { kind: MarkupKind::PlainText, value: description }),
+            range: None
+        })
+    }
+    let position = match state.json_documents.get(uri) {
+        Some(document) => document.to_dsl_position(position_in_document)?,
+        None => position_in_document
+    };
+
+    match state.marlowe_asts.get(uri) {
+        Some(token_list) => {
+            let closest = marlowe_lang::parsing::Rule::get_token_info_at_position(
+                token_list.0.to_vec(),
+                position,
+                |r| match r {
+                    marlowe_lang::parsing::Rule::Notify |
+                    marlowe_lang::parsing::Rule::Choice |
+                    marlowe_lang::parsing::Rule::Deposit => String::from("Contracts in Marlowe run on a blockchain, but need to interact with the off-chain world. The parties to the contract, whom we also call the participants, can engage in various actions: they can be asked to deposit money, or to make a choice between various alternatives. A notification of an external value (also called an oracle value), such as the current price of a particular commodity, is the other possible form of input."),
+                    marlowe_lang::parsing::Rule::Case => String::from("A When contract contains a collection of cases. Each case has the form Case action next where action is an Action and next a continuation (another contract). When a particular action happens, the state is updated accordingly and the contract will continue as the corresponding continuation next."),
+                    marlowe_lang::parsing::Rule::Bound => String::from("A choice is made for a particular id with a list of bounds on the values that are acceptable. For example, [Bound 0 0, Bound 3 5] offers the choice of one of 0, 3, 4 and 5."),
+                    marlowe_lang::parsing::Rule::Party |
+                    marlowe_lang::parsing::Rule::PK |
+                    marlowe_lang::parsing::Rule::Role => String::from("A Party is represented as either a public key hash or a role name. In order to progress a Marlowe contract, a party must provide an evidence. For PK party that would be a valid signature of a transaction signed by a private key of a public key that hashes to party’s PubKeyHash, similarly to Bitcoin’s Pay to Public Key Hash mechanism. For a Role party the evidence is spending a role token within the same transaction, usually to the same owner. So, Role parties will look like (Role \"alice\"), (Role \"bob\") and so on."),
+                    marlowe_lang::parsing::Rule::ChoiceId => String::from("Choices – of integers – are identified by ChoiceId which combines a name for the choice with the Party who had made the choice"),
+                    marlowe_lang::parsing::Rule::TimeIntervalStart |
+                    marlowe_lang::parsing::Rule::ConstantParam |
+                    marlowe_lang::parsing::Rule::Constant |
+                    marlowe_lang::parsing::Rule::MulValue |
+                    marlowe_lang::parsing::Rule::DivValue |
+                    marlowe_lang::parsing::Rule::SubValue |
+                    marlowe_lang::parsing::Rule::TimeIntervalEnd => String::from("A Value encompasses Ada, fungible tokens (think currencies), non-fungible tokens (a custom token that is not interchangeable with other tokens), and more exotic mixed cases."),
+                    marlowe_lang::parsing::Rule::TimeInterval |
+                    marlowe_lang::parsing::Rule::TimeParam |
+                    marlowe_lang::parsing::Rule::TimeConstant => String::from("Timeout is the slot number after which the When will no longer accept any new events: Case branches will become unusable, and the contract will continue as specified by the timeout continuation. Timeouts accept templates, this means that instead of writing a specific slot number it is possible to fill Timeouts by using a template parameter that can be filled just before deploying or simulating the contract, for example: TimeParam \"maturityDate\""),
+                    marlowe_lang::parsing::Rule::Close |
+                    marlowe_lang::parsing::Rule::Pay |
+                    marlowe_lang::parsing::Rule::Let |
+                    marlowe_lang::parsing::Rule::If |
+                    marlowe_lang::parsing::Rule::Assert |
+                    marlowe_lang::parsing::Rule::When => String::from("Marlowe has six ways of building contracts. Five of these – Pay, Let, If, When and Assert – build a complex contract from simpler contracts, and the sixth, Close, is a simple contract. At each step of execution, as well as returning a new state and continuation contract, it is possible that effects – payments – and warnings can be generated too."),
+                    marlowe_lang::parsing::Rule::Token => 
+                        String::from("A Marlowe Account holds amounts of multiple currencies and/or fungible and non-fungible tokens. A concrete amount is indexed by a Token, which is a pair of CurrencySymbol and TokenName."),
+                    _ => format!("{r:?}")
+                }
+            );
+            match closest {
+                Some(v) => {
+                    let v = match state.size_estimates.get(uri).and_then(|(_,sizes)| size_estimate::when_at(sizes, position)) {
+                        Some(when) => format!("{v}\n\nEstimated on-chain size: {}", size_estimate::summary(when)),
+                        None => v
+                    };
+                    Some(
+                        Hover { 
+                            contents: HoverContents::Markup(
+                                    MarkupContent {
+                                        kind: MarkupKind::PlainText,
+                                        value: v
+                                    }
+                            ),
+                            range: Some(
+                                Range::new(
+                                    position_in_document,
+                                    position_in_document,
+                                )
+                            )
+                        }
+                    )
+                },
+                None => None,
+            }
+        },
+        None => None,
+    }
+}
+
+/// The outline of a document, None if it can not be parsed.
+fn get_document_symbols(state: &State, uri: &Url) -> Option<Vec<DocumentSymbol>> {
+    let parsed = contract_model::parse_contract(&get_contract_source(state, uri)?).ok()?;
+    let mut symbols = outline::document_symbols(&parsed);
+    if let Some(document) = state.json_documents.get(uri) {
+        outline::map_ranges(&mut symbols, &|range| document.to_json_range(range));
+        outline::mark_merkleized(&mut symbols, &document.merkleized_cases);
+    }
+    Some(symbols)
+}
+
+/// The legend of the semantic tokens of a document, see [`Document::semantic_tokens`].
+pub fn semantic_token_legend() -> SemanticTokensLegend {
+    SemanticTokensLegend { 
+        token_types: vec![
+            SemanticTokenType::VARIABLE,
+            SemanticTokenType::STRING,
+            SemanticTokenType::NUMBER ,
+            SemanticTokenType::STRUCT,
+            SemanticTokenType::COMMENT
+        ], 
+        token_modifiers: vec![
+            SemanticTokenModifier::STATIC
+        ]
+    }
+}
+
+fn get_semantic_tokens(state: &State, uri: &Url) -> Option<SemanticTokens> {
+    // JSON documents are highlighted by the editor itself
+    if state.json_documents.contains_key(uri) {
+        return None
+    }
+    state.sexpression_asts.get(uri).map(|token_list| SemanticTokens {
+        result_id: Some("FULL".into()),
+        data: token_list.0.iter().map(|x|x.2).collect()
+    })
+}
+
+/// A state with a single open document, for looking at documents outside of an editor
+/// session (the command line and the WebAssembly build).
+fn open_document(uri: &Url, text: &str, config: &config::Config) -> State {
+    let mut state = State::new();
+    state.config = config.clone();
+    let language_id = if json_document::is_json_document(uri) { "MarloweJSON" } else { "Marlowe" };
+    get_or_insert_document(&mut state, &TextDocumentItem { uri: uri.clone(), language_id: language_id.to_string(), version: 0, text: text.to_string() });
+    state
+}
+
+fn get_or_insert_document(state: &mut State, document: &TextDocumentItem) -> FileId {
+    if let Some(id) = state.sources.get(&document.uri) {
+        *id
+    } else {
+
+        let id = match state.closed_files.remove(&document.uri) {
+            Some(id) => {
+                state.files.update(id, document.text.clone());
+                id
+            }
+            None => state.files.add(document.uri.to_string(), document.text.clone()),
+        };
+
+        state.sources.insert(document.uri.clone(), id);
+        load_param_file(state, &document.uri);
+        load_continuation_map(state, &document.uri);
+        
+        update_asts(
+            document.text.clone(), 
+            state, 
+            document.uri.clone()
+        );
+        id
+
+    }
+}
+
+fn get_source(state: &State, url: &Url) -> Option<String> {
+    let id = *state.sources.get(url)?;
+    Some(state.files.source(id).to_owned())
+}
+
+// The contract in a document as DSL, which for JSON documents is their translation.
+fn get_contract_source(state: &State, url: &Url) -> Option<String> {
+    match state.json_documents.get(url) {
+        Some(document) => Some(document.dsl.clone()),
+        None => get_source(state, url).map(|source| suppression::without_comments(&source))
+    }
+}
+
+// Name of the file in a uri without any of its extensions: file:///a/b.marlowe.json -> b
+fn file_stem(uri: &Url) -> String {
+    let name = uri.path_segments().and_then(|mut s| s.next_back()).unwrap_or("contract");
+    match name.split('.').next() {
+        Some(stem) if !stem.is_empty() => stem.to_string(),
+        _ => String::from("contract"),
+    }
+}
+
+// Reads a file next to a document, like its parameter file. There is no file system in browsers.
+#[cfg(not(target_arch = "wasm32"))]
+fn read_file(uri: &Url) -> Option<String> {
+    std::fs::read_to_string(uri.to_file_path().ok()?).ok()
+}
+
+#[cfg(target_arch = "wasm32")]
+fn read_file(_: &Url) -> Option<String> {
+    None
+}
+
+fn load_param_file(state: &mut State, url: &Url) {
+    match params::load(url) {
+        Some(file) => { state.param_files.insert(url.clone(), file); },
+        None => { state.param_files.remove(url); }
+    }
+}
+
+fn load_continuation_map(state: &mut State, url: &Url) {
+    match merkle::load(url).filter(|_| json_document::is_json_document(url)) {
+        Some(map) => { state.continuation_maps.insert(url.clone(), map); },
+        None => { state.continuation_maps.remove(url); }
+    }
+}
+
+// The validation settings for a document: TimeParam values from its parameter file
+// take precedence over the ones from the client.
+fn get_validation_settings(state: &State, url: &Url) -> ValidationSettings {
+    let mut settings = state.config.analysis.clone();
+    if let Some(file) = state.param_files.get(url) {
+        settings.time_params.extend(file.values(term_sheet::ParameterKind::TimeParam));
+    }
+    settings
+}
+
+// The parameters used in a contract, as found by the validator.
+fn get_used_params(state: &State, url: &Url) -> Vec<(String,term_sheet::ParameterKind,Range)> {
+    match state.marlowe_asts.get(url) {
+        Some((_,validation)) => params::used_params(&validation.facts),
+        None => vec![]
+    }
+}
+
+// Diagnostics for the parameter file of a contract, which are published for the parameter file itself.
+fn get_param_file_diagnostics(state: &mut State, url: &Url) -> Option<(Url,Vec<Diagnostic>)> {
+    let file = state.param_files.get(url)?;
+    let mut diagnostics = if state.marlowe_parser_error.is_some() {
+        // We don't know which parameters the contract uses, so only report problems in the file itself.
+        params::check(file, &[]).1.into_iter().filter(|d|d.severity == Some(DiagnosticSeverity::ERROR)).collect()
+    } else {
+        params::check(file, &get_used_params(state, url)).1
+    };
+    rules::describe(&mut diagnostics);
+    state.config.apply(&mut diagnostics);
+    Some((file.uri.clone(),diagnostics))
+}
+
+// None if the document is not open
+fn update_document(
+    state: &mut State,
+    url: &Url,
+    changes: Vec<TextDocumentContentChangeEvent>,
+) -> Option<FileId> {
+    let id = *state.sources.get(url)?;
+    let mut source = state.files.source(id).to_owned();
+    for change in changes {
+        if let (None, None) = (change.range, change.range_length) {
+            source = change.text;
+        } else if let Some(range) = change.range {
+            let span = range_to_byte_span(
+                &state.files, 
+                id, 
+                &range
+            ).unwrap_or_default();
+            let range = (span.start)..(span.end);
+            source.replace_range(range, &change.text);
+        }
+    }
+    state.files.update(id, source.clone());
+    update_asts(source,state,url.clone());
+    Some(id)
+}
+
+
+// uses marlowe token rule in combination with s expression to create final verdict 
+// on which semantictoken type to use for a specific range
+fn get_token_id(mar_vec:Vec<(Range, marlowe_lang::parsing::Rule, SemanticToken)>) -> impl Fn(sex::Rule,Range) -> u32 {
+    move |rule:sex::Rule,range:Range| {
+        let marlowe_match = 
+            mar_vec.iter().find(|x|x.0==range);
+        let default_func = |rule| match rule {
+            sex::Rule::string => 1,
+            sex::Rule::number => 2,
+            sex::Rule::ident => 0,
+            sex::Rule::comment => 4,
+            _ => 99
+        };
+        if let Some(x) = marlowe_match {
+            match x.1 {
+                marlowe_lang::parsing::Rule::Case => 3,
+                _ => default_func(rule)
+            }
+        } else { default_func(rule) }
+    }
+}
+
+fn update_asts(source:String,state:&mut State,url:Url)  {
+
+    // JSON documents are validated through their translation to the DSL,
+    // get_diagnostics maps everything back to the JSON document.
+    let source = if json_document::is_json_document(&url) {
+        let continuations = state.continuation_maps.get(&url).map(|map| &map.continuations);
+        match json_document::JsonDocument::parse_with_continuations(&source, continuations) {
+            Ok(document) => {
+                state.json_parser_errors.remove(&url);
+                let dsl = document.dsl.clone();
+                state.json_documents.insert(url.clone(), document);
+                dsl
+            },
+            Err(e) => {
+                state.json_parser_errors.insert(url.clone(), e);
+                state.json_documents.remove(&url);
+                state.path_analysis.remove(&url);
+                state.size_estimates.remove(&url);
+                state.marlowe_asts.insert(url.clone(),(vec![],ContractValidationResult::default()));
+                state.sexpression_asts.remove(&url);
+                return
+            }
+        }
+    } else {
+        let comments = suppression::comments(&source);
+        state.suppressions.insert(url.clone(), suppression::Suppressions::from_comments(&comments));
+        source
+    };
+    // marlowe_lang does not support comments, the S-expression parser still gets the source with comments
+    let contract_source = suppression::without_comments(&source);
+    
+    let settings = get_validation_settings(state, &url);
+    let marlowe_tokens = parse_marlowe(&contract_source, &settings);
+
+    let mar_vec = 
+        match &marlowe_tokens {
+            Ok(x) => x.0.to_vec(),
+            Err(_) => vec![],
+        };
+        
+    let sex_tokens = 
+        sex::Rule::lsp_parse(
+            source.clone(),  get_token_id(mar_vec), &settings
+        );
+
+    match marlowe_tokens {
+        Ok(tokens) => {
+            //println!("Marlowe parser succeeded");
+            state.marlowe_parser_error = None;
+            if state.marlowe_asts.contains_key(&url) {
+                *state.marlowe_asts.get_mut(&url).unwrap() = tokens;    
+            } else {
+                state.marlowe_asts.insert(url.clone(),tokens);    
+            }
+            match contract_model::parse_contract(&contract_source) {
+                Ok(parsed) => {
+                    // Most changes are edits of other documents or of the configuration, the analyses
+                    // only run again when the contract or their settings changed
+                    let key = analysis_key(&contract_source, &settings.time_params, settings.exploration_limits);
+                    if state.path_analysis.get(&url).map(|(k,_)| *k) != Some(key) {
+                        let result = explorer::explore(&parsed, &settings.time_params, settings.exploration_limits);
+                        state.path_analysis.insert(url.clone(), (key, result));
+                    }
+                    let key = analysis_key(&contract_source, &settings.time_params, settings.size_limits);
+                    if state.size_estimates.get(&url).map(|(k,_)| *k) != Some(key) {
+                        let sizes = size_estimate::estimate(&parsed, &settings.time_params, settings.size_limits);
+                        state.size_estimates.insert(url.clone(), (key, sizes));
+                    }
+                },
+                Err(_) => {
+                    state.path_analysis.remove(&url);
+                    state.size_estimates.remove(&url);
+                }
+            }
+            
+        },
+        Err((e,r)) => {
+            //println!("Marlowe parser failed.. error was: \n{e:#}");
+            state.marlowe_parser_error = Some((e,r));
+            state.path_analysis.remove(&url);
+            state.size_estimates.remove(&url);
+            if state.marlowe_asts.contains_key(&url) {
+                *state.marlowe_asts.get_mut(&url).unwrap() = (vec![],ContractValidationResult::default());    
+            } else {
+                state.marlowe_asts.insert(url.clone(),(vec![],ContractValidationResult::default()));    
+            }
+
+
+
+         }
+     };  
+
+    match sex_tokens {
+        Ok(tokens) => {
+            //println!("S-expression parser succeeded");
+            state.sexpression_parser_error = None;
+            if state.sexpression_asts.contains_key(&url) {
+                *state.sexpression_asts.get_mut(&url).unwrap() = tokens;    
+            } else {
+                state.sexpression_asts.insert(url.clone(),tokens);    
+            }
+            
+        },
+        Err((e,r)) => {
+            //println!("S-expression parser failed.. error was: \n{e:#}");
+            state.sexpression_parser_error = Some((e,r));
+            if state.sexpression_asts.contains_key(&url) {
+                *state.sexpression_asts.get_mut(&url).unwrap() = (vec![],ContractValidationResult::default()); 
+            } else {
+                state.sexpression_asts.insert(url.clone(),(vec![],ContractValidationResult::default()));    
+            }
+        }
+    }; 
+
+}
+
+// A hash of the inputs of an analysis of a contract: the contract without comments,
+// the TimeParam values and the limits of the analysis.
+fn analysis_key(contract_source: &str, time_params: &HashMap<String,i64>, limits: impl Hash) -> u64 {
+    let mut hasher = std::collections::hash_map::DefaultHasher::new();
+    contract_source.hash(&mut hasher);
+    let mut params: Vec<(&String,&i64)> = time_params.iter().collect();
+    params.sort();
+    params.hash(&mut hasher);
+    limits.hash(&mut hasher);
+    std::hash::Hasher::finish(&hasher)
+}
+
+type MarloweTokens = Vec<(Range,marlowe_lang::parsing::Rule,SemanticToken)>;
+
+// Runs the marlowe parser and the validator on a contract without comments.
+fn parse_marlowe(source:&str,settings:&ValidationSettings) -> std::result::Result<(MarloweTokens,ContractValidationResult),(String,Range)> {
+    let (contract,offset) = contract_model::skip_leading_whitespace(source);
+    let shift = |range,first_line| contract_model::shift_range(range, offset, first_line);
+    // we don't use the token types from this parser atm
+    match marlowe_lang::parsing::Rule::lsp_parse(contract.to_string(), |_rule,_range|{0}, settings) {
+        Ok((mut tokens,mut validation)) => {
+            for token in tokens.iter_mut() { token.0 = shift(token.0,1) }
+            for item in validation.items.iter_mut() { item.0 = shift(item.0,0) }
+            for fact in validation.facts.iter_mut() { fact.range = shift(fact.range,0) }
+            Ok((tokens,validation))
+        },
+        Err((e,range)) => Err((e,shift(range,0)))
+    }
+}
+
+fn get_diagnostics(state:&mut State,url:&Url) -> Vec<Diagnostic> {
+    let mut diagnostics = get_document_diagnostics(state, url);
+    rules::describe(&mut diagnostics);
+    if let Some(suppressions) = state.suppressions.get(url) {
+        suppressions.apply(&mut diagnostics);
+    }
+    state.config.apply(&mut diagnostics);
+    diagnostics
+}
+
+// Parses and validates an open document again, for when something it depends on changed.
+fn revalidate(state: &mut State, url: &Url, results: &mut Vec<(Url,Vec<Diagnostic>)>) {
+    if let Some(source) = get_source(state, url) {
+        update_asts(source, state, url.clone());
+    }
+    results.push((url.clone(),get_diagnostics(state,url)));
+    results.extend(get_param_file_diagnostics(state,url));
+}
+
+fn get_document_diagnostics(state:&mut State,url:&Url) -> Vec<Diagnostic> {
+
+    if !json_document::is_json_document(url) {
+        return get_contract_diagnostics(state, url)
+    }
+
+    if let Some((msg,range)) = state.json_parser_errors.get(url) {
+        let mut message = msg.to_string();
+        if let Some(merkle::ContinuationMap { uri: map_uri, problem: Some((problem,problem_range)), .. }) = state.continuation_maps.get(url) {
+            message = format!("{message}\nThe continuation map {map_uri} could not be read (line {}): {problem}", problem_range.start.line + 1);
+        }
+        return vec![Diagnostic { 
+            range: *range, 
+            code: Some(NumberOrString::String("ML000".to_string())), 
+            message,
+            ..Default::default()
+        }]
+    }
+
+    let mut diagnostics = get_contract_diagnostics(state, url);
+    if let Some(document) = state.json_documents.get(url) {
+        for d in diagnostics.iter_mut() {
+            d.range = document.to_json_range(d.range);
+            for info in d.related_information.iter_mut().flatten() {
+                if &info.location.uri == url {
+                    info.location.range = document.to_json_range(info.location.range);
+                }
+            }
+        }
+        diagnostics.extend(merkle::check_hashes(document));
+    }
+    diagnostics
+}
+
+fn get_contract_diagnostics(state:&mut State,url:&Url) -> Vec<Diagnostic> {
+    
+    match &state.sexpression_parser_error {
+        None => {},
+        Some((msg,range)) => {
+            return vec![
+                Diagnostic { 
+                    range: range.clone(), 
+                    severity: None, 
+                    code: Some(NumberOrString::String("ML000".to_string())),  
+                    code_description: None, 
+                    source: None,
+                    message: msg.to_string(), 
+                    related_information: None,
+                    tags: None,
+                    data: None
+                }    
+            ];
+        }
+    };
+
+    
+    match &state.marlowe_parser_error {
+        None => {},
+        Some((msg,range)) => 
+            return vec![Diagnostic { 
+                range: range.clone(), 
+                severity: None, 
+                code: Some(NumberOrString::String("ML000".to_string())), 
+                code_description: None, 
+                source: None, 
+                message: msg.to_string(),
+                related_information: None, 
+                tags: None, 
+                data: None 
+            }]
+    };
+    
+    let mut diagnostics : Vec<Diagnostic> = match state.marlowe_asts.get(url) {
+        Some(x) => {
+            x.1.items.iter().map(|d|               
+                Diagnostic { 
+                    range: d.0, 
+                    severity: Some(d.3), 
+                    code: Some(NumberOrString::String(d.1.clone())), 
+                    code_description: None, 
+                    source: None, 
+                    message: d.2.to_owned(),
+                    related_information: None, 
+                    tags: if d.4.is_empty() { None } else { Some(d.4.clone()) }, 
+                    data: None 
+                }   
+            ).collect()
+        }
+        None => vec![]
+    };
+
+    if let Some((_,analysis)) = state.path_analysis.get(url) {
+        diagnostics.extend(explorer::to_diagnostics(analysis, url));
+    }
+
+    if let Some((_,sizes)) = state.size_estimates.get(url) {
+        diagnostics.extend(size_estimate::to_diagnostics(sizes));
+    }
+
+    let used_params = get_used_params(state, url);
+    diagnostics.extend(params::kind_conflicts(url, &used_params));
+    if let Some(file) = state.param_files.get(url) {
+        diagnostics.extend(params::check(file, &used_params).0);
+    }
+
+    diagnostics
+
+}
+
+
+
+
+
+fn get_range(x:pest::iterators::Pair<marlowe_lang::parsing::Rule>) -> Range {
+    let span = x.as_span();
+    let start_pos = span.start_pos().line_col();
+    let end_pos = span.end_pos().line_col();
+    Range {
+        start : Position { line: start_pos.0 as u32 -1 , character: start_pos.1 as u32 - 1  },
+        end : Position { line: end_pos.0 as u32 -1 , character: end_pos.1 as u32 - 1},
+    }
+}
+
+/// Range, rule id (see [`RULES`]), message, severity and tags.
+pub type ValidationItem = (Range,String,String,DiagnosticSeverity,Vec<DiagnosticTag>);
+
+/// What the validator found in a contract.
+#[derive(Debug,Default)]
+pub struct ContractValidationResult {
+    pub items : Vec<ValidationItem>,
+    // What the contract does (parties, deposits, payments..), used for the term sheet.
+    facts : Vec<term_sheet::ContractFact>
+}
+
+impl ContractValidationResult {
+    fn merge(&mut self,other:ContractValidationResult) {
+        self.items.extend(other.items);
+        self.facts.extend(other.facts);
+    }
+}
+
+// #[derive(Clone,Default,Debug)]
+// struct TokenType {
+//     currency_symbol : String ,
+//     token_name : String
+// }
+
+// #[derive(Clone,Default,Debug)]
+// struct AccountInfo {
+//     token_amounts : HashMap<TokenType,i64>
+// }
+
+// #[derive(Clone)]
+// enum VariableAssignment {
+//     Constant(i64),
+//     VariablePointer(String)
+// }
+
+/// Settings that affect how contracts are validated, the `analysis` section of the configuration.
+#[derive(Clone,serde::Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct ValidationSettings {
+    /// Values to assume for TimeParam timeouts.
+    pub time_params : HashMap<String,i64>,
+    /// When contracts that are open for less time than this (in milliseconds) are reported.
+    pub minimum_when_window : i64,
+    /// Thresholds for the on-chain size estimates.
+    pub size_limits : size_estimate::SizeLimits,
+    /// How far the static analysis looks.
+    pub exploration_limits : explorer::ExplorationLimits,
+    /// Lint rules of the team, see [`CustomRule`].
+    pub custom_rules : Vec<CustomRule>,
+    /// Lints of an application that uses the library, see [`Config::with_lint`].
+    #[serde(skip)]
+    pub extra_lints : Vec<std::sync::Arc<dyn Lint>>
+}
+
+impl Default for ValidationSettings {
+    fn default() -> Self {
+        ValidationSettings {
+            time_params: HashMap::new(),
+            // Roughly the time between two blocks on Cardano
+            minimum_when_window: 20_000,
+            size_limits: size_estimate::SizeLimits::default(),
+            exploration_limits: explorer::ExplorationLimits::default(),
+            custom_rules: vec![],
+            extra_lints: vec![]
+        }
+    }
+}
+
+impl std::fmt::Debug for ValidationSettings {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("ValidationSettings")
+            .field("time_params", &self.time_params)
+            .field("minimum_when_window", &self.minimum_when_window)
+            .field("size_limits", &self.size_limits)
+            .field("exploration_limits", &self.exploration_limits)
+            .field("custom_rules", &self.custom_rules)
+            .field("extra_lints", &self.extra_lints.len())
+            .finish()
+    }
+}
+
+// A timeout that we know something about: its value, or at least which parameter it comes from.
+#[derive(Clone,Debug)]
+struct KnownTimeout {
+    value : Option<i64>,
+    param : Option<String>
+}
+
+impl KnownTimeout {
+    fn compare(&self,other:&KnownTimeout) -> Option<std::cmp::Ordering> {
+        match (self.value,other.value) {
+            (Some(a),Some(b)) => Some(a.cmp(&b)),
+            _ => match (&self.param,&other.param) {
+                (Some(a),Some(b)) if a == b => Some(std::cmp::Ordering::Equal),
+                _ => None
+            }
+        }
+    }
+}
+
+impl std::fmt::Display for KnownTimeout {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match (&self.param,self.value) {
+            (Some(p),Some(v)) => write!(f,"(TimeParam \"{p}\") = {v}"),
+            (Some(p),None) => write!(f,"(TimeParam \"{p}\")"),
+            (None,Some(v)) => write!(f,"{v}"),
+            (None,None) => write!(f,"?timeout")
+        }
+    }
+}
+
+/// What is known at a node of a contract while it is validated, see [`Lint`].
+#[derive(Clone)]
+pub struct NodeContext {
+    //defined_roles : Vec<String>,
+    // The time is known to be at least this timeout (we are in the timeout continuation of a When)
+    earliest_time : Option<KnownTimeout>,
+    // The time is known to be before this timeout (we are in a case of a When)
+    latest_time : Option<KnownTimeout>,
+    settings : std::sync::Arc<ValidationSettings>,
+    //known_accounts : HashMap<String,AccountInfo>,
+    //let_assigns : HashMap<String,VariableAssignment>,
+    choices: Vec<String>,
+    // What must have happened for a node to be reached, and the range of the nodes it applies to
+    conditions: Vec<(Range,String)>,
+    // The kind of node that the node is in
+    parent: Option<Rule>,
+    lints: std::sync::Arc<lints::Registry>
+}
+
+impl NodeContext {
+    /// The settings the contract is validated with.
+    pub fn settings(&self) -> &ValidationSettings {
+        &self.settings
+    }
+    /// The kind of node that the node is in.
+    pub fn parent(&self) -> Option<Rule> {
+        self.parent
+    }
+    /// The time is known to be at least this (the node is in the timeout continuation of a When).
+    pub fn earliest_time(&self) -> Option<i64> {
+        self.earliest_time.as_ref().and_then(|t| t.value)
+    }
+    /// The time is known to be before this (the node is in a case of a When).
+    pub fn latest_time(&self) -> Option<i64> {
+        self.latest_time.as_ref().and_then(|t| t.value)
+    }
+    /// A problem with a node, to report from a lint.
+    pub fn item(&self,pair:&pest::iterators::Pair<Rule>,code:&str,message:&str,severity:DiagnosticSeverity) -> ValidationItem {
+        (get_range(pair.clone()),code.to_string(),message.to_string(),severity,vec![])
+    }
+    fn conditions_for(&self,range:Range) -> Vec<String> {
+        self.conditions.iter()
+            .filter(|(scope,_)| scope.start <= range.start && range.end <= scope.end)
+            .map(|(_,condition)| condition.clone())
+            .collect()
+    }
+    fn fact(&self,pair:&pest::iterators::Pair<Rule>,kind:term_sheet::FactKind) -> term_sheet::ContractFact {
+        let range = get_range(pair.clone());
+        term_sheet::ContractFact {
+            kind,
+            range,
+            deadline: self.latest_time.as_ref().map(|t|t.to_string()),
+            conditions: self.conditions_for(range)
+        }
+    }
+}
+
+// The text of a node with all whitespace collapsed, so that it fits on a single line.
+fn normalized_text(pair:&pest::iterators::Pair<Rule>) -> String {
+    pair.as_str().split_whitespace().collect::<Vec<&str>>().join(" ")
+}
+
+// What we know about the timeout of a When. TimeParams get their values from the
+// validation settings when available, otherwise we only know their name.
+fn known_timeout(timeout:&pest::iterators::Pair<Rule>,settings:&ValidationSettings) -> std::result::Result<Option<KnownTimeout>,std::num::ParseIntError> {
+    match timeout.as_rule() {
+        Rule::TimeConstant | Rule::Number => 
+            timeout.as_str().parse::<i64>().map(|value| Some(KnownTimeout { value: Some(value), param: None })),
+        Rule::TimeParam => {
+            let name = timeout.clone().into_inner().next().map(|n|n.as_str().to_string()).unwrap_or_default();
+            Ok(Some(KnownTimeout { value: settings.time_params.get(&name).copied(), param: Some(name) }))
+        },
+        _ => Ok(None)
+    }
+}
+
+// Visits a node and everything in it. The lints (see lints.rs) report what is wrong with
+// every node, here we keep track of what is known in the nodes below it and record what
+// the contract does for the term sheet.
+#[decurse::decurse]
+fn recursively_validate_contract(x:pest::iterators::Pair<'static,marlowe_lang::parsing::Rule>,context:NodeContext) -> ContractValidationResult {
+    
+    let mut result = ContractValidationResult::default();
+    context.lints.check(&x, &context, &mut result.items);
+
+    let mut inner_context = context.clone();
+    inner_context.parent = Some(x.as_rule());
+
+    // Most nodes do not change what we know, the ones that do give their children a context of their own
+    let children : Vec<(pest::iterators::Pair<'static,Rule>,NodeContext)> = match x.as_rule() {
+            
+        Rule::Case => {
+            
+            // Cases consist of an action and a continuation contract.
+            // There are currently three possible actions available:
+            // - Deposit <-- This changes our context
+            // - Choice <-- This changes our context
+            // - Notify <-- Just needs to be validated as usual
+
+            // In the case of an action being a deposit, we must register it in our context
+            // so that we can validate against it in sub-nodes.
+
+            // Should this be a Choice, we also register the choise so that we can
+            // validate against possible choices in sub-nodes.
+
+            // We will validate the action and the continuation of the case with
+            // the case-local context (possibly affected by deposit or choice).
+
+            let mut case = x.clone().into_inner();
+            let action = case.next().unwrap();
+            let continuation_contract = case.next().unwrap();
+
+            // -- PERFORM ALL CONTEXT MUTATIONS --------------------
+            match action.as_rule() {
+                Rule::Deposit => {
+                    // A deposit was made. Update the context with information regarding the known
+                    // values of source and target accounts such that we can validate against it 
+                    // in child-nodes. This is so that we can know if a payment can be made or not later.
+                    // TODO: Add support for account context validation!
+                },
+                Rule::Choice => {
+                    // We clone this here because we still want to perform the normal validation
+                    let mut cloned_choice = action.clone().into_inner();
+                    // A (possibly) new choice was made. Register it in the list of available choice values
+                    // such that we can validate against it in child-nodes.
+
+                    let mut choice_id = cloned_choice.next().unwrap().into_inner(); // choice_id can never be a hole.
+                    let choice_name = choice_id.next().unwrap();
+                    let choice_name_value = choice_name.as_str().to_string(); // Strings cannot be holes.
+                    let party = choice_id.next().unwrap(); // party can be a hole.
+                    let who_done_it = party.as_str();
+                    
+                    if !inner_context.choices.contains(&choice_name_value) {
+                        inner_context.choices.push(choice_name_value+who_done_it)
+                    }
+
+                }
+                _ => {
+                    // nothing here can change the context 
+                }
+            }
+
+            // Record what the case does.
+            // The action is listed on the term sheet, and the continuation can only
+            // be reached once the action has happened.
+            let happened = match action.as_rule() {
+                Rule::Deposit => {
+                    let mut deposit = action.clone().into_inner().map(|p|normalized_text(&p));
+                    let (into_account,from,token,amount) = (
+                        deposit.next().unwrap_or_default(),deposit.next().unwrap_or_default(),
+                        deposit.next().unwrap_or_default(),deposit.next().unwrap_or_default());
+                    let happened = format!("{from} deposited {amount} {token} into the account of {into_account}");
+                    result.facts.push(context.fact(&action,term_sheet::FactKind::Deposit { into_account, from, token, amount }));
+                    Some(happened)
+                },
+                Rule::Choice => {
+                    let mut choice = action.clone().into_inner();
+                    let mut choice_id = choice.next().unwrap().into_inner();
+                    let name = choice_id.next().map(|p|p.as_str().to_string()).unwrap_or_default();
+                    let owner = choice_id.next().map(|p|normalized_text(&p)).unwrap_or_default();
+                    let bounds = choice.next().map(|b| b.into_inner().map(|bound| {
+                        let mut numbers = bound.clone().into_inner();
+                        match (numbers.next(),numbers.next()) {
+                            (Some(low),Some(high)) => format!("{} to {}",low.as_str(),high.as_str()),
+                            _ => normalized_text(&bound)
+                        }
+                    }).collect::<Vec<String>>().join(", ")).unwrap_or_default();
+                    let happened = format!("{owner} chose \"{name}\"");
+                    result.facts.push(context.fact(&action,term_sheet::FactKind::Choice { name, owner, bounds }));
+                    Some(happened)
+                },
+                Rule::Notify => {
+                    let observation = action.clone().into_inner().next().map(|p|normalized_text(&p)).unwrap_or_default();
+                    let happened = format!("notified that {observation}");
+                    result.facts.push(context.fact(&action,term_sheet::FactKind::Notify { observation }));
+                    Some(happened)
+                },
+                _ => None
+            };
+            if let Some(happened) = happened {
+                inner_context.conditions.push((get_range(continuation_contract.clone()),happened));
+            }
+
+            vec![(action,inner_context.clone()),(continuation_contract,inner_context)]
+        }
+        Rule::When => {
+            
+            // A when contract node has three arguments, in this order:
+            // ArrayOfCases ~ Timeout ~ WrappedContract.
+            let mut when_contract = x.clone().into_inner();
+            let case_list = when_contract.next().unwrap();
+            let timeout = when_contract.next().unwrap();
+            let continuation_contract = when_contract.next().unwrap();
+
+            let this_timeout = known_timeout(&timeout, &context.settings).ok().flatten();
+
+            // The timeout is a deadline on the term sheet
+            let timeout_text = this_timeout.as_ref().map(|t|t.to_string()).unwrap_or_else(|| normalized_text(&timeout));
+            if let Some(KnownTimeout { param: Some(name), .. }) = &this_timeout {
+                result.facts.push(context.fact(&timeout,term_sheet::FactKind::Parameter { name: name.clone(), kind: term_sheet::ParameterKind::TimeParam }));
+            }
+            result.facts.push(context.fact(&x,term_sheet::FactKind::Deadline { 
+                timeout: timeout_text.clone(), 
+                value: this_timeout.as_ref().and_then(|t|t.value)
+            }));
+
+            // Cases can only fire before this timeout.
+            let mut sub_context_for_the_cases = inner_context.clone();
+            sub_context_for_the_cases.latest_time = this_timeout.clone();
+
+            // The timeout continuation runs at the earliest at this timeout (or later, if we already knew that).
+            let mut sub_context_for_the_timeout_continuation = inner_context.clone();
+            sub_context_for_the_timeout_continuation.latest_time = None;
+            sub_context_for_the_timeout_continuation.conditions.push((
+                get_range(continuation_contract.clone()),
+                format!("nothing happened before {timeout_text}")
+            ));
+            if let Some(this_timeout) = this_timeout {
+                let known_to_be_earlier = match &context.earliest_time {
+                    Some(earliest) => matches!(this_timeout.compare(earliest),Some(std::cmp::Ordering::Less)),
+                    None => false
+                };
+                if !known_to_be_earlier {
+                    sub_context_for_the_timeout_continuation.earliest_time = Some(this_timeout);
+                }
+            }
+
+            vec![
+                (case_list,sub_context_for_the_cases),
+                (timeout,inner_context),
+                (continuation_contract,sub_context_for_the_timeout_continuation)
+            ]
+        }
+        Rule::If => {
+            let mut if_contract = x.clone().into_inner();
+            let observation = if_contract.next().unwrap();
+            let then_contract = if_contract.next().unwrap();
+            let else_contract = if_contract.next().unwrap();
+            let observation_text = normalized_text(&observation);
+            inner_context.conditions.push((get_range(then_contract),format!("{observation_text} is true")));
+            inner_context.conditions.push((get_range(else_contract),format!("{observation_text} is false")));
+            x.clone().into_inner().map(|child| (child,inner_context.clone())).collect()
+        }
+        Rule::Pay => {
+            let mut pay = x.clone().into_inner().map(|p|normalized_text(&p));
+            let (from_account,to,token,amount) = (
+                pay.next().unwrap_or_default(),pay.next().unwrap_or_default(),
+                pay.next().unwrap_or_default(),pay.next().unwrap_or_default());
+            result.facts.push(context.fact(&x,term_sheet::FactKind::Payment { from_account, to, token, amount }));
+            x.clone().into_inner().map(|child| (child,inner_context.clone())).collect()
+        }
+        _ => {
+            match x.as_rule() {
+                Rule::Role | Rule::PK => result.facts.push(context.fact(&x,term_sheet::FactKind::Party(normalized_text(&x)))),
+                Rule::ADA => result.facts.push(context.fact(&x,term_sheet::FactKind::Token(String::from("ADA")))),
+                Rule::Currency => {
+                    let token = if x.clone().into_inner().all(|s|s.as_str().is_empty()) { String::from("ADA") } else { normalized_text(&x) };
+                    result.facts.push(context.fact(&x,term_sheet::FactKind::Token(token)))
+                }
+                Rule::ConstantParam => {
+                    let name = x.clone().into_inner().next().map(|n|n.as_str().to_string()).unwrap_or_default();
+                    result.facts.push(context.fact(&x,term_sheet::FactKind::Parameter { name, kind: term_sheet::ParameterKind::ConstantParam }))
+                }
+                _ => {}
+            }
+            x.clone().into_inner().map(|child| (child,inner_context.clone())).collect()
+        }
+    };
+
+    for (child,child_context) in children {
+        result.merge(recursively_validate_contract(child, child_context));
+    }
+
+    result
+
+}
+
+
+// We do multiple passes (sexpress+marlowe) for parsing because it was easier to do
+// than switch from pest.rs which does not support token streaming..
+
+/// Parsing with positions for an editor, for the rules `T` of a pest grammar.
+pub trait LSParse<T> {
+    /// Parses a contract into semantic tokens (the token type of a rule is given by `f`)
+    /// and validates it. Errors are a message and the range where parsing failed.
+    fn lsp_parse(sample:String, f: impl Fn(T,Range) -> u32, settings:&ValidationSettings) ->
+        std::result::Result<
+            (Vec<(Range,T,lsp_types::SemanticToken)>,ContractValidationResult),
+            (String,lsp_types::Range)>;
+    /// The innermost token that contains a position.
+    fn get_token_at_position(tokens:Vec<(Range,T,lsp_types::SemanticToken)>,position:lsp_types::Position) -> Option<(Range,T,SemanticToken)>;
+    /// A description of the innermost token at a position, from `f`.
+    fn get_token_info_at_position(p:Vec<(Range,T,lsp_types::SemanticToken)>,position:lsp_types::Position, f:fn(T)->String) -> Option<String>;
+}
+
+use pest::{Parser};
+#[macro_export]
+#[doc(hidden)]
+macro_rules! Impl_LSPARSE_For {
+    
+    ($rule_type:ty,$parser_type:ty,$top_type:expr,$test:expr) => {
+        
+        impl LSParse<$rule_type> for $rule_type {
+            
+            fn lsp_parse(sample:String,f: impl Fn($rule_type,Range) -> u32,settings:&ValidationSettings) -> 
+                std::result::Result<
+                    (Vec<(Range,$rule_type,lsp_types::SemanticToken)>,ContractValidationResult), (String,lsp_types::Range)
+                > {
+                let boxed = Box::new(sample.clone());
+                let lookup = LineColLookup::new(&sample);
+                match <$parser_type>::parse(
+                    $top_type,
+                    Box::leak(boxed)
+                ) {
+                    Ok(p) => { 
+                        
+                        let mut previous_range : Option<lsp_types::Range> = None;
+                        let mut last_line_start : usize = 1;
+                        let mut last_line_end: usize = 1;
+                        let mut last_start: usize = 1;
+                        let mut last_end: usize = 1;
+                        
+                        let data = 
+                            p.clone().flatten().map(|x|{
+                                let span = x.as_span();
+                                let start_pos = span.start();
+                                let end_pos = span.end();
+                                
+                                let (start_line,start_col) = lookup.get(start_pos);
+                                let (end_line,end_col) = lookup.get(end_pos);
+                                
+                                let range = lsp_types::Range {
+                                    start: lsp_types::Position::new(start_line as u32,start_col as u32),
+                                    end:   lsp_types::Position::new(end_line as u32,end_col as u32),
+                                };
+                                let mut corrected_start = start_col as usize;
+                                if start_line == last_line_start {
+                                    corrected_start = corrected_start - last_start;
+                                    
+                                } else {
+                                    corrected_start = corrected_start - 1;
+                                }       
+                                let corrected_line = (start_line - last_line_start);
+                                let calculated_length = span.as_str().len();
+
+                                let token = SemanticToken { 
+                                    // `deltaLine`: token line number, relative to the previous token
+                                    // `deltaStart`: token start character, relative to the previous token 
+                                    //  (relative to 0 or the previous token's start if they are on the same line)
+                                    // `length`: the length of the token. A token cannot be multiline.
+                                    // `tokenType`: will be looked up in `SemanticTokensLegend.tokenTypes`
+                                    // `tokenModifiers`: each set bit will be looked up in `SemanticTokensLegend.tokenModifiers`
+                                    delta_line: corrected_line as u32,
+                                    delta_start: corrected_start as u32 ,
+                                    length: calculated_length as u32,
+                                    token_type: f(x.as_rule(),range), 
+                                    token_modifiers_bitset: 0 
+                                };
+        
+                                (last_line_end,last_end) = (end_line,end_col);
+                                (last_line_start,last_start) = (start_line,start_col);
+                                previous_range = Some(range);
+                                (range,x.as_rule(),token)
+                            }).collect();
+
+                        let validation_result = $test(p,settings);
+                        Ok((data,validation_result))
+                       
+                    },
+                    Err(x) => {
+                        
+                        let error_message = format!("{x:#}");
+                        match x.line_col {
+                            pest::error::LineColLocation::Span(start,end) => {
+                                Err((
+                                    error_message,
+                                    lsp_types::Range {
+                                        start: lsp_types::Position::new(
+                                            start.0 as u32 - 1,start.1 as u32),
+                                        end: lsp_types::Position::new(
+                                            end.0 as u32 - 1,end.1 as u32)
+                                    }))
+                            }
+                            pest::error::LineColLocation::Pos(position) =>
+                                Err((
+                                    error_message,
+                                    lsp_types::Range {
+                                        start: lsp_types::Position::new(position.0 as u32 - 1,position.1 as u32),
+                                        end: lsp_types::Position::new(position.0 as u32 - 1,position.1 as u32)
+                                    }))
+                            }
+                        }
+                    }
+                }
+            
+                fn get_token_at_position(tokens:Vec<(Range,$rule_type,lsp_types::SemanticToken)>,position:lsp_types::Position) -> Option<(Range,$rule_type,SemanticToken)> {
+                    let line = position.line + 1;
+                    let char = position.character + 1;
+                    let mut currently_closest : Option<(Range,$rule_type,SemanticToken)> = None;
+                    let mut filtered = 
+                        tokens.iter().filter(|(range,_rule,_token)|{    
+                            if range.start.line > line || (range.start.line == line && range.start.character > char) {
+                                return false
+                            }
+                            true
+                        });
+                    while let Some(current) = filtered.next() {
+                        match &currently_closest {
+                            Some(currently_closest_item) => {
+                                let previous_start = currently_closest_item.0.start;
+                                let previous_end = currently_closest_item.0.end;
+                                let start_pos = current.0.start;
+                                let end_pos = current.0.end;
+                                if start_pos >= previous_start || end_pos <= previous_end {
+                                    currently_closest = Some(*current)
+                                }
+                
+                            },
+                            None => {
+                                currently_closest = Some(*current)
+                            },
+                        }
+                    }
+                    
+                    match currently_closest {
+                        None => None,
+                        Some((a,b,c)) => {
+                            Some((Range {
+                                start: Position {
+                                    character: a.start.character - 1,
+                                    line: a.start.line - 1
+                                },
+                                end: Position {
+                                    character: a.end.character - 1,
+                                    line: a.end.line - 1
+                                }
+                            },b,c))
+                        }
+                    }
+                }
+                
+                fn get_token_info_at_position(p:Vec<(Range,$rule_type,lsp_types::SemanticToken)>,position:lsp_types::Position, f:fn($rule_type)->String) -> Option<String> {
+                    match Self::get_token_at_position(p,position) {
+                            Some(ooh) => Some(f(ooh.1)),
+                            None => None
+                    }    
+                }
+            
+            }
+        }
+    }
+    
+
+Impl_LSPARSE_For!(
+    sex::Rule,
+    sex::SexParser,
+    sex::Rule::expressions,
+    |_,_| ContractValidationResult::default()
+);
+
+Impl_LSPARSE_For!(
+    marlowe_lang::parsing::Rule,
+    marlowe_lang::parsing::MarloweParser,
+    marlowe_lang::parsing::Rule::Contract,
+    |x:pest::iterators::Pairs<'static,marlowe_lang::parsing::Rule>,settings:&ValidationSettings| {
+        let context = NodeContext { 
+            //defined_roles: vec![], 
+            earliest_time: None,
+            latest_time: None,
+            settings: std::sync::Arc::new(settings.clone()),
+            //known_accounts: HashMap::new(),
+            //let_assigns : HashMap::new(),
+            choices: vec![],
+            conditions: vec![],
+            parent: None,
+            lints: std::sync::Arc::new(lints::Registry::new(settings))
+        };
+        let mut result = ContractValidationResult::default();
+        for contract in x {
+            result.merge(recursively_validate_contract(contract, context.clone()));
+        }
+        result
+    }
+);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn open(state: &mut State, uri: &Url, text: &str) {
+        get_or_insert_document(state, &TextDocumentItem { uri: uri.clone(), language_id: "MarloweJSON".into(), version: 1, text: text.into() });
+    }
+
+    fn codes(state: &mut State, uri: &Url) -> Vec<Option<NumberOrString>> {
+        get_diagnostics(state, uri).into_iter().map(|d| d.code).collect()
+    }
+
+    #[test]
+    fn json_errors_belong_to_their_document() {
+        let mut state = State::new();
+        let broken = Url::parse("file:///contracts/broken.marlowe.json").unwrap();
+        let close = Url::parse("file:///contracts/close.marlowe.json").unwrap();
+        open(&mut state, &broken, "{ \"when\": [");
+        open(&mut state, &close, "\"close\"");
+        assert_eq!(codes(&mut state, &broken), [Some(NumberOrString::String("ML000".into()))]);
+        assert_eq!(codes(&mut state, &close), []);
+    }
+
+    #[test]
+    fn json_parties_are_written_the_way_the_dsl_accepts_them() {
+        let pay = |party: &str| format!("{{ \"pay\": 1, \"from_account\": {party}, \"to\": {{ \"party\": {{ \"role_token\": \"b\" }} }}, \"token\": {{ \"currency_symbol\": \"\", \"token_name\": \"\" }}, \"then\": \"close\" }}");
+        let mut state = State::new();
+        let pk = Url::parse("file:///contracts/pk.marlowe.json").unwrap();
+        open(&mut state, &pk, &pay(&format!("{{ \"pk_hash\": \"{}\" }}", "ab".repeat(32))));
+        assert!(!codes(&mut state, &pk).contains(&Some(NumberOrString::String("ML000".into()))));
+
+        let address = Url::parse("file:///contracts/address.marlowe.json").unwrap();
+        open(&mut state, &address, &pay("{ \"address\": \"addr_test1vz\" }"));
+        let diagnostics = get_diagnostics(&mut state, &address);
+        assert_eq!(diagnostics.len(), 1);
+        assert!(diagnostics[0].message.starts_with("Unsupported party"), "{}", diagnostics[0].message);
+        assert_eq!(diagnostics[0].range, Range::new(Position::new(0, 28), Position::new(0, 57)));
+    }
+
+    #[test]
+    fn only_marlowe_json_files_are_contracts() {
+        assert!(json_document::is_json_document(&Url::parse("file:///contracts/swap.marlowe.json").unwrap()));
+        assert!(!json_document::is_json_document(&Url::parse("file:///contracts/swap.params.json").unwrap()));
+        assert!(!json_document::is_json_document(&Url::parse("file:///contracts/swap.continuations.json").unwrap()));
+    }
+}
@@ -0,0 +1,416 @@
+// The checks that run while the validator walks a DSL contract (ML001-ML034), and the
+// custom rules of a team, from the configuration.
+//
+// recursively_validate_contract (lib.rs) visits every node of the contract with a
+// NodeContext: what is known at that point (the timeouts around it, the choices made
+// before it..). Every lint of the registry gets to look at every node, and reports
+// what is wrong with it. The walker keeps the context up to date and collects the
+// facts for the term sheet, the lints only report.
+//
+// Custom rules report nodes of a kind whose text matches a pattern:
+//
+//   [[analysis.customRules]]
+//   id = "HOUSE001"
+//   node = "Role"
+//   pattern = '"[^"]*[A-Z]'
+//   message = "Roles must be lowercase."
+//
+//   [[analysis.customRules]]
+//   id = "HOUSE002"
+//   node = "When"
+//   unless = ' Close$'
+//   message = "Every When must refund (Close) when it times out."
+//   severity = "error"
+
+use std::sync::Arc;
+use lsp_types::{DiagnosticSeverity, DiagnosticTag};
+use marlowe_lang::parsing::Rule;
+use regex::Regex;
+use crate::config::RuleLevel;
+use crate::{get_range, known_timeout, normalized_text, NodeContext, ValidationItem, ValidationSettings};
+
+/// A node of the parse tree of a DSL contract.
+pub type Node = pest::iterators::Pair<'static, Rule>;
+
+/// A check that looks at one node of a contract at a time. Applications that use the library
+/// can add their own with [`Config::with_lint`](crate::Config::with_lint):
+///
+/// ```
+/// use marlowe_lsp::{Config, Document, Lint, LintNode, NodeContext, ValidationItem};
+/// use marlowe_lsp::lsp_types::{DiagnosticSeverity, NumberOrString, Url};
+/// use marlowe_lang::parsing::Rule;
+///
+/// struct NoAssert;
+///
+/// impl Lint for NoAssert {
+///     fn check(&self, node: &LintNode, context: &NodeContext, items: &mut Vec<ValidationItem>) {
+///         if node.as_rule() == Rule::Assert {
+///             items.push(context.item(node, "APP001", "Assert is not allowed here.", DiagnosticSeverity::ERROR));
+///         }
+///     }
+/// }
+///
+/// let config = Config::default().with_lint(NoAssert);
+/// let uri = Url::parse("file:///contracts/escrow.marlowe").unwrap();
+/// let document = Document::parse_with_config(uri, "Assert TrueObs Close", &config);
+/// assert_eq!(document.diagnostics()[0].code, Some(NumberOrString::String("APP001".into())));
+/// ```
+pub trait Lint: Send + Sync {
+    /// Reports what is wrong with a node, if anything.
+    fn check(&self, node: &Node, context: &NodeContext, items: &mut Vec<ValidationItem>);
+}
+
+fn note(node: &Node, code: &str, message: &str, severity: DiagnosticSeverity) -> ValidationItem {
+    (get_range(node.clone()), code.to_string(), message.to_string(), severity, vec![])
+}
+
+// Marks a node that can never be reached
+fn unreachable_note(node: &Node, code: &str, message: &str) -> ValidationItem {
+    (get_range(node.clone()), code.to_string(), message.to_string(), DiagnosticSeverity::WARNING, vec![DiagnosticTag::UNNECESSARY])
+}
+
+/// The built-in lints, see rules.rs for their ids.
+const BUILTIN: &[&dyn Lint] = &[&Timeouts, &Holes, &UndefinedChoices, &Unreachable];
+
+/// The lints that a contract is validated with: the built-in ones, the ones of the
+/// application and the custom rules.
+pub(crate) struct Registry {
+    extra: Vec<Arc<dyn Lint>>,
+    custom: Vec<PatternLint>,
+}
+
+impl Registry {
+
+    /// Custom rules with problems are left out, Config::load reports them.
+    pub fn new(settings: &ValidationSettings) -> Registry {
+        Registry {
+            extra: settings.extra_lints.clone(),
+            custom: settings.custom_rules.iter().filter_map(|rule| PatternLint::new(rule).ok()).collect(),
+        }
+    }
+
+    pub fn check(&self, node: &Node, context: &NodeContext, items: &mut Vec<ValidationItem>) {
+        for lint in BUILTIN {
+            lint.check(node, context, items)
+        }
+        for lint in &self.extra {
+            lint.check(node, context, items)
+        }
+        for lint in &self.custom {
+            lint.check(node, context, items)
+        }
+    }
+}
+
+// -- ML001-ML009 --------------------------------------------------------------------
+
+/// Timeouts that do not fit the path that leads to them: inside of a case the time is
+/// earlier than the timeout of the When that the case belongs to, and inside of a timeout
+/// continuation it is at least the timeout of that When.
+struct Timeouts;
+
+impl Lint for Timeouts {
+    fn check(&self, node: &Node, context: &NodeContext, items: &mut Vec<ValidationItem>) {
+        if node.as_rule() != Rule::When {
+            return
+        }
+        let Some(timeout) = node.clone().into_inner().nth(1) else { return };
+        let this_timeout = match known_timeout(&timeout, &context.settings) {
+            Ok(Some(this_timeout)) => this_timeout,
+            Ok(None) => return,
+            Err(e) => {
+                items.push(note(&timeout, "ML004", &format!("This does not seem to be a valid number! {e:?}"), DiagnosticSeverity::ERROR));
+                return
+            }
+        };
+        if let Some(earliest) = &context.earliest_time {
+            if let (Some(std::cmp::Ordering::Greater), Some(this_timeout_value), Some(earliest_value)) = (this_timeout.compare(earliest), this_timeout.value, earliest.value) {
+                let window = this_timeout_value - earliest_value;
+                if window < context.settings.minimum_when_window {
+                    items.push(note(&timeout, "ML003", &format!("This When is only open for {window} ms after the timeout of the enclosing When: {earliest}, which is less than the minimum of {} ms. There might not be enough time for anyone to act before it times out.", context.settings.minimum_when_window), DiagnosticSeverity::WARNING));
+                }
+            }
+        }
+        if let Some(latest) = &context.latest_time {
+            match this_timeout.compare(latest) {
+                Some(std::cmp::Ordering::Less) =>
+                    items.push(note(&timeout, "ML001", &format!("Timeouts should always increase. This value ({this_timeout}) was expected to be greater than: {latest}"), DiagnosticSeverity::WARNING)),
+                Some(std::cmp::Ordering::Equal) =>
+                    items.push(note(&timeout, "ML002", &format!("This timeout is the same as the timeout of the enclosing When: {latest}. This When times out at the same moment as the When it is reached from."), DiagnosticSeverity::WARNING)),
+                _ => {}
+            }
+        }
+    }
+}
+
+// -- ML010-ML019 --------------------------------------------------------------------
+
+struct Holes;
+
+impl Lint for Holes {
+    fn check(&self, node: &Node, context: &NodeContext, items: &mut Vec<ValidationItem>) {
+        let (code, message) = match node.as_rule() {
+            // Continuations get a message that says what is missing
+            Rule::ContractHole => ("ML011", match context.parent {
+                Some(Rule::Case) => "Found a hole of type 'Contract': The continuation contract for this case is missing.",
+                Some(Rule::When) => "Found a hole of type 'Contract (Continuation)'. What should happen if this 'When' contract times out?",
+                _ => "Found a hole of type 'Contract'.",
+            }),
+            Rule::Hole => ("ML019", "Found a hole"),
+            Rule::PartyHole => ("ML010", "Found a hole of type 'Party'."),
+            Rule::FromPartyHole => ("ML010", "Found a hole of type '(From) Party'."),
+            Rule::PayeeHole => ("ML010", "Found a hole of type 'Party (Payee)'."),
+            Rule::ValueHole => ("ML013", "Found a hole of type 'Value'."),
+            Rule::ObservationHole => ("ML014", "Found a hole of type 'Observation'."),
+            Rule::TimeoutHole => ("ML015", "Found a hole of type 'Timeout'."),
+            Rule::TokenHole => ("ML016", "Found a hole of type 'Token'."),
+            Rule::BoundHole => ("ML017", "Found a hole of type 'Bound'."),
+            Rule::RoleHole => ("ML010", "Found a hole of type 'Role'."),
+            Rule::PubkeyHole => ("ML010", "Found a hole of type 'PK'."),
+            Rule::CaseHole => ("ML018", "Found a hole of type 'Case'."),
+            Rule::ActionHole => ("ML012", "Found a hole of type 'Action'."),
+            Rule::AccountHole => ("ML010", "Found a hole of type 'Account'"),
+            _ => return
+        };
+        items.push(note(node, code, message, DiagnosticSeverity::WARNING))
+    }
+}
+
+// -- ML020-ML029 --------------------------------------------------------------------
+
+struct UndefinedChoices;
+
+impl Lint for UndefinedChoices {
+    fn check(&self, node: &Node, context: &NodeContext, items: &mut Vec<ValidationItem>) {
+        if node.as_rule() != Rule::ChoiceValue {
+            return
+        }
+        // ChoiceValue always contain a single ChoiceId node.
+        // The inner ChoiceId node always has a string value and then a party or a party hole.
+        let Some(choice_id_node) = node.clone().into_inner().next() else { return };
+        let mut choice_id = choice_id_node.clone().into_inner();
+        let (Some(name), Some(party)) = (choice_id.next(), choice_id.next()) else { return };
+
+        // Validate that a choice with that name, and the same party exists.
+        let the_choice = name.as_str().to_string() + party.as_str();
+        if !context.choices.contains(&the_choice) {
+            items.push(note(&choice_id_node, "ML020", "The contract uses a ChoiceId that has not been input by a When, so (Constant 0) will be used.", DiagnosticSeverity::WARNING));
+        }
+    }
+}
+
+// -- ML030-ML039 --------------------------------------------------------------------
+
+struct Unreachable;
+
+impl Lint for Unreachable {
+    fn check(&self, node: &Node, context: &NodeContext, items: &mut Vec<ValidationItem>) {
+        match node.as_rule() {
+            // Some actions can never happen, which makes the whole case unreachable
+            Rule::Case => {
+                let Some(action) = node.clone().into_inner().next() else { return };
+                match action.as_rule() {
+                    Rule::Notify => {
+                        if let Some(observation) = action.into_inner().next() {
+                            if constant_observation(&observation) == Some(false) {
+                                items.push(unreachable_note(node, "ML030", "This case can never be reached: the Notify observation is always false."));
+                            }
+                        }
+                    }
+                    Rule::Choice => {
+                        if let Some(bounds) = action.into_inner().nth(1) {
+                            if has_no_valid_bounds(&bounds) {
+                                items.push(unreachable_note(node, "ML031", "This case can never be reached: the choice has no valid bounds, so no value can ever be chosen."));
+                            }
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            // Only the first case that matches an input is used, so cases with the same action
+            // as an earlier case can never fire. If this When has already timed out when
+            // we get here, none of its cases can fire.
+            Rule::When => {
+                let mut when = node.clone().into_inner();
+                let (Some(case_list), Some(timeout)) = (when.next(), when.next()) else { return };
+                let this_timeout = known_timeout(&timeout, &context.settings).ok().flatten();
+                let already_timed_out = match (&this_timeout, &context.earliest_time) {
+                    (Some(this_timeout), Some(earliest)) if matches!(this_timeout.compare(earliest), Some(std::cmp::Ordering::Less | std::cmp::Ordering::Equal)) =>
+                        Some(format!("This case can never be reached: the When can only be reached after the timeout {earliest} has passed, so its own timeout {this_timeout} has already passed too.")),
+                    _ => None
+                };
+                let mut seen_actions : Vec<String> = vec![];
+                for case in case_list.into_inner().filter(|c| c.as_rule() == Rule::Case) {
+                    if let Some(message) = &already_timed_out {
+                        items.push(unreachable_note(&case, "ML032", message));
+                        continue;
+                    }
+                    let Some(action) = case.clone().into_inner().next() else { continue };
+                    if action.as_rule() == Rule::ActionHole { continue }
+                    let normalized_action = normalized_text(&action);
+                    if seen_actions.contains(&normalized_action) {
+                        items.push(unreachable_note(&case, "ML033", "This case can never be reached: an earlier case in the same When has the same action, and only the first matching case is used."));
+                    } else {
+                        seen_actions.push(normalized_action);
+                    }
+                }
+            }
+            // If the observation is constant, one of the branches is dead.
+            Rule::If => {
+                let mut if_contract = node.clone().into_inner();
+                let (Some(observation), Some(then_contract), Some(else_contract)) = (if_contract.next(), if_contract.next(), if_contract.next()) else { return };
+                match constant_observation(&observation) {
+                    Some(true) => items.push(unreachable_note(&else_contract, "ML034", "This branch can never be reached since the observation of the If is always true.")),
+                    Some(false) => items.push(unreachable_note(&then_contract, "ML034", "This branch can never be reached since the observation of the If is always false.")),
+                    None => {}
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+fn constant_value(pair: &Node) -> Option<i64> {
+    match pair.as_rule() {
+        Rule::Constant => pair.clone().into_inner().next()?.as_str().parse::<i64>().ok(),
+        _ => None
+    }
+}
+
+// Returns the value of an observation if it does not depend on anything that happens in the contract.
+fn constant_observation(pair: &Node) -> Option<bool> {
+    let mut inner = pair.clone().into_inner();
+    match pair.as_rule() {
+        Rule::TrueObs => Some(true),
+        Rule::FalseObs => Some(false),
+        Rule::NotObs => constant_observation(&inner.next()?).map(|b| !b),
+        Rule::AndObs => {
+            match (constant_observation(&inner.next()?), constant_observation(&inner.next()?)) {
+                (Some(false), _) | (_, Some(false)) => Some(false),
+                (Some(true), Some(true)) => Some(true),
+                _ => None
+            }
+        }
+        Rule::OrObs => {
+            match (constant_observation(&inner.next()?), constant_observation(&inner.next()?)) {
+                (Some(true), _) | (_, Some(true)) => Some(true),
+                (Some(false), Some(false)) => Some(false),
+                _ => None
+            }
+        }
+        Rule::ValueEQ | Rule::ValueGE | Rule::ValueGT | Rule::ValueLE | Rule::ValueLT => {
+            let a = constant_value(&inner.next()?)?;
+            let b = constant_value(&inner.next()?)?;
+            match pair.as_rule() {
+                Rule::ValueEQ => Some(a == b),
+                Rule::ValueGE => Some(a >= b),
+                Rule::ValueGT => Some(a > b),
+                Rule::ValueLE => Some(a <= b),
+                _ => Some(a < b)
+            }
+        }
+        _ => None
+    }
+}
+
+// A choice can only be made if at least one of its bounds contains a value.
+fn has_no_valid_bounds(array_of_bounds: &Node) -> bool {
+    array_of_bounds.clone().into_inner().all(|b| {
+        if b.as_rule() != Rule::Bound { return false } // holes could still become anything
+        let mut numbers = b.into_inner();
+        match (numbers.next().map(|n| n.as_str().parse::<i64>()), numbers.next().map(|n| n.as_str().parse::<i64>())) {
+            (Some(Ok(low)), Some(Ok(high))) => low > high,
+            _ => false
+        }
+    })
+}
+
+// -- Custom rules -------------------------------------------------------------------
+
+/// A lint rule from the configuration (`analysis.customRules`): nodes of a kind are
+/// reported when their text matches `pattern` and does not match `unless`.
+#[derive(Clone, Debug, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CustomRule {
+    /// The code of the diagnostics, like HOUSE001. Ids of built-in rules (ML...) can not be used.
+    pub id: String,
+    /// The kind of node to look at, like `When` or `Role`: the names of the Marlowe grammar.
+    pub node: String,
+    /// A regular expression for the text of the node, with whitespace collapsed. Any node if left out.
+    pub pattern: Option<String>,
+    /// A regular expression for the text of nodes that are never reported.
+    pub unless: Option<String>,
+    pub message: String,
+    #[serde(default = "default_severity")]
+    pub severity: RuleLevel,
+}
+
+fn default_severity() -> RuleLevel {
+    RuleLevel::Warn
+}
+
+/// The kinds of nodes that custom rules can look at, by their name in the Marlowe grammar.
+pub const NODE_KINDS: &[(&str, Rule)] = &[
+    ("Close", Rule::Close), ("When", Rule::When), ("Pay", Rule::Pay), ("If", Rule::If),
+    ("Let", Rule::Let), ("Assert", Rule::Assert),
+    ("Case", Rule::Case), ("Deposit", Rule::Deposit), ("Choice", Rule::Choice), ("Notify", Rule::Notify),
+    ("Role", Rule::Role), ("PK", Rule::PK), ("PayeeAccount", Rule::PayeeAccount), ("PayeeParty", Rule::PayeeParty),
+    ("ADA", Rule::ADA), ("Currency", Rule::Currency), ("ChoiceId", Rule::ChoiceId), ("Bound", Rule::Bound),
+    ("TimeConstant", Rule::TimeConstant), ("TimeParam", Rule::TimeParam),
+    ("Constant", Rule::Constant), ("ConstantParam", Rule::ConstantParam), ("AvailableMoney", Rule::AvailableMoney),
+    ("ChoiceValue", Rule::ChoiceValue), ("UseValue", Rule::UseValue), ("Cond", Rule::Cond),
+    ("AddValue", Rule::AddValue), ("SubValue", Rule::SubValue), ("MulValue", Rule::MulValue),
+    ("DivValue", Rule::DivValue), ("NegValue", Rule::NegValue),
+    ("TimeIntervalStart", Rule::TimeIntervalStart), ("TimeIntervalEnd", Rule::TimeIntervalEnd),
+    ("AndObs", Rule::AndObs), ("OrObs", Rule::OrObs), ("NotObs", Rule::NotObs),
+    ("ChoseSomething", Rule::ChoseSomething), ("TrueObs", Rule::TrueObs), ("FalseObs", Rule::FalseObs),
+    ("ValueEQ", Rule::ValueEQ), ("ValueGE", Rule::ValueGE), ("ValueGT", Rule::ValueGT),
+    ("ValueLE", Rule::ValueLE), ("ValueLT", Rule::ValueLT),
+];
+
+struct PatternLint {
+    id: String,
+    node: Rule,
+    pattern: Option<Regex>,
+    unless: Option<Regex>,
+    message: String,
+    // None for rules that are off
+    severity: Option<DiagnosticSeverity>,
+}
+
+impl PatternLint {
+    fn new(rule: &CustomRule) -> Result<PatternLint, String> {
+        let id = rule.id.to_uppercase();
+        if id.is_empty() || crate::rules::by_id(&id).is_some() || id == "ML000" {
+            return Err(format!("Custom rule '{}': the id must not be empty or the id of a built-in rule.", rule.id))
+        }
+        let node = NODE_KINDS.iter().find(|(name, _)| *name == rule.node).map(|(_, kind)| *kind)
+            .ok_or_else(|| format!("Custom rule {id}: unknown node kind '{}', expected one of {}.", rule.node,
+                NODE_KINDS.iter().map(|(name, _)| *name).collect::<Vec<&str>>().join(", ")))?;
+        let regex = |pattern: &Option<String>| pattern.as_deref().map(Regex::new).transpose()
+            .map_err(|e| format!("Custom rule {id}: invalid pattern: {e}"));
+        Ok(PatternLint {
+            node,
+            pattern: regex(&rule.pattern)?,
+            unless: regex(&rule.unless)?,
+            message: rule.message.clone(),
+            severity: rule.severity.severity(),
+            id,
+        })
+    }
+}
+
+impl Lint for PatternLint {
+    fn check(&self, node: &Node, _context: &NodeContext, items: &mut Vec<ValidationItem>) {
+        let Some(severity) = self.severity.filter(|_| node.as_rule() == self.node) else { return };
+        let text = normalized_text(node);
+        if self.pattern.iter().all(|p| p.is_match(&text)) && !self.unless.iter().any(|u| u.is_match(&text)) {
+            items.push(note(node, &self.id, &self.message, severity))
+        }
+    }
+}
+
+/// Problems with the custom rules of a configuration.
+pub fn problems(custom_rules: &[CustomRule]) -> Vec<String> {
+    custom_rules.iter().filter_map(|rule| PatternLint::new(rule).err()).collect()
+}
@@ -0,0 +1,124 @@
+// Merkleization: case continuations replaced by the hash of their on-chain
+// representation (MerkleizedCase), so that only the part of the contract that
+// is needed next has to be in the datum.
+//
+// The continuations live in a continuation map next to the contract, which maps
+// each hash to its continuation in Marlowe JSON (nested cases are merkleized too):
+//
+//   escrow.merkleized.marlowe.json -> escrow.continuations.json
+//
+//   {
+//       "8e2a..": { "when": [ { "case": { .. }, "merkleized_then": "51fc.." } ], .. },
+//       "51fc..": "close"
+//   }
+//
+// A list of [hash, continuation] pairs is accepted as well. Merkleized cases are
+// resolved from the map when a JSON document is translated to the DSL, so the
+// rest of the server sees a normal contract.
+
+use std::collections::HashMap;
+use lsp_types::{Diagnostic, DiagnosticSeverity, NumberOrString, Range, Url};
+use serde_json::{Map, Value as Json};
+use marlowe_lang::types::marlowe::Contract;
+use crate::contract_model::parse_contract;
+use crate::core_json::{contract_to_merkleized_json, describe_holes};
+use crate::json_document::{JsonDocument, JsonNode, JsonValue, parse_json};
+use crate::plutus_data::DataEncoder;
+
+pub const EXTENSION: &str = "continuations.json";
+
+#[derive(Debug)]
+pub struct ContinuationMap {
+    pub uri: Url,
+    pub continuations: HashMap<String, JsonNode>,
+    /// Why the file could not be read, if it could not.
+    pub problem: Option<(String, Range)>,
+}
+
+pub fn is_continuation_map(uri: &Url) -> bool {
+    uri.path().ends_with(&format!(".{EXTENSION}"))
+}
+
+/// The continuation map of a contract: <file_stem>.continuations.json
+pub fn candidate(contract: &Url) -> Option<Url> {
+    contract.join(&format!("{}.{EXTENSION}", crate::file_stem(contract))).ok()
+}
+
+pub fn parse(uri: &Url, text: &str) -> ContinuationMap {
+    let mut map = ContinuationMap { uri: uri.clone(), continuations: HashMap::new(), problem: None };
+    let root = match parse_json(text) {
+        Ok(root) => root,
+        Err(problem) => {
+            map.problem = Some(problem);
+            return map
+        }
+    };
+    let range = root.range;
+    match root.value {
+        JsonValue::Object(entries) => map.continuations.extend(entries),
+        JsonValue::Array(items) => for item in items {
+            let item_range = item.range;
+            match item.value {
+                JsonValue::Array(pair) if pair.len() == 2 => {
+                    let mut pair = pair.into_iter();
+                    match (pair.next(), pair.next()) {
+                        (Some(JsonNode { value: JsonValue::String(hash), .. }), Some(continuation)) => { map.continuations.insert(hash, continuation); }
+                        _ => map.problem = Some((String::from("Expected a pair of a hash and a continuation."), item_range)),
+                    }
+                }
+                _ => map.problem = Some((String::from("Expected a pair of a hash and a continuation."), item_range)),
+            }
+        },
+        _ => map.problem = Some((String::from("Expected an object with continuations by hash."), range)),
+    }
+    map
+}
+
+/// Reads the continuation map of a contract from disk, if there is one.
+pub fn load(contract: &Url) -> Option<ContinuationMap> {
+    let uri = candidate(contract)?;
+    let text = crate::read_file(&uri)?;
+    Some(parse(&uri, &text))
+}
+
+/// The hash of a continuation: blake2b-256 of its Plutus Data, with its own cases merkleized.
+pub fn continuation_hash(contract: &Contract) -> String {
+    DataEncoder { merkleize: true, ..Default::default() }.contract(contract).hash()
+}
+
+/// Merkleizes a DSL contract. Returns the merkleized contract and its continuation map, both as pretty printed JSON.
+pub fn merkleize(source: &str) -> Result<(String, String), String> {
+    let parsed = parse_contract(source)?;
+    let (contract, continuations) = contract_to_merkleized_json(&parsed.contract).map_err(|holes| describe_holes(&holes, &parsed))?;
+    let map: Map<String, Json> = continuations.into_iter().collect();
+    let pretty = |json: &Json| serde_json::to_string_pretty(json).map_err(|e| e.to_string());
+    Ok((pretty(&contract)?, pretty(&Json::Object(map))?))
+}
+
+/// Continuations in the map that do not have the hash they are stored under.
+pub fn check_hashes(document: &JsonDocument) -> Vec<Diagnostic> {
+    document.merkleized_cases.iter().filter_map(|case| {
+        let dsl = document.continuation_dsl(case);
+        let dsl = dsl.strip_prefix('(').and_then(|d| d.strip_suffix(')')).unwrap_or(&dsl);
+        let actual = continuation_hash(&parse_contract(dsl).ok()?.contract);
+        if actual == case.hash {
+            return None
+        }
+        Some(Diagnostic {
+            range: case.hash_range,
+            severity: Some(DiagnosticSeverity::WARNING),
+            code: Some(NumberOrString::String("ML070".to_string())),
+            message: format!("The continuation stored under {} in the continuation map has the hash {actual}.", case.hash),
+            ..Default::default()
+        })
+    }).collect()
+}
+
+/// A description of the merkleized case whose hash is at the position, for hovers.
+pub fn describe_at(document: &JsonDocument, map: Option<&ContinuationMap>, position: lsp_types::Position) -> Option<String> {
+    let case = document.merkleized_cases.iter().find(|c| c.hash_range.start <= position && position <= c.hash_range.end)?;
+    let file = map.and_then(|m| m.uri.path_segments()?.next_back().map(String::from)).unwrap_or_default();
+    let dsl = document.continuation_dsl(case);
+    let dsl = if dsl.chars().count() > 300 { format!("{}..", dsl.chars().take(300).collect::<String>()) } else { dsl };
+    Some(format!("Merkleized continuation {} from {file}:\n\n{dsl}", case.hash))
+}
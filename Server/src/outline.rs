@@ -0,0 +1,116 @@
+// Document outline (textDocument/documentSymbol): one symbol per contract node,
+// nested the same way as the contract itself. Cases are listed below their When,
+// and the timeout continuation of a When gets its own "Timeout" symbol so that it
+// is not mistaken for one of the cases.
+
+use lsp_types::{DocumentSymbol, Range, SymbolKind};
+use marlowe_lang::types::marlowe::*;
+use crate::contract_model::{ContractPath, ParsedContract, child_at, child_count};
+use crate::json_document::MerkleizedCase;
+
+fn show<T: std::fmt::Display>(item: &Option<T>, hole: &str) -> String {
+    match item {
+        Some(item) => item.to_string(),
+        None => hole.to_string(),
+    }
+}
+
+#[allow(deprecated)] // DocumentSymbol::deprecated
+fn symbol(name: &str, detail: String, kind: SymbolKind, range: Range, children: Vec<DocumentSymbol>) -> DocumentSymbol {
+    DocumentSymbol {
+        name: name.to_string(),
+        detail: Some(detail),
+        kind,
+        tags: None,
+        deprecated: None,
+        range,
+        selection_range: range,
+        children: if children.is_empty() { None } else { Some(children) },
+    }
+}
+
+fn action_name(action: &Option<Action>) -> &'static str {
+    match action {
+        Some(Action::Deposit { .. }) => "Deposit",
+        Some(Action::Choice { .. }) => "Choice",
+        Some(Action::Notify { .. }) => "Notify",
+        None => "?action",
+    }
+}
+
+fn child_symbols(parsed: &ParsedContract, contract: &Contract, path: &mut ContractPath) -> Vec<DocumentSymbol> {
+    let mut result = vec![];
+    for index in 0..child_count(contract) {
+        if let Some(child) = child_at(contract, index) {
+            path.push(index);
+            if let Some(child_symbol) = contract_symbol(parsed, child, path) {
+                result.push(child_symbol)
+            }
+            path.pop();
+        }
+    }
+    result
+}
+
+fn contract_symbol(parsed: &ParsedContract, contract: &Contract, path: &mut ContractPath) -> Option<DocumentSymbol> {
+    let range = *parsed.contract_ranges.get(path)?;
+    let result = match contract {
+        Contract::Close => symbol("Close", String::from("Refunds all accounts"), SymbolKind::NULL, range, vec![]),
+        Contract::When { when, timeout, timeout_continuation } => {
+            let mut children = vec![];
+            for (index, case) in when.iter().enumerate() {
+                path.push(index);
+                if let (Some(case), Some(case_range)) = (case, parsed.case_ranges.get(path)) {
+                    let continuation = case.then.as_deref().and_then(|c| contract_symbol(parsed, c, path));
+                    children.push(symbol(action_name(&case.case), show(&case.case, "?action"), SymbolKind::ENUM_MEMBER, *case_range, continuation.into_iter().collect()))
+                }
+                path.pop();
+            }
+            path.push(when.len());
+            let continuation = timeout_continuation.as_deref().and_then(|c| contract_symbol(parsed, c, path));
+            if let (Some(continuation), Some(continuation_range)) = (continuation, parsed.contract_ranges.get(path)) {
+                children.push(symbol("Timeout", show(timeout, "?timeout"), SymbolKind::EVENT, *continuation_range, vec![continuation]))
+            }
+            path.pop();
+            symbol("When", format!("{} case(s), timeout {}", when.len(), show(timeout, "?timeout")), SymbolKind::STRUCT, range, children)
+        }
+        Contract::If { r#if, .. } =>
+            symbol("If", show(r#if, "?observation"), SymbolKind::BOOLEAN, range, child_symbols(parsed, contract, path)),
+        Contract::Assert { assert, .. } =>
+            symbol("Assert", show(assert, "?observation"), SymbolKind::BOOLEAN, range, child_symbols(parsed, contract, path)),
+        Contract::Let { r#let, be, .. } =>
+            symbol("Let", format!("\"{}\" = {}", r#let, show(be, "?value")), SymbolKind::VARIABLE, range, child_symbols(parsed, contract, path)),
+        Contract::Pay { from_account, to, token, pay, .. } =>
+            symbol("Pay", format!("{} pays {} {} to {}", show(from_account, "?party"), show(pay, "?value"), show(token, "?token"), show(to, "?payee")),
+                SymbolKind::FUNCTION, range, child_symbols(parsed, contract, path)),
+    };
+    Some(result)
+}
+
+/// The outline of a parsed contract.
+pub fn document_symbols(parsed: &ParsedContract) -> Vec<DocumentSymbol> {
+    contract_symbol(parsed, &parsed.contract, &mut vec![]).into_iter().collect()
+}
+
+/// Replaces all ranges in an outline, used for documents that were translated to the DSL.
+pub fn map_ranges(symbols: &mut [DocumentSymbol], f: &dyn Fn(Range) -> Range) {
+    for s in symbols {
+        s.range = f(s.range);
+        s.selection_range = s.range;
+        if let Some(children) = &mut s.children {
+            map_ranges(children, f)
+        }
+    }
+}
+
+/// Marks the cases of a JSON document whose continuation was resolved from a continuation map.
+pub fn mark_merkleized(symbols: &mut [DocumentSymbol], cases: &[MerkleizedCase]) {
+    for s in symbols {
+        if let Some(case) = cases.iter().find(|c| c.case_range == s.range && s.kind == SymbolKind::ENUM_MEMBER) {
+            s.detail = Some(format!("{} (merkleized {})", s.detail.clone().unwrap_or_default(), case.hash));
+        }
+        if let Some(children) = &mut s.children {
+            mark_merkleized(children, cases)
+        }
+    }
+}
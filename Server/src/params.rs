@@ -0,0 +1,486 @@
+// Parameter files: values for the TimeParam and ConstantParam parameters of a
+// contract, in a JSON or TOML file next to it:
+//
+//   escrow.marlowe -> escrow.params.json or escrow.params.toml
+//
+//   {
+//       "timeParams": { "Payment deadline": 1700000000000 },
+//       "constantParams": { "Price": 450000000 }
+//   }
+//
+//   [timeParams]
+//   "Payment deadline" = 1700000000000
+//   [constantParams]
+//   Price = 450000000
+//
+// Times are POSIX times in milliseconds. The editor watches these files
+// (workspace/didChangeWatchedFiles), so contracts are validated again when their
+// parameter file changes, and TimeParam values from the file are used by the
+// timeout checks in the same way as the timeParams validation setting.
+
+use std::collections::HashMap;
+use lsp_types::{Diagnostic, DiagnosticRelatedInformation, DiagnosticSeverity, Location, NumberOrString, Position, Range, Url};
+use crate::json_document::{JsonValue, parse_json};
+use crate::term_sheet::{ContractFact, FactKind, ParameterKind};
+use marlowe_lang::types::marlowe::{Action, Contract, Observation, Timeout, Value};
+
+pub const EXTENSIONS: [&str; 2] = ["params.json", "params.toml"];
+
+/// Sections of a parameter file and the kind of parameter they contain.
+const SECTIONS: [(&str, ParameterKind); 2] = [
+    ("timeParams", ParameterKind::TimeParam),
+    ("constantParams", ParameterKind::ConstantParam),
+];
+
+fn kind_name(kind: ParameterKind) -> &'static str {
+    match kind {
+        ParameterKind::TimeParam => "TimeParam",
+        ParameterKind::ConstantParam => "ConstantParam",
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct ParamEntry {
+    pub kind: ParameterKind,
+    pub name: String,
+    /// None if the value in the file does not have the right type.
+    pub value: Option<i64>,
+    pub range: Range,
+}
+
+#[derive(Clone, Debug)]
+pub struct ParamFile {
+    pub uri: Url,
+    pub entries: Vec<ParamEntry>,
+    /// Syntax and type errors in the file.
+    pub problems: Vec<(Range, String)>,
+}
+
+impl ParamFile {
+
+    pub fn get(&self, kind: ParameterKind, name: &str) -> Option<&ParamEntry> {
+        self.entries.iter().find(|e| e.kind == kind && e.name == name)
+    }
+
+    /// Values of all valid entries of one kind.
+    pub fn values(&self, kind: ParameterKind) -> HashMap<String, i64> {
+        self.entries.iter()
+            .filter(|e| e.kind == kind)
+            .filter_map(|e| Some((e.name.clone(), e.value?)))
+            .collect()
+    }
+
+    fn file_name(&self) -> String {
+        self.uri.path_segments().and_then(|mut s| s.next_back()).unwrap_or_default().to_string()
+    }
+}
+
+pub fn is_param_file(uri: &Url) -> bool {
+    EXTENSIONS.iter().any(|extension| uri.path().ends_with(&format!(".{extension}")))
+}
+
+/// The parameter files that a contract could have, in order of preference.
+pub fn candidates(contract: &Url) -> Vec<Url> {
+    let stem = crate::file_stem(contract);
+    EXTENSIONS.iter().filter_map(|extension| contract.join(&format!("{stem}.{extension}")).ok()).collect()
+}
+
+/// Reads the parameter file of a contract from disk, if it has one.
+pub fn load(contract: &Url) -> Option<ParamFile> {
+    candidates(contract).into_iter().find_map(|uri| {
+        let text = crate::read_file(&uri)?;
+        Some(parse(&uri, &text))
+    })
+}
+
+fn line_range(text: &str, line: usize) -> Range {
+    let length = text.lines().nth(line).map(|l| l.chars().count()).unwrap_or_default();
+    Range::new(Position::new(line as u32, 0), Position::new(line as u32, length as u32))
+}
+
+/// Finds the line that sets a key in a TOML table, since the toml crate does not keep spans.
+fn toml_key_range(text: &str, table: &str, key: &str) -> Range {
+    let mut current_table = String::new();
+    for (line, content) in text.lines().enumerate() {
+        let content = content.trim();
+        if content.starts_with('[') {
+            current_table = content.trim_matches(|c| c == '[' || c == ']').trim().trim_matches('"').to_string();
+            if table.is_empty() && current_table == key {
+                return line_range(text, line)
+            }
+        } else if let Some((k, _)) = content.split_once('=') {
+            if current_table == table && k.trim().trim_matches(|c| c == '"' || c == '\'') == key {
+                return line_range(text, line)
+            }
+        }
+    }
+    Range::default()
+}
+
+fn expected(kind: ParameterKind, name: &str, found: &str) -> String {
+    match kind {
+        ParameterKind::TimeParam => format!("Expected a POSIX time in milliseconds (an integer) for (TimeParam \"{name}\"), found {found}."),
+        ParameterKind::ConstantParam => format!("Expected an integer for (ConstantParam \"{name}\"), found {found}."),
+    }
+}
+
+fn parse_json_file(uri: &Url, text: &str) -> ParamFile {
+    let mut file = ParamFile { uri: uri.clone(), entries: vec![], problems: vec![] };
+    let root = match parse_json(text) {
+        Ok(root) => root,
+        Err((message, range)) => {
+            file.problems.push((range, message));
+            return file
+        }
+    };
+    let fields = match &root.value {
+        JsonValue::Object(fields) => fields,
+        _ => {
+            file.problems.push((root.range, String::from("Expected an object with 'timeParams' and 'constantParams'.")));
+            return file
+        }
+    };
+    for (section, node) in fields {
+        let kind = match SECTIONS.iter().find(|(name, _)| name == section) {
+            Some((_, kind)) => *kind,
+            None => {
+                file.problems.push((node.range, format!("Unknown section '{section}', expected 'timeParams' or 'constantParams'.")));
+                continue
+            }
+        };
+        let params = match &node.value {
+            JsonValue::Object(params) => params,
+            _ => {
+                file.problems.push((node.range, format!("Expected '{section}' to be an object with a value for each parameter.")));
+                continue
+            }
+        };
+        for (name, value) in params {
+            let parsed = match &value.value {
+                JsonValue::Number(n) => n.parse::<i64>().map_err(|_| format!("the number {n}")),
+                JsonValue::String(_) => Err(String::from("a string")),
+                JsonValue::Bool(_) => Err(String::from("a boolean")),
+                JsonValue::Null => Err(String::from("null")),
+                JsonValue::Array(_) => Err(String::from("an array")),
+                JsonValue::Object(_) => Err(String::from("an object")),
+            };
+            if let Err(found) = &parsed {
+                file.problems.push((value.range, expected(kind, name, found)));
+            }
+            file.entries.push(ParamEntry { kind, name: name.clone(), value: parsed.ok(), range: value.range });
+        }
+    }
+    file
+}
+
+fn parse_toml_file(uri: &Url, text: &str) -> ParamFile {
+    let mut file = ParamFile { uri: uri.clone(), entries: vec![], problems: vec![] };
+    let root = match toml::from_str::<serde_json::Value>(text) {
+        Ok(serde_json::Value::Object(root)) => root,
+        Ok(_) => return file,
+        Err(e) => {
+            let range = e.line_col().map(|(line, _)| line_range(text, line)).unwrap_or_default();
+            file.problems.push((range, e.to_string()));
+            return file
+        }
+    };
+    for (section, params) in &root {
+        let kind = match SECTIONS.iter().find(|(name, _)| name == section) {
+            Some((_, kind)) => *kind,
+            None => {
+                file.problems.push((toml_key_range(text, "", section), format!("Unknown section '{section}', expected 'timeParams' or 'constantParams'.")));
+                continue
+            }
+        };
+        let params = match params.as_object() {
+            Some(params) => params,
+            None => {
+                file.problems.push((toml_key_range(text, "", section), format!("Expected '{section}' to be a table with a value for each parameter.")));
+                continue
+            }
+        };
+        for (name, value) in params {
+            let range = toml_key_range(text, section, name);
+            let parsed = match value {
+                serde_json::Value::Number(n) => n.as_i64().ok_or_else(|| format!("the number {n}")),
+                serde_json::Value::String(_) => Err(String::from("a string")),
+                serde_json::Value::Bool(_) => Err(String::from("a boolean")),
+                serde_json::Value::Array(_) => Err(String::from("an array")),
+                _ => Err(String::from("a table")),
+            };
+            if let Err(found) = &parsed {
+                file.problems.push((range, expected(kind, name, found)));
+            }
+            file.entries.push(ParamEntry { kind, name: name.clone(), value: parsed.ok(), range });
+        }
+    }
+    file
+}
+
+/// The parameters used in a contract, from the facts collected by the validator.
+pub fn used_params(facts: &[ContractFact]) -> Vec<(String, ParameterKind, Range)> {
+    facts.iter().filter_map(|fact| match &fact.kind {
+        FactKind::Parameter { name, kind } => Some((name.clone(), *kind, fact.range)),
+        _ => None,
+    }).collect()
+}
+
+/// Parses a parameter file, collecting every problem rather than stopping at the first one.
+pub fn parse(uri: &Url, text: &str) -> ParamFile {
+    if uri.path().ends_with(".toml") {
+        parse_toml_file(uri, text)
+    } else {
+        parse_json_file(uri, text)
+    }
+}
+
+fn diagnostic(range: Range, code: &str, severity: DiagnosticSeverity, message: String) -> Diagnostic {
+    Diagnostic {
+        range,
+        severity: Some(severity),
+        code: Some(NumberOrString::String(code.to_string())),
+        message,
+        ..Default::default()
+    }
+}
+
+/// Checks a parameter file against the parameters that a contract uses.
+/// Returns the diagnostics for the contract and the diagnostics for the parameter file.
+pub fn check(file: &ParamFile, used: &[(String, ParameterKind, Range)]) -> (Vec<Diagnostic>, Vec<Diagnostic>) {
+    let mut contract_diagnostics = vec![];
+    for (name, kind, range) in used {
+        if file.get(*kind, name).is_none() {
+            contract_diagnostics.push(diagnostic(*range, "ML050", DiagnosticSeverity::WARNING,
+                format!("No value for ({} \"{name}\") in {}.", kind_name(*kind), file.file_name())));
+        }
+    }
+
+    let mut file_diagnostics: Vec<Diagnostic> = file.problems.iter()
+        .map(|(range, message)| diagnostic(*range, "ML052", DiagnosticSeverity::ERROR, message.clone()))
+        .collect();
+    for entry in &file.entries {
+        if !used.iter().any(|(name, kind, _)| *kind == entry.kind && *name == entry.name) {
+            file_diagnostics.push(diagnostic(entry.range, "ML053", DiagnosticSeverity::WARNING,
+                format!("Unused parameter: the contract does not use ({} \"{}\").", kind_name(entry.kind), entry.name)));
+        }
+    }
+    (contract_diagnostics, file_diagnostics)
+}
+
+/// Replaces every parameter that a contract uses with its value from the parameter file.
+pub fn instantiate(source: &str, file: &ParamFile, used: &[(String, ParameterKind, Range)]) -> Result<String, String> {
+    if let Some((range, message)) = file.problems.first() {
+        return Err(format!("{}, line {}: {message}", file.file_name(), range.start.line + 1))
+    }
+    let mut missing: Vec<String> = used.iter()
+        .filter(|(name, kind, _)| file.get(*kind, name).and_then(|e| e.value).is_none())
+        .map(|(name, kind, _)| format!("({} \"{name}\")", kind_name(*kind)))
+        .collect();
+    missing.sort();
+    missing.dedup();
+    if !missing.is_empty() {
+        return Err(format!("{} has no value for: {}", file.file_name(), missing.join(", ")))
+    }
+    let mut contract = crate::contract_model::parse_contract(source)?.contract;
+    let filler = Filler {
+        constants: file.values(ParameterKind::ConstantParam),
+        times: file.values(ParameterKind::TimeParam),
+    };
+    filler.contract(&mut contract);
+    Ok(format!("{contract:#}"))
+}
+
+/// Fills in the parameters of a contract, each kind from its own values.
+struct Filler {
+    constants: HashMap<String, i64>,
+    times: HashMap<String, i64>,
+}
+
+impl Filler {
+    fn contract(&self, contract: &mut Contract) {
+        match contract {
+            Contract::Close => {}
+            Contract::When { when, timeout, timeout_continuation } => {
+                for case in when.iter_mut().flatten() {
+                    if let Some(action) = &mut case.case {
+                        self.action(action)
+                    }
+                    self.boxed_contract(&mut case.then)
+                }
+                if let Some(Timeout::TimeParam(name)) = timeout {
+                    if let Some(time) = self.times.get(name) {
+                        *timeout = Some(Timeout::TimeConstant(*time))
+                    }
+                }
+                self.boxed_contract(timeout_continuation)
+            }
+            Contract::If { r#if, then, r#else } => {
+                if let Some(observation) = r#if {
+                    self.observation(observation)
+                }
+                self.boxed_contract(then);
+                self.boxed_contract(r#else)
+            }
+            Contract::Assert { assert, then } => {
+                if let Some(observation) = assert {
+                    self.observation(observation)
+                }
+                self.boxed_contract(then)
+            }
+            Contract::Let { be, then, .. } => {
+                self.boxed_value(be);
+                self.boxed_contract(then)
+            }
+            Contract::Pay { pay, then, .. } => {
+                if let Some(value) = pay {
+                    self.value(value)
+                }
+                self.boxed_contract(then)
+            }
+        }
+    }
+
+    fn boxed_contract(&self, contract: &mut Option<Box<Contract>>) {
+        if let Some(contract) = contract {
+            self.contract(contract)
+        }
+    }
+
+    fn action(&self, action: &mut Action) {
+        match action {
+            Action::Deposit { deposits: Some(value), .. } => self.value(value),
+            Action::Notify { notify_if: Some(observation) } => self.observation(observation),
+            _ => {}
+        }
+    }
+
+    fn boxed_value(&self, value: &mut Option<Box<Value>>) {
+        if let Some(value) = value {
+            self.value(value)
+        }
+    }
+
+    fn boxed_observation(&self, observation: &mut Option<Box<Observation>>) {
+        if let Some(observation) = observation {
+            self.observation(observation)
+        }
+    }
+
+    fn value(&self, value: &mut Value) {
+        match value {
+            Value::ConstantParam(name) => {
+                if let Some(constant) = self.constants.get(name) {
+                    *value = Value::ConstantValue(*constant)
+                }
+            }
+            Value::NegValue(a) => self.boxed_value(a),
+            Value::AddValue(a, b) | Value::SubValue(a, b) | Value::MulValue(a, b) | Value::DivValue(a, b) => {
+                self.boxed_value(a);
+                self.boxed_value(b)
+            }
+            Value::Cond(o, a, b) => {
+                if let Some(observation) = o {
+                    self.observation(observation)
+                }
+                self.boxed_value(a);
+                self.boxed_value(b)
+            }
+            Value::TimeIntervalStart | Value::TimeIntervalEnd | Value::AvailableMoney(..) |
+            Value::ConstantValue(_) | Value::UseValue(_) | Value::ChoiceValue(_) => {}
+        }
+    }
+
+    fn observation(&self, observation: &mut Observation) {
+        match observation {
+            Observation::ValueGT { value, gt_than: other } |
+            Observation::ValueGE { value, ge_than: other } |
+            Observation::ValueLT { value, lt_than: other } |
+            Observation::ValueLE { value, le_than: other } |
+            Observation::ValueEQ { value, equal_to: other } => {
+                self.boxed_value(value);
+                self.boxed_value(other)
+            }
+            Observation::AndObs { both: a, and: b } | Observation::OrObs { either: a, or: b } => {
+                self.boxed_observation(a);
+                self.boxed_observation(b)
+            }
+            Observation::NotObs { not } => self.boxed_observation(not),
+            Observation::True | Observation::False | Observation::ChoseSomething(_) => {}
+        }
+    }
+}
+
+/// Warns about names that are used both as a TimeParam and as a ConstantParam.
+pub fn kind_conflicts(contract: &Url, used: &[(String, ParameterKind, Range)]) -> Vec<Diagnostic> {
+    let mut result = vec![];
+    for (name, kind, range) in used {
+        let others: Vec<&(String, ParameterKind, Range)> = used.iter().filter(|(n, k, _)| n == name && k != kind).collect();
+        if others.is_empty() {
+            continue
+        }
+        let other_kind = kind_name(others[0].1);
+        let mut d = diagnostic(*range, "ML051", DiagnosticSeverity::WARNING, format!(
+            "\"{name}\" is used both as a TimeParam and as a ConstantParam. Tools that fill in parameters by name may give both the same value. Consider renaming the {}.",
+            kind_name(*kind)));
+        d.related_information = Some(others.iter().map(|(_, _, other)| DiagnosticRelatedInformation {
+            location: Location::new(contract.clone(), *other),
+            message: format!("Used as a {other_kind} here"),
+        }).collect());
+        result.push(d)
+    }
+    result
+}
+
+/// The text of a new parameter file with a placeholder value for every parameter that
+/// the validator found. TimeParams get times one day apart (starting tomorrow), with
+/// the timeouts of nested Whens after the ones they are nested in, so that the timeouts
+/// of the contract increase. ConstantParams get 0.
+pub fn scaffold(uri: &Url, facts: &[ContractFact], now: i64) -> String {
+    const DAY: i64 = 24 * 60 * 60 * 1000;
+    let contains = |outer: &Range, inner: &Range| outer.start <= inner.start && inner.end <= outer.end;
+    let depth = |range: &Range| facts.iter()
+        .filter(|f| matches!(f.kind, FactKind::Deadline { .. }) && contains(&f.range, range))
+        .count();
+    let mut sorted = used_params(facts);
+    sorted.sort_by_key(|(_, _, range)| (depth(range), range.start));
+
+    let mut sections: Vec<(ParameterKind, Vec<String>)> = vec![(ParameterKind::TimeParam, vec![]), (ParameterKind::ConstantParam, vec![])];
+    for (name, kind, _) in &sorted {
+        let names = &mut sections.iter_mut().find(|(k, _)| k == kind).unwrap().1;
+        if !names.contains(name) {
+            names.push(name.clone())
+        }
+    }
+    let start = now - now.rem_euclid(DAY) + DAY;
+    let value = |kind: ParameterKind, index: usize| match kind {
+        ParameterKind::TimeParam => start + DAY * index as i64,
+        ParameterKind::ConstantParam => 0,
+    };
+    let section_name = |kind: ParameterKind| SECTIONS.iter().find(|(_, k)| *k == kind).unwrap().0;
+    let quote = |s: &str| serde_json::to_string(s).unwrap_or_default();
+
+    if uri.path().ends_with(".toml") {
+        let mut out = String::new();
+        for (kind, names) in &sections {
+            out.push_str(&format!("[{}]\n", section_name(*kind)));
+            for (index, name) in names.iter().enumerate() {
+                out.push_str(&format!("{} = {}\n", quote(name), value(*kind, index)));
+            }
+            out.push('\n');
+        }
+        out.trim_end().to_string() + "\n"
+    } else {
+        // Written by hand to keep the parameters in the order they are used
+        let sections: Vec<String> = sections.iter().map(|(kind, names)| {
+            let values: Vec<String> = names.iter().enumerate()
+                .map(|(index, name)| format!("        {}: {}", quote(name), value(*kind, index)))
+                .collect();
+            if values.is_empty() {
+                format!("    {}: {{}}", quote(section_name(*kind)))
+            } else {
+                format!("    {}: {{\n{}\n    }}", quote(section_name(*kind)), values.join(",\n"))
+            }
+        }).collect();
+        format!("{{\n{}\n}}\n", sections.join(",\n"))
+    }
+}
@@ -0,0 +1,628 @@
+// The on-chain representation of Marlowe contracts: Plutus Data, serialized as CBOR.
+//
+// The constructor indexes follow the ToData instances in
+// Language.Marlowe.Core.V1.Semantics.Types, and the CBOR encoding follows
+// the one used by the Cardano ledger for Plutus Data (tags 121-127 and
+// 1280-1400 for constructors, indefinite length lists, byte strings split
+// in chunks of 64 bytes).
+//
+// Decoding accepts any valid encoding of Plutus Data (definite or indefinite
+// lengths), not only the canonical one we write.
+//
+// With `DataEncoder::merkleize`, case continuations are replaced by the hash of
+// their own (merkleized) encoding, as in MerkleizedCase.
+//
+// Things that have no on-chain representation (holes, TimeParam and
+// ConstantParam without a value) are replaced with placeholders of a
+// realistic size, and recorded in `DataEncoder::missing`.
+
+use std::collections::{BTreeSet, HashMap};
+use marlowe_lang::types::marlowe::*;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum PlutusData {
+    Constr(u64, Vec<PlutusData>),
+    Map(Vec<(PlutusData, PlutusData)>),
+    List(Vec<PlutusData>),
+    Integer(i128),
+    Bytes(Vec<u8>),
+}
+
+/// A time in 2030, used in place of timeouts that we don't know the value of.
+pub const PLACEHOLDER_TIME: i64 = 1_900_000_000_000;
+/// Used in place of amounts and other numbers that we don't know the value of.
+pub const PLACEHOLDER_AMOUNT: i64 = 1_000_000_000;
+
+fn write_header(out: &mut Vec<u8>, major: u8, n: u64) {
+    let major = major << 5;
+    if n < 24 {
+        out.push(major | n as u8)
+    } else if n <= u8::MAX as u64 {
+        out.push(major | 24);
+        out.push(n as u8)
+    } else if n <= u16::MAX as u64 {
+        out.push(major | 25);
+        out.extend((n as u16).to_be_bytes())
+    } else if n <= u32::MAX as u64 {
+        out.push(major | 26);
+        out.extend((n as u32).to_be_bytes())
+    } else {
+        out.push(major | 27);
+        out.extend(n.to_be_bytes())
+    }
+}
+
+fn write_bytes(out: &mut Vec<u8>, bytes: &[u8]) {
+    if bytes.len() <= 64 {
+        write_header(out, 2, bytes.len() as u64);
+        out.extend(bytes)
+    } else {
+        out.push(0x5f);
+        for chunk in bytes.chunks(64) {
+            write_header(out, 2, chunk.len() as u64);
+            out.extend(chunk)
+        }
+        out.push(0xff)
+    }
+}
+
+fn write_list(out: &mut Vec<u8>, items: &[PlutusData]) {
+    if items.is_empty() {
+        out.push(0x80)
+    } else {
+        out.push(0x9f);
+        for item in items {
+            item.write_cbor(out)
+        }
+        out.push(0xff)
+    }
+}
+
+impl PlutusData {
+
+    fn write_cbor(&self, out: &mut Vec<u8>) {
+        match self {
+            PlutusData::Constr(index, fields) => {
+                match index {
+                    0..=6 => write_header(out, 6, 121 + index),
+                    7..=127 => write_header(out, 6, 1280 + index - 7),
+                    _ => {
+                        write_header(out, 6, 102);
+                        out.push(0x82);
+                        write_header(out, 0, *index);
+                    }
+                }
+                write_list(out, fields)
+            }
+            PlutusData::Map(entries) => {
+                write_header(out, 5, entries.len() as u64);
+                for (key, value) in entries {
+                    key.write_cbor(out);
+                    value.write_cbor(out)
+                }
+            }
+            PlutusData::List(items) => write_list(out, items),
+            PlutusData::Integer(n) => {
+                if *n >= 0 && *n <= u64::MAX as i128 {
+                    write_header(out, 0, *n as u64)
+                } else if *n < 0 && -1 - *n <= u64::MAX as i128 {
+                    write_header(out, 1, (-1 - *n) as u64)
+                } else {
+                    // Big integers are tagged byte strings
+                    let (tag, magnitude) = if *n >= 0 { (2, *n as u128) } else { (3, (-1 - *n) as u128) };
+                    write_header(out, 6, tag);
+                    let bytes = magnitude.to_be_bytes();
+                    let first = bytes.iter().position(|b| *b != 0).unwrap_or(bytes.len() - 1);
+                    write_bytes(out, &bytes[first..])
+                }
+            }
+            PlutusData::Bytes(bytes) => write_bytes(out, bytes),
+        }
+    }
+
+    pub fn to_cbor(&self) -> Vec<u8> {
+        let mut out = vec![];
+        self.write_cbor(&mut out);
+        out
+    }
+
+    pub fn encoded_size(&self) -> usize {
+        self.to_cbor().len()
+    }
+
+    /// The hash that Plutus uses for data (blake2b-256 of the CBOR), as hex.
+    pub fn hash(&self) -> String {
+        to_hex(blake2b_simd::Params::new().hash_length(32).hash(&self.to_cbor()).as_bytes())
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    offset: usize,
+}
+
+impl Reader<'_> {
+
+    fn error<T>(&self, message: &str) -> Result<T, String> {
+        Err(format!("Invalid CBOR at byte {}: {message}", self.offset))
+    }
+
+    fn byte(&mut self) -> Result<u8, String> {
+        match self.bytes.get(self.offset) {
+            Some(b) => { self.offset += 1; Ok(*b) }
+            None => self.error("unexpected end of data"),
+        }
+    }
+
+    fn take(&mut self, n: usize) -> Result<&[u8], String> {
+        match self.bytes.get(self.offset..self.offset.saturating_add(n)) {
+            Some(taken) => { self.offset += n; Ok(taken) }
+            None => self.error("unexpected end of data"),
+        }
+    }
+
+    fn peek_break(&mut self) -> Result<bool, String> {
+        match self.bytes.get(self.offset) {
+            Some(0xff) => { self.offset += 1; Ok(true) }
+            Some(_) => Ok(false),
+            None => self.error("unexpected end of data"),
+        }
+    }
+
+    /// The major type and argument of the next item. The argument is None for indefinite lengths.
+    fn header(&mut self) -> Result<(u8, Option<u64>), String> {
+        let initial = self.byte()?;
+        let (major, info) = (initial >> 5, initial & 0x1f);
+        let n = match info {
+            0..=23 => info as u64,
+            24 => self.byte()? as u64,
+            25 => u16::from_be_bytes(self.take(2)?.try_into().unwrap()) as u64,
+            26 => u32::from_be_bytes(self.take(4)?.try_into().unwrap()) as u64,
+            27 => u64::from_be_bytes(self.take(8)?.try_into().unwrap()),
+            31 if matches!(major, 2 | 4 | 5) => return Ok((major, None)),
+            _ => return self.error(&format!("unsupported initial byte 0x{initial:02x}")),
+        };
+        Ok((major, Some(n)))
+    }
+
+    fn bytes(&mut self, length: Option<u64>) -> Result<Vec<u8>, String> {
+        match length {
+            Some(n) => Ok(self.take(n as usize)?.to_vec()),
+            None => {
+                let mut bytes = vec![];
+                while !self.peek_break()? {
+                    match self.header()? {
+                        (2, Some(n)) => bytes.extend(self.take(n as usize)?),
+                        _ => return self.error("expected a chunk of a byte string"),
+                    }
+                }
+                Ok(bytes)
+            }
+        }
+    }
+
+    fn items(&mut self, length: Option<u64>) -> Result<Vec<PlutusData>, String> {
+        let mut items = vec![];
+        match length {
+            Some(n) => for _ in 0..n { items.push(self.data()?) },
+            None => while !self.peek_break()? { items.push(self.data()?) },
+        }
+        Ok(items)
+    }
+
+    fn fields(&mut self) -> Result<Vec<PlutusData>, String> {
+        match self.header()? {
+            (4, length) => self.items(length),
+            _ => self.error("expected the fields of a constructor"),
+        }
+    }
+
+    fn data(&mut self) -> Result<PlutusData, String> {
+        let (major, argument) = self.header()?;
+        match (major, argument) {
+            (0, Some(n)) => Ok(PlutusData::Integer(n as i128)),
+            (1, Some(n)) => Ok(PlutusData::Integer(-1 - n as i128)),
+            (2, length) => Ok(PlutusData::Bytes(self.bytes(length)?)),
+            (4, length) => Ok(PlutusData::List(self.items(length)?)),
+            (5, length) => {
+                let mut entries = vec![];
+                let mut entry = |reader: &mut Self| -> Result<(), String> {
+                    let key = reader.data()?;
+                    entries.push((key, reader.data()?));
+                    Ok(())
+                };
+                match length {
+                    Some(n) => for _ in 0..n { entry(self)? },
+                    None => while !self.peek_break()? { entry(self)? },
+                }
+                Ok(PlutusData::Map(entries))
+            }
+            (6, Some(tag @ 121..=127)) => Ok(PlutusData::Constr(tag - 121, self.fields()?)),
+            (6, Some(tag @ 1280..=1400)) => Ok(PlutusData::Constr(tag - 1280 + 7, self.fields()?)),
+            (6, Some(102)) => match self.header()? {
+                (4, Some(2)) => match self.header()? {
+                    (0, Some(index)) => Ok(PlutusData::Constr(index, self.fields()?)),
+                    _ => self.error("expected a constructor index"),
+                },
+                _ => self.error("expected a constructor index and its fields"),
+            },
+            (6, Some(tag @ (2 | 3))) => {
+                let magnitude = match self.header()? {
+                    (2, length) => self.bytes(length)?,
+                    _ => return self.error("expected the bytes of a big integer"),
+                };
+                let first = magnitude.iter().position(|b| *b != 0).unwrap_or(magnitude.len());
+                if magnitude.len() - first > 15 {
+                    return self.error("integer too large")
+                }
+                let n = magnitude[first..].iter().fold(0i128, |n, b| (n << 8) | *b as i128);
+                Ok(PlutusData::Integer(if tag == 2 { n } else { -1 - n }))
+            }
+            (6, Some(tag)) => self.error(&format!("unexpected tag {tag}")),
+            _ => self.error(&format!("unexpected major type {major}")),
+        }
+    }
+}
+
+impl PlutusData {
+
+    pub fn from_cbor(bytes: &[u8]) -> Result<PlutusData, String> {
+        let mut reader = Reader { bytes, offset: 0 };
+        let data = reader.data()?;
+        if reader.offset < bytes.len() {
+            return reader.error("unexpected data after the end")
+        }
+        Ok(data)
+    }
+
+    /// Reads hex CBOR, ignoring whitespace and an optional 0x prefix.
+    pub fn from_hex(text: &str) -> Result<PlutusData, String> {
+        let text: String = text.chars().filter(|c| !c.is_whitespace()).collect();
+        let text = text.strip_prefix("0x").unwrap_or(&text);
+        let bytes: Option<Vec<u8>> = text.as_bytes().chunks(2).map(|pair| match pair {
+            [_, _] => u8::from_str_radix(std::str::from_utf8(pair).ok()?, 16).ok(),
+            _ => None,
+        }).collect();
+        match bytes {
+            Some(bytes) if !bytes.is_empty() => PlutusData::from_cbor(&bytes),
+            _ => Err(String::from("Expected CBOR as a hex string.")),
+        }
+    }
+
+    pub fn to_hex(&self) -> String {
+        to_hex(&self.to_cbor())
+    }
+}
+
+pub fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+/// Hex strings (currency symbols, public key hashes) as bytes. Anything that is not
+/// valid hex is taken as text, so that we still get a size out of it.
+pub fn hex_bytes(text: &str) -> Vec<u8> {
+    let decoded: Option<Vec<u8>> = text.as_bytes().chunks(2).map(|pair| match pair {
+        [_, _] => u8::from_str_radix(std::str::from_utf8(pair).ok()?, 16).ok(),
+        _ => None,
+    }).collect();
+    decoded.unwrap_or_else(|| text.as_bytes().to_vec())
+}
+
+fn constr(index: u64, fields: Vec<PlutusData>) -> PlutusData {
+    PlutusData::Constr(index, fields)
+}
+
+fn int(n: i64) -> PlutusData {
+    PlutusData::Integer(n as i128)
+}
+
+fn text(s: &str) -> PlutusData {
+    PlutusData::Bytes(s.as_bytes().to_vec())
+}
+
+/// Converts contracts to Plutus Data.
+#[derive(Default)]
+pub struct DataEncoder {
+    pub time_params: HashMap<String, i64>,
+    pub constant_params: HashMap<String, i64>,
+    /// Encode cases as MerkleizedCase, with the hash of their continuation.
+    pub merkleize: bool,
+    /// What had to be replaced with a placeholder, like "a hole of type 'Contract'"
+    /// or "(TimeParam \"deadline\")", without duplicates.
+    pub missing: BTreeSet<String>,
+}
+
+impl DataEncoder {
+
+    fn hole(&mut self, kind: &str) {
+        self.missing.insert(format!("a hole of type '{kind}'"));
+    }
+
+    fn boxed_contract(&mut self, contract: &Option<Box<Contract>>) -> PlutusData {
+        match contract {
+            Some(c) => self.contract(c),
+            None => { self.hole("Contract"); constr(0, vec![]) }
+        }
+    }
+
+    pub fn contract(&mut self, contract: &Contract) -> PlutusData {
+        match contract {
+            Contract::Close => constr(0, vec![]),
+            Contract::Pay { from_account, to, token, pay, then } => {
+                let fields = vec![self.party(from_account), self.payee(to), self.token(token), self.value(pay), self.boxed_contract(then)];
+                constr(1, fields)
+            }
+            Contract::If { r#if, then, r#else } => {
+                let fields = vec![self.observation(r#if), self.boxed_contract(then), self.boxed_contract(r#else)];
+                constr(2, fields)
+            }
+            Contract::When { when, timeout, timeout_continuation } => {
+                let cases = when.iter().map(|case| self.case(case)).collect();
+                let fields = vec![PlutusData::List(cases), self.timeout(timeout), self.boxed_contract(timeout_continuation)];
+                constr(3, fields)
+            }
+            Contract::Let { r#let, be, then } => {
+                let be = self.boxed_value(be);
+                constr(4, vec![text(r#let), be, self.boxed_contract(then)])
+            }
+            Contract::Assert { assert, then } => {
+                let fields = vec![self.observation(assert), self.boxed_contract(then)];
+                constr(5, fields)
+            }
+        }
+    }
+
+    pub fn timeout(&mut self, timeout: &Option<Timeout>) -> PlutusData {
+        match timeout {
+            Some(Timeout::TimeConstant(t)) => int(*t),
+            Some(Timeout::TimeParam(name)) => match self.time_params.get(name) {
+                Some(t) => int(*t),
+                None => {
+                    self.missing.insert(format!("(TimeParam \"{name}\")"));
+                    int(PLACEHOLDER_TIME)
+                }
+            },
+            None => { self.hole("Timeout"); int(PLACEHOLDER_TIME) }
+        }
+    }
+
+    pub fn case(&mut self, case: &Option<Case>) -> PlutusData {
+        match case {
+            Some(Case { case, then: Some(then) }) if self.merkleize => {
+                let action = self.action(case);
+                let hash = self.contract(then).hash();
+                constr(1, vec![action, PlutusData::Bytes(hex_bytes(&hash))])
+            }
+            Some(Case { case, then }) => {
+                let action = self.action(case);
+                constr(0, vec![action, self.boxed_contract(then)])
+            }
+            None => {
+                self.hole("Case");
+                constr(0, vec![constr(2, vec![constr(9, vec![])]), constr(0, vec![])])
+            }
+        }
+    }
+
+    pub fn action(&mut self, action: &Option<Action>) -> PlutusData {
+        match action {
+            Some(Action::Deposit { party, of_token, into_account, deposits }) => {
+                let fields = vec![self.party(into_account), self.party(party), self.token(of_token), self.value(deposits)];
+                constr(0, fields)
+            }
+            Some(Action::Choice { for_choice, choose_between }) => {
+                let choice_id = self.choice_id(for_choice);
+                let bounds = choose_between.iter().map(|bound| match bound {
+                    Some(Bound(from, to)) => constr(0, vec![int(*from), int(*to)]),
+                    None => { self.hole("Bound"); constr(0, vec![int(0), int(0)]) }
+                }).collect();
+                constr(1, vec![choice_id, PlutusData::List(bounds)])
+            }
+            Some(Action::Notify { notify_if }) => constr(2, vec![self.observation(notify_if)]),
+            None => { self.hole("Action"); constr(2, vec![constr(9, vec![])]) }
+        }
+    }
+
+    pub fn party(&mut self, party: &Option<Party>) -> PlutusData {
+        match party {
+            Some(Party::PK { pk_hash }) => constr(0, vec![PlutusData::Bytes(hex_bytes(pk_hash))]),
+            Some(Party::Role { role_token }) => constr(1, vec![text(role_token)]),
+            None => { self.hole("Party"); constr(1, vec![text("")]) }
+        }
+    }
+
+    pub fn payee(&mut self, payee: &Option<Payee>) -> PlutusData {
+        match payee {
+            Some(Payee::Account(party)) => constr(0, vec![self.party(party)]),
+            Some(Payee::Party(party)) => constr(1, vec![self.party(party)]),
+            None => { self.hole("Payee"); constr(0, vec![constr(1, vec![text("")])]) }
+        }
+    }
+
+    pub fn token(&mut self, token: &Option<Token>) -> PlutusData {
+        match token {
+            Some(Token::ADA) => constr(0, vec![text(""), text("")]),
+            Some(Token::Custom { currency_symbol, token_name }) =>
+                constr(0, vec![PlutusData::Bytes(hex_bytes(currency_symbol)), text(token_name)]),
+            None => { self.hole("Token"); constr(0, vec![text(""), text("")]) }
+        }
+    }
+
+    pub fn choice_id(&mut self, choice_id: &Option<ChoiceId>) -> PlutusData {
+        match choice_id {
+            Some(ChoiceId { choice_name, choice_owner }) => constr(0, vec![text(choice_name), self.party(choice_owner)]),
+            None => { self.hole("ChoiceId"); constr(0, vec![text(""), constr(1, vec![text("")])]) }
+        }
+    }
+
+    fn boxed_value(&mut self, value: &Option<Box<Value>>) -> PlutusData {
+        match value {
+            Some(v) => self.value_ref(v),
+            None => { self.hole("Value"); constr(1, vec![int(0)]) }
+        }
+    }
+
+    pub fn value(&mut self, value: &Option<Value>) -> PlutusData {
+        match value {
+            Some(v) => self.value_ref(v),
+            None => { self.hole("Value"); constr(1, vec![int(0)]) }
+        }
+    }
+
+    fn value_ref(&mut self, value: &Value) -> PlutusData {
+        match value {
+            Value::AvailableMoney(party, token) => {
+                let fields = vec![self.party(party), self.token(token)];
+                constr(0, fields)
+            }
+            Value::ConstantValue(n) => constr(1, vec![int(*n)]),
+            Value::ConstantParam(name) => match self.constant_params.get(name) {
+                Some(n) => constr(1, vec![int(*n)]),
+                None => {
+                    self.missing.insert(format!("(ConstantParam \"{name}\")"));
+                    constr(1, vec![int(PLACEHOLDER_AMOUNT)])
+                }
+            },
+            Value::NegValue(v) => constr(2, vec![self.boxed_value(v)]),
+            Value::AddValue(a, b) => { let a = self.boxed_value(a); constr(3, vec![a, self.boxed_value(b)]) }
+            Value::SubValue(a, b) => { let a = self.boxed_value(a); constr(4, vec![a, self.boxed_value(b)]) }
+            Value::MulValue(a, b) => { let a = self.boxed_value(a); constr(5, vec![a, self.boxed_value(b)]) }
+            Value::DivValue(a, b) => { let a = self.boxed_value(a); constr(6, vec![a, self.boxed_value(b)]) }
+            Value::ChoiceValue(choice_id) => constr(7, vec![self.choice_id(choice_id)]),
+            Value::TimeIntervalStart => constr(8, vec![]),
+            Value::TimeIntervalEnd => constr(9, vec![]),
+            Value::UseValue(name) => constr(10, vec![text(name)]),
+            Value::Cond(obs, a, b) => {
+                let obs = self.observation(obs);
+                let a = self.boxed_value(a);
+                constr(11, vec![obs, a, self.boxed_value(b)])
+            }
+        }
+    }
+
+    fn boxed_observation(&mut self, observation: &Option<Box<Observation>>) -> PlutusData {
+        match observation {
+            Some(o) => self.observation_ref(o),
+            None => { self.hole("Observation"); constr(9, vec![]) }
+        }
+    }
+
+    pub fn observation(&mut self, observation: &Option<Observation>) -> PlutusData {
+        match observation {
+            Some(o) => self.observation_ref(o),
+            None => { self.hole("Observation"); constr(9, vec![]) }
+        }
+    }
+
+    fn observation_ref(&mut self, observation: &Observation) -> PlutusData {
+        let values = |this: &mut Self, index: u64, a: &Option<Box<Value>>, b: &Option<Box<Value>>| {
+            let a = this.boxed_value(a);
+            constr(index, vec![a, this.boxed_value(b)])
+        };
+        match observation {
+            Observation::AndObs { both, and } => { let a = self.boxed_observation(both); constr(0, vec![a, self.boxed_observation(and)]) }
+            Observation::OrObs { either, or } => { let a = self.boxed_observation(either); constr(1, vec![a, self.boxed_observation(or)]) }
+            Observation::NotObs { not } => constr(2, vec![self.boxed_observation(not)]),
+            Observation::ChoseSomething(choice_id) => constr(3, vec![self.choice_id(choice_id)]),
+            Observation::ValueGE { value, ge_than } => values(self, 4, value, ge_than),
+            Observation::ValueGT { value, gt_than } => values(self, 5, value, gt_than),
+            Observation::ValueLT { value, lt_than } => values(self, 6, value, lt_than),
+            Observation::ValueLE { value, le_than } => values(self, 7, value, le_than),
+            Observation::ValueEQ { value, equal_to } => values(self, 8, value, equal_to),
+            Observation::True => constr(9, vec![]),
+            Observation::False => constr(10, vec![]),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_cbor(data: PlutusData, hex: &str) {
+        assert_eq!(data.to_hex(), hex, "{data:?}");
+        assert_eq!(PlutusData::from_hex(hex), Ok(data));
+    }
+
+    fn bytes(length: usize) -> Vec<u8> {
+        (0..length).map(|i| i as u8).collect()
+    }
+
+    #[test]
+    fn constructors() {
+        assert_cbor(constr(0, vec![]), "d87980");
+        assert_cbor(constr(6, vec![]), "d87f80");
+        // 7..127 are tags 1280..1400
+        assert_cbor(constr(7, vec![]), "d9050080");
+        assert_cbor(constr(127, vec![]), "d9057880");
+        // Larger indexes are tag 102 with the index and the fields
+        assert_cbor(constr(128, vec![]), "d86682188080");
+        assert_cbor(constr(1000, vec![int(1)]), "d866821903e89f01ff");
+    }
+
+    #[test]
+    fn integers() {
+        assert_cbor(int(0), "00");
+        assert_cbor(int(23), "17");
+        assert_cbor(int(24), "1818");
+        assert_cbor(int(-1), "20");
+        assert_cbor(int(-500), "3901f3");
+        assert_cbor(PlutusData::Integer(u64::MAX as i128), "1bffffffffffffffff");
+        assert_cbor(PlutusData::Integer(-1 - u64::MAX as i128), "3bffffffffffffffff");
+        // Beyond 64 bits they are big integers, tag 2 (positive) or 3 (negative) with the bytes
+        assert_cbor(PlutusData::Integer(1 << 64), "c249010000000000000000");
+        assert_cbor(PlutusData::Integer(-1 - (1 << 64)), "c349010000000000000000");
+    }
+
+    #[test]
+    fn byte_strings_are_split_in_chunks_of_64_bytes() {
+        assert_cbor(PlutusData::Bytes(vec![]), "40");
+        assert_cbor(PlutusData::Bytes(bytes(64)), &format!("5840{}", to_hex(&bytes(64))));
+        let long = bytes(130);
+        assert_cbor(PlutusData::Bytes(long.clone()), &format!("5f5840{}5840{}42{}ff", to_hex(&long[..64]), to_hex(&long[64..128]), to_hex(&long[128..])));
+    }
+
+    #[test]
+    fn lists_are_indefinite_unless_empty() {
+        assert_cbor(PlutusData::List(vec![]), "80");
+        assert_cbor(PlutusData::List(vec![int(1), int(2)]), "9f0102ff");
+        assert_cbor(constr(1, vec![PlutusData::List(vec![int(1)]), text("a")]), "d87a9f9f01ff4161ff");
+        assert_cbor(PlutusData::Map(vec![(int(1), int(2))]), "a10102");
+    }
+
+    #[test]
+    fn definite_lengths_are_read_too() {
+        assert_eq!(PlutusData::from_hex("d8798201 02"), Ok(constr(0, vec![int(1), int(2)])));
+        assert_eq!(PlutusData::from_hex("0x820102"), Ok(PlutusData::List(vec![int(1), int(2)])));
+        assert_eq!(PlutusData::from_hex("5f4101420203ff"), Ok(PlutusData::Bytes(vec![1, 2, 3])));
+        assert_eq!(PlutusData::from_hex("bf0102ff"), Ok(PlutusData::Map(vec![(int(1), int(2))])));
+    }
+
+    #[test]
+    fn hashes() {
+        // The well known hash of the unit datum, which is also how Close is encoded
+        assert_eq!(constr(0, vec![]).hash(), "923918e403bf43c34b4ef6b48eb2ee04babed17320d8d1b9ff9ad086e86f44ec");
+        assert_eq!(DataEncoder::default().contract(&Contract::Close).hash(), "923918e403bf43c34b4ef6b48eb2ee04babed17320d8d1b9ff9ad086e86f44ec");
+        assert_eq!(constr(7, vec![]).hash(), "357ea89304163aa90e1bde5c87f453b082758b3268759b9d7ec41f8c7f971049");
+    }
+
+    #[test]
+    fn decoding_what_was_encoded_gives_the_same_data() {
+        let nested = constr(200, vec![
+            PlutusData::Map(vec![(text("key"), PlutusData::List(vec![int(-3), PlutusData::Integer(i128::from(i64::MAX) * 4)]))]),
+            PlutusData::Bytes(bytes(200)),
+            PlutusData::List(vec![constr(8, vec![]), PlutusData::List(vec![])]),
+        ]);
+        for data in [nested, PlutusData::Integer(-(1 << 100)), PlutusData::Integer((1 << 119) - 1), constr(u64::MAX, vec![int(0)])] {
+            assert_eq!(PlutusData::from_cbor(&data.to_cbor()), Ok(data.clone()), "{}", data.to_hex());
+        }
+    }
+
+    #[test]
+    fn invalid_cbor_is_an_error() {
+        assert!(PlutusData::from_hex("9f01").is_err());
+        assert!(PlutusData::from_hex("0102").is_err());
+        assert!(PlutusData::from_hex("c3").is_err());
+        assert!(PlutusData::from_hex("xyz").is_err());
+    }
+}
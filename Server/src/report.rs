@@ -0,0 +1,141 @@
+// Lint results as SARIF 2.1 (for code review tools) and JUnit XML (for CI test
+// reports). Each diagnostic is reported under the rule it comes from (see rules).
+//
+// SARIF lists every rule of the server in tool.driver.rules, so results can refer
+// to them by index. Columns are UTF-16 code units, like in LSP.
+//
+// JUnit has one test suite per file and one failed test case per diagnostic.
+// Files without diagnostics get a single passing test case.
+
+use lsp_types::{Diagnostic, DiagnosticSeverity, Position};
+use serde_json::{json, Value as Json};
+use crate::rules::{RULES, rule_for, rule_id};
+
+/// The diagnostics of one file.
+#[derive(Debug)]
+pub struct FileReport {
+    pub path: String,
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+/// Parser errors are published without a severity, editors show them as errors.
+pub fn severity(diagnostic: &Diagnostic) -> DiagnosticSeverity {
+    diagnostic.severity.unwrap_or(DiagnosticSeverity::ERROR)
+}
+
+pub fn severity_name(severity: DiagnosticSeverity) -> &'static str {
+    match severity {
+        DiagnosticSeverity::ERROR => "error",
+        DiagnosticSeverity::WARNING => "warning",
+        DiagnosticSeverity::INFORMATION => "information",
+        _ => "hint",
+    }
+}
+
+fn sarif_level(severity: DiagnosticSeverity) -> &'static str {
+    match severity {
+        DiagnosticSeverity::ERROR => "error",
+        DiagnosticSeverity::WARNING => "warning",
+        _ => "note",
+    }
+}
+
+fn sarif_region(start: Position, end: Position) -> Json {
+    json!({
+        "startLine": start.line + 1,
+        "startColumn": start.character + 1,
+        "endLine": end.line + 1,
+        "endColumn": end.character + 1,
+    })
+}
+
+pub fn sarif(files: &[FileReport]) -> Json {
+    let rules: Vec<Json> = RULES.iter().map(|rule| json!({
+        "id": rule.id,
+        "name": rule.name,
+        "shortDescription": { "text": rule.description },
+        "defaultConfiguration": { "level": sarif_level(rule.default_severity) },
+    })).collect();
+    let mut results = vec![];
+    for file in files {
+        let uri = file.path.replace('\\', "/");
+        for diagnostic in &file.diagnostics {
+            let related: Vec<Json> = diagnostic.related_information.iter().flatten().enumerate().map(|(i, info)| json!({
+                "id": i,
+                "message": { "text": info.message },
+                "physicalLocation": {
+                    "artifactLocation": { "uri": uri },
+                    "region": sarif_region(info.location.range.start, info.location.range.end),
+                },
+            })).collect();
+            let mut result = json!({
+                "ruleId": rule_id(diagnostic),
+                "level": sarif_level(severity(diagnostic)),
+                "message": { "text": diagnostic.message },
+                "locations": [{
+                    "physicalLocation": {
+                        "artifactLocation": { "uri": uri },
+                        "region": sarif_region(diagnostic.range.start, diagnostic.range.end),
+                    },
+                }],
+            });
+            // Custom rules are not in the list of rules of the tool
+            if let Some(index) = RULES.iter().position(|r| r.id == rule_id(diagnostic)) {
+                result["ruleIndex"] = json!(index);
+            }
+            if !related.is_empty() {
+                result["relatedLocations"] = Json::Array(related);
+            }
+            results.push(result)
+        }
+    }
+    json!({
+        "$schema": "https://json.schemastore.org/sarif-2.1.0.json",
+        "version": "2.1.0",
+        "runs": [{
+            "tool": {
+                "driver": {
+                    "name": "marlowe_lsp",
+                    "version": env!("CARGO_PKG_VERSION"),
+                    "rules": rules,
+                },
+            },
+            "columnKind": "utf16CodeUnits",
+            "results": results,
+        }],
+    })
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;").replace('\n', "&#10;")
+}
+
+pub fn junit(files: &[FileReport]) -> String {
+    let total: usize = files.iter().map(|f| f.diagnostics.len().max(1)).sum();
+    let failures: usize = files.iter().map(|f| f.diagnostics.len()).sum();
+    let mut out = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    out.push_str(&format!("<testsuites name=\"marlowe_lsp\" tests=\"{total}\" failures=\"{failures}\">\n"));
+    for file in files {
+        let path = escape(&file.path);
+        let count = file.diagnostics.len();
+        out.push_str(&format!("  <testsuite name=\"{path}\" tests=\"{}\" failures=\"{count}\" errors=\"0\">\n", count.max(1)));
+        if count == 0 {
+            out.push_str(&format!("    <testcase classname=\"{path}\" name=\"{path}\"/>\n"));
+        }
+        for diagnostic in &file.diagnostics {
+            let name = match rule_for(diagnostic) {
+                Some(rule) => format!("{} {}", rule.id, rule.name),
+                None => rule_id(diagnostic).to_string(),
+            };
+            let start = diagnostic.range.start;
+            let location = format!("{}:{}:{}", file.path, start.line + 1, start.character + 1);
+            out.push_str(&format!("    <testcase classname=\"{path}\" name=\"{} ({})\">\n", escape(&name), escape(&location)));
+            out.push_str(&format!("      <failure type=\"{}\" message=\"{}\">{}: {}</failure>\n",
+                severity_name(severity(diagnostic)), escape(&diagnostic.message), escape(&location), escape(&diagnostic.message)));
+            out.push_str("    </testcase>\n");
+        }
+        out.push_str("  </testsuite>\n");
+    }
+    out.push_str("</testsuites>\n");
+    out
+}
@@ -0,0 +1,128 @@
+// The checks that the server reports, with stable ids for reports and tools
+// that filter on them. Ids are grouped by topic and never reused:
+//
+//   ML000       syntax
+//   ML001-ML009 timeouts
+//   ML010-ML019 holes
+//   ML020-ML029 choices
+//   ML030-ML039 unreachable code
+//   ML040-ML049 static analysis (explorer)
+//   ML050-ML059 parameters
+//   ML060-ML069 on-chain size
+//   ML070-ML079 merkleization
+//
+// Diagnostics carry the rule id as their code, "marlowe" as their source and a
+// link to the description of the rule in docs/rules.md. The extension ships that
+// file next to the server (bin/docs/rules.md), which is linked when it is there,
+// so the description matches the server. Otherwise the link goes to the file in
+// the repository at the release of this version.
+
+use std::sync::OnceLock;
+use lsp_types::{CodeDescription, Diagnostic, DiagnosticSeverity, NumberOrString, Url};
+
+pub const SOURCE: &str = "marlowe";
+
+const RELEASE_DOCUMENTATION: &str = concat!("https://github.com/olofblomqvist/marlowe_vscode_lspc/blob/v", env!("CARGO_PKG_VERSION"), "/Server/docs/rules.md");
+
+#[derive(Debug)]
+pub struct LintRule {
+    pub id: &'static str,
+    pub name: &'static str,
+    pub description: &'static str,
+    pub default_severity: DiagnosticSeverity,
+}
+
+const fn rule(id: &'static str, name: &'static str, description: &'static str, default_severity: DiagnosticSeverity) -> LintRule {
+    LintRule { id, name, description, default_severity }
+}
+
+const ERROR: DiagnosticSeverity = DiagnosticSeverity::ERROR;
+const WARNING: DiagnosticSeverity = DiagnosticSeverity::WARNING;
+
+pub const RULES: &[LintRule] = &[
+    rule("ML000", "syntax-error", "The document is not a valid Marlowe contract (DSL or JSON).", ERROR),
+    rule("ML001", "timeout-not-increasing", "A When nested in a case times out before the When it is reached from.", WARNING),
+    rule("ML002", "timeout-not-after-enclosing", "A When nested in a case times out at the same moment as the When it is reached from.", WARNING),
+    rule("ML003", "when-window-too-short", "A When is open for less than the minimum window after the timeout of the When it is reached from.", WARNING),
+    rule("ML004", "invalid-timeout", "A timeout is not a valid number.", ERROR),
+    rule("ML010", "hole-party", "A party, payee or account is a hole.", WARNING),
+    rule("ML011", "hole-contract", "A contract or continuation is a hole.", WARNING),
+    rule("ML012", "hole-action", "The action of a case is a hole.", WARNING),
+    rule("ML013", "hole-value", "A value is a hole.", WARNING),
+    rule("ML014", "hole-observation", "An observation is a hole.", WARNING),
+    rule("ML015", "hole-timeout", "A timeout is a hole.", WARNING),
+    rule("ML016", "hole-token", "A token is a hole.", WARNING),
+    rule("ML017", "hole-bound", "A bound of a choice is a hole.", WARNING),
+    rule("ML018", "hole-case", "A case is a hole.", WARNING),
+    rule("ML019", "hole", "The contract contains a hole.", WARNING),
+    rule("ML020", "undefined-choice", "A ChoiceValue refers to a choice that no When before it asks for, so it is always 0.", WARNING),
+    rule("ML030", "unreachable-notify", "A case can never be reached because its Notify observation is always false.", WARNING),
+    rule("ML031", "unreachable-choice", "A case can never be reached because its choice has no valid bounds.", WARNING),
+    rule("ML032", "unreachable-timed-out", "The cases of a When can never be reached because the When has always timed out when it is reached.", WARNING),
+    rule("ML033", "duplicate-case", "A case has the same action as an earlier case of the same When, so it can never be used.", WARNING),
+    rule("ML034", "unreachable-branch", "A branch of an If can never be reached because the observation is constant.", WARNING),
+    rule("ML040", "non-positive-deposit", "A deposit of zero or a negative amount can happen.", WARNING),
+    rule("ML041", "non-positive-pay", "A payment of zero or a negative amount can happen.", WARNING),
+    rule("ML042", "partial-pay", "A payment can happen when the account does not have enough to pay it in full.", WARNING),
+    rule("ML043", "shadowing", "A Let can overwrite a value that was already bound.", WARNING),
+    rule("ML044", "assertion-failed", "An Assert can fail.", WARNING),
+    rule("ML050", "missing-parameter-value", "The parameter file of the contract has no value for a parameter that the contract uses.", WARNING),
+    rule("ML051", "parameter-kind-conflict", "The same name is used both as a TimeParam and as a ConstantParam.", WARNING),
+    rule("ML052", "invalid-parameter-file", "The parameter file of the contract could not be read.", ERROR),
+    rule("ML053", "unused-parameter", "The parameter file has a value for a parameter that the contract does not use.", WARNING),
+    rule("ML060", "datum-too-large", "The estimated size of the datum is over the limit.", WARNING),
+    rule("ML061", "transaction-too-large", "A transaction of the contract is estimated to be larger than the maximum transaction size.", WARNING),
+    rule("ML070", "continuation-hash-mismatch", "A continuation in the continuation map does not have the hash it is stored under.", WARNING),
+];
+
+pub fn by_id(id: &str) -> Option<&'static LintRule> {
+    RULES.iter().find(|rule| rule.id == id)
+}
+
+/// The rule id of a diagnostic of the server, its code. Custom rules (see lints.rs) have ids of their own.
+pub fn rule_id(diagnostic: &Diagnostic) -> &str {
+    match &diagnostic.code {
+        Some(NumberOrString::String(code)) => code,
+        _ => RULES[0].id,
+    }
+}
+
+/// The built-in rule that a diagnostic of the server comes from, None for custom rules.
+pub fn rule_for(diagnostic: &Diagnostic) -> Option<&'static LintRule> {
+    by_id(rule_id(diagnostic))
+}
+
+/// docs/rules.md next to the server executable if it is there, the one of the release otherwise.
+fn documentation_base() -> &'static Url {
+    static BASE: OnceLock<Url> = OnceLock::new();
+    BASE.get_or_init(|| {
+        std::env::current_exe().ok()
+            .and_then(|exe| Some(exe.parent()?.join("docs").join("rules.md")))
+            .filter(|path| path.is_file())
+            .and_then(|path| Url::from_file_path(path).ok())
+            .unwrap_or_else(|| Url::parse(RELEASE_DOCUMENTATION).unwrap())
+    })
+}
+
+/// The documentation of a rule, in the docs/rules.md that belongs to this server.
+pub fn documentation(rule: &LintRule) -> Option<Url> {
+    let mut url = documentation_base().clone();
+    url.set_fragment(Some(&format!("{}-{}", rule.id.to_lowercase(), rule.name)));
+    Some(url)
+}
+
+/// Sets the source of the diagnostics, gives the ones without a severity the default severity
+/// of their rule and links their codes to the documentation of the rule.
+pub fn describe(diagnostics: &mut [Diagnostic]) {
+    for diagnostic in diagnostics {
+        diagnostic.source = Some(String::from(SOURCE));
+        let rule = match &diagnostic.code {
+            Some(NumberOrString::String(code)) => by_id(code),
+            _ => None,
+        };
+        if diagnostic.severity.is_none() {
+            diagnostic.severity = rule.map(|rule| rule.default_severity);
+        }
+        diagnostic.code_description = rule.and_then(documentation).map(|href| CodeDescription { href });
+    }
+}
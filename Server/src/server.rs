@@ -0,0 +1,501 @@
+// The language server: the LSP requests and notifications of an editor session, on
+// top of the State of lib.rs. Commands and the simulation requests are in commands.rs
+// and simulation.rs, the network transports in transport.rs.
+
+use super::*;
+use tower_lsp::{ jsonrpc::{Result}, LanguageServer, LspService, Server };
+
+#[tower_lsp::async_trait]
+impl LanguageServer for MyLSPServer {
+    async fn initialize(&self, params: InitializeParams) -> Result<InitializeResult> {
+
+        // Clients can still pass validation settings (timeParams, minimumWhenWindow) as initialization options,
+        // the rest of the configuration is read in initialized
+        {
+            let mut state = self.state.lock().unwrap();
+            state.initialization_options = params.initialization_options.clone();
+            state.workspace_root = config::workspace_root(&params);
+            state.pull_configuration = params.capabilities.workspace.as_ref().and_then(|w| w.configuration).unwrap_or(false);
+        }
+
+        Ok(InitializeResult {
+            server_info: None,
+            capabilities: ServerCapabilities {
+                text_document_sync: Some(TextDocumentSyncCapability::Kind(
+                    TextDocumentSyncKind::INCREMENTAL,
+                )),
+                completion_provider: Some(CompletionOptions {
+                    resolve_provider: Some(false),
+                    trigger_characters: Some(vec![String::from("\"")]),
+                    work_done_progress_options: Default::default(),
+                    all_commit_characters: None,
+                    ..Default::default()
+                }),
+                execute_command_provider: Some(ExecuteCommandOptions {
+                    commands: commands::all(),
+                    work_done_progress_options: Default::default(),
+                }),
+                workspace: Some(WorkspaceServerCapabilities {
+                    workspace_folders: Some(WorkspaceFoldersServerCapabilities {
+                        supported: Some(false),
+                        change_notifications: Some(OneOf::Left(true)),
+                    }),
+                    file_operations: None,
+                }),
+                document_highlight_provider: Some(OneOf::Left(true)),
+                document_symbol_provider: Some(OneOf::Left(true)),
+                code_action_provider: Some(CodeActionProviderCapability::Simple(true)),
+                code_lens_provider: Some(CodeLensOptions { resolve_provider: Some(false) }),
+                semantic_tokens_provider: Some(
+                    SemanticTokensServerCapabilities::SemanticTokensRegistrationOptions(
+                        SemanticTokensRegistrationOptions { 
+                            text_document_registration_options: TextDocumentRegistrationOptions{ 
+                                document_selector: None
+                            }, 
+                            semantic_tokens_options: SemanticTokensOptions{ 
+                                work_done_progress_options: WorkDoneProgressOptions{ 
+                                    work_done_progress: Some(false)
+                                 },
+                                legend: semantic_token_legend(), 
+                                range: Some(false), 
+                                full: Some(SemanticTokensFullOptions::Bool(true)) 
+                        },
+                            static_registration_options: StaticRegistrationOptions::default()
+                        }
+                    )
+                ),
+                hover_provider: Some(HoverProviderCapability::Simple(true)), 
+                document_formatting_provider: Some(OneOf::Left(true)),
+                
+                ..Default::default()
+            },
+            ..Default::default()
+        })
+    }
+
+    async fn hover(&self, params: HoverParams) -> Result<Option<Hover>> {
+        let state = match self.state.lock() {
+            Err(_) => return Ok(None),
+            Ok(l) => l
+        };
+        Ok(get_hover(&state, &params.text_document_position_params.text_document.uri, params.text_document_position_params.position))
+    }
+
+    async fn semantic_tokens_full(
+        &self,
+        params: SemanticTokensParams,
+    ) -> Result<Option<SemanticTokensResult>> {
+        let state = match self.state.lock() {
+            Err(_) => return Ok(None),
+            Ok(l) => l
+        };
+        Ok(get_semantic_tokens(&state, &params.text_document.uri).map(SemanticTokensResult::Tokens))
+    }
+
+
+    async fn document_highlight(
+        &self,
+        params: DocumentHighlightParams,
+    ) -> Result<Option<Vec<DocumentHighlight>>> {
+        
+        // While a simulation is running, we highlight where the simulation is at instead.
+        let uri = &params.text_document_position_params.text_document.uri;
+        let simulation_highlights = {
+            let state = self.state.lock().unwrap();
+            state.simulations.get(uri).map(|s|s.highlights())
+        };
+        if let Some(mut highlights) = simulation_highlights {
+            let state = self.state.lock().unwrap();
+            if let Some(document) = state.json_documents.get(uri) {
+                for h in highlights.iter_mut() { h.range = document.to_json_range(h.range) }
+            }
+            return Ok(Some(highlights))
+        }
+
+        let (toks,position) = {
+            let mut state = self.state.lock().unwrap();
+            let position = match state.json_documents.get(uri) {
+                Some(document) => match document.to_dsl_position(params.text_document_position_params.position) {
+                    Some(position) => position,
+                    None => return Ok(None)
+                },
+                None => params.text_document_position_params.position
+            };
+            match state.marlowe_asts.get_mut(uri) {
+                None => (vec![],position),
+                Some(semantic_tokens) => (semantic_tokens.0.clone(),position)
+            }
+            
+        };
+       
+        let closest = 
+            marlowe_lang::parsing::Rule::get_token_at_position(
+                toks.to_vec(),position
+            );
+        
+        match closest {
+            Some((a,rule,_c)) => {
+                {
+                    self.client.log_message(MessageType::INFO, format!("highlighting selected '{rule:?}'") ).await;        
+                }
+                let range = match self.state.lock().unwrap().json_documents.get(uri) {
+                    Some(document) => document.to_json_range(a),
+                    None => a
+                };
+                Ok(Some(vec![
+                    DocumentHighlight { 
+                        range,
+                        kind: Some(DocumentHighlightKind::TEXT)
+                    }])
+                )
+            }
+            None => Ok(None)
+        }
+
+    }
+
+    async fn initialized(&self, _: InitializedParams) {
+        self.client
+            .log_message(MessageType::INFO, "initialized!")
+            .await;
+
+        // Ask the editor to tell us about changes to parameter files, continuation maps and the configuration file,
+        // see params.rs, merkle.rs and config.rs
+        let mut watchers = DidChangeWatchedFilesRegistrationOptions {
+            watchers: params::EXTENSIONS.iter().chain([merkle::EXTENSION].iter()).map(|extension| FileSystemWatcher { 
+                glob_pattern: format!("**/*.{extension}"), 
+                kind: None 
+            }).collect()
+        };
+        watchers.watchers.push(FileSystemWatcher { glob_pattern: format!("**/{}", config::FILE_NAME), kind: None });
+        let registration = Registration {
+            id: String::from("marlowe-sidecar-files"),
+            method: String::from("workspace/didChangeWatchedFiles"),
+            register_options: serde_json::to_value(watchers).ok()
+        };
+        if let Err(e) = self.client.register_capability(vec![registration]).await {
+            self.client.log_message(MessageType::WARNING, format!("Parameter files, continuation maps and {} will not be reloaded when they change: {e}", config::FILE_NAME)).await
+        }
+
+        // The editor settings could not be asked for before now
+        self.pull_editor_settings().await;
+        self.reload_config().await;
+        self.revalidate_all().await;
+    }
+
+    async fn shutdown(&self) -> Result<()> {  Ok(()) }
+    async fn did_change_workspace_folders(&self, _: DidChangeWorkspaceFoldersParams) {}
+    async fn did_change_configuration(&self, params: DidChangeConfigurationParams) {
+        // Clients that push their settings send them here, the others only tell us that something changed
+        if let Some(settings) = params.settings.get(config::SECTION).filter(|s| s.is_object()) {
+            self.state.lock().unwrap().editor_settings = Some(settings.clone());
+        } else {
+            self.pull_editor_settings().await;
+        }
+        self.reload_config().await;
+        self.revalidate_all().await;
+    }
+    async fn did_change_watched_files(&self, params: DidChangeWatchedFilesParams) {
+        if params.changes.iter().any(|c| c.uri.path().ends_with(&format!("/{}", config::FILE_NAME))) {
+            self.reload_config().await;
+            self.revalidate_all().await;
+        }
+        // Validate every open contract whose parameter file or continuation map changed
+        let changed : Vec<Url> = params.changes.into_iter().map(|c|c.uri).filter(|uri| params::is_param_file(uri) || merkle::is_continuation_map(uri)).collect();
+        if changed.is_empty() { return }
+        let mut results = vec![];
+        {
+            let mut state = self.state.lock().unwrap();
+            let contracts : Vec<Url> = state.sources.keys()
+                .filter(|contract| params::candidates(contract).iter().chain(merkle::candidate(contract).iter()).any(|c|changed.contains(c)))
+                .cloned().collect();
+            for contract in contracts {
+                if let Some(old) = state.param_files.remove(&contract) {
+                    results.push((old.uri,vec![]));
+                }
+                load_param_file(&mut state, &contract);
+                load_continuation_map(&mut state, &contract);
+                revalidate(&mut state, &contract, &mut results);
+            }
+        }
+        for (uri,diagnostics) in results {
+            self.client.publish_diagnostics(uri, diagnostics, None).await;
+        }
+    }
+
+    async fn execute_command(&self, params: ExecuteCommandParams) -> Result<Option<Value>> {
+        self.run_command(params).await
+    }
+
+    async fn did_open(&self, params: DidOpenTextDocumentParams) {
+        let (result,param_file_result) = {   
+            let mut state = self.state.lock().unwrap();
+            get_or_insert_document(&mut state, &params.text_document);
+            (get_diagnostics(&mut state,&params.text_document.uri),get_param_file_diagnostics(&mut state,&params.text_document.uri))
+        };
+        self.client.publish_diagnostics(
+            params.text_document.uri.clone(), 
+            result,
+            None
+        ).await;
+        if let Some((uri,diagnostics)) = param_file_result {
+            self.client.publish_diagnostics(uri, diagnostics, None).await;
+        }
+    }
+
+    async fn did_change(&self, params: DidChangeTextDocumentParams) {
+        
+        let result = {
+            let mut state = self.state.lock().unwrap();
+            state.simulations.remove(&params.text_document.uri);
+            // Changes that arrive after the document was closed are ignored
+            update_document(&mut state, &params.text_document.uri, params.content_changes)
+                .map(|_| (get_diagnostics(&mut state,&params.text_document.uri),get_param_file_diagnostics(&mut state,&params.text_document.uri)))
+        };  
+        let Some(result) = result else { return };
+        self.client.publish_diagnostics(
+            params.text_document.uri, 
+            result.0, 
+            None).await;
+        if let Some((uri,diagnostics)) = result.1 {
+            self.client.publish_diagnostics(uri, diagnostics, None).await;
+        }
+    }
+
+    async fn did_save(&self, _: DidSaveTextDocumentParams) {
+    }
+
+    async fn did_close(&self, params: DidCloseTextDocumentParams) {
+        let param_file = {
+            let mut state = self.state.lock().unwrap();
+            // Closed documents are read from disk again, and not validated when the configuration changes
+            if let Some(id) = state.sources.remove(&params.text_document.uri) {
+                state.files.update(id, String::new());
+                state.closed_files.insert(params.text_document.uri.clone(), id);
+            }
+            state.marlowe_asts.remove(&params.text_document.uri);
+            state.sexpression_asts.remove(&params.text_document.uri);
+            state.path_analysis.remove(&params.text_document.uri);
+            state.size_estimates.remove(&params.text_document.uri);
+            state.json_documents.remove(&params.text_document.uri);
+            state.json_parser_errors.remove(&params.text_document.uri);
+            state.simulations.remove(&params.text_document.uri);
+            state.continuation_maps.remove(&params.text_document.uri);
+            state.suppressions.remove(&params.text_document.uri);
+            state.param_files.remove(&params.text_document.uri)
+        };
+        self.client.publish_diagnostics(params.text_document.uri, vec![], None).await;
+        // Unused entries are only reported while the contract is open
+        if let Some(param_file) = param_file {
+            self.client.publish_diagnostics(param_file.uri, vec![], None).await;
+        }
+    }
+
+    async fn document_symbol(&self, params: DocumentSymbolParams) -> Result<Option<DocumentSymbolResponse>> {
+        let state = self.state.lock().unwrap();
+        Ok(get_document_symbols(&state, &params.text_document.uri).map(DocumentSymbolResponse::Nested))
+    }
+
+    async fn code_action(&self, params: CodeActionParams) -> Result<Option<CodeActionResponse>> {
+        let state = self.state.lock().unwrap();
+        let uri = &params.text_document.uri;
+        let mut actions = vec![];
+
+        // Offer to create a parameter file when the cursor is on a parameter and there is none yet
+        let facts = state.marlowe_asts.get(uri).map(|(_,validation)|validation.facts.as_slice()).unwrap_or_default();
+        let used = params::used_params(facts);
+        let to_document_range = |range:Range| match state.json_documents.get(uri) {
+            Some(document) => document.to_json_range(range),
+            None => range
+        };
+        let on_parameter = used.iter().any(|(_,_,range)| {
+            let range = to_document_range(*range);
+            range.start <= params.range.end && params.range.start <= range.end
+        });
+        if on_parameter && !state.param_files.contains_key(uri) {
+            if let Some(file_uri) = params::candidates(uri).into_iter().next() {
+                let now = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).map(|d|d.as_millis() as i64).unwrap_or_default();
+                let name = file_uri.path_segments().and_then(|mut s|s.next_back()).unwrap_or_default().to_string();
+                actions.push(CodeActionOrCommand::CodeAction(CodeAction {
+                    title: format!("Create parameter file {name}"),
+                    kind: Some(CodeActionKind::QUICKFIX),
+                    edit: Some(commands::create_file_edit(&file_uri, params::scaffold(&file_uri, facts, now))),
+                    ..Default::default()
+                }));
+            }
+        }
+
+        // Suppression comments, only DSL documents can have comments
+        let source = if state.json_documents.contains_key(uri) { None } else { get_source(&state, uri) };
+        for diagnostic in params.context.diagnostics.iter().filter(|_| source.is_some()) {
+            let id = match &diagnostic.code {
+                Some(NumberOrString::String(code)) if code != "ML000" && diagnostic.source.as_deref() == Some(rules::SOURCE) => code,
+                _ => continue
+            };
+            let edit = |edit:TextEdit| WorkspaceEdit { changes: Some(HashMap::from([(uri.clone(),vec![edit])])), ..Default::default() };
+            actions.push(CodeActionOrCommand::CodeAction(CodeAction {
+                title: format!("Suppress this warning ({id})"),
+                kind: Some(CodeActionKind::QUICKFIX),
+                diagnostics: Some(vec![diagnostic.clone()]),
+                edit: Some(edit(suppression::suppress_edit(source.as_deref().unwrap_or_default(), diagnostic, id))),
+                ..Default::default()
+            }));
+            actions.push(CodeActionOrCommand::CodeAction(CodeAction {
+                title: format!("Suppress {id} in this file"),
+                kind: Some(CodeActionKind::QUICKFIX),
+                diagnostics: Some(vec![diagnostic.clone()]),
+                edit: Some(edit(suppression::suppress_file_edit(id))),
+                ..Default::default()
+            }));
+        }
+
+        Ok(if actions.is_empty() { None } else { Some(actions) })
+    }
+
+    async fn formatting(&self, params: DocumentFormattingParams) -> Result<Option<Vec<TextEdit>>> {
+        // The options of the editor are ignored, so that the result is the same as `marlowe_lsp fmt`
+        let state = self.state.lock().unwrap();
+        let uri = &params.text_document.uri;
+        let source = match get_source(&state, uri) {
+            Some(source) => source,
+            None => return Ok(None)
+        };
+        match format::format_document(uri, &source, &state.config.format) {
+            Ok(formatted) if formatted != source => {
+                let end = LineColLookup::new(&source).get(source.len());
+                let range = Range::new(Position::new(0, 0), Position::new(end.0 as u32 - 1, end.1 as u32 - 1));
+                Ok(Some(vec![TextEdit::new(range, formatted)]))
+            }
+            // Documents with syntax errors are left alone, the error is already reported
+            _ => Ok(None)
+        }
+    }
+
+    async fn code_lens(&self, params: CodeLensParams) -> Result<Option<Vec<CodeLens>>> {
+        let state = self.state.lock().unwrap();
+        let uri = &params.text_document.uri;
+        let sizes = match state.size_estimates.get(uri) {
+            Some((_,sizes)) => sizes,
+            None => return Ok(None)
+        };
+        // The size estimate of each When, shown above it. The lenses have no command to run.
+        let lenses = sizes.whens.iter().map(|when| {
+            let range = match state.json_documents.get(uri) {
+                Some(document) => document.to_json_range(when.range),
+                None => when.range
+            };
+            CodeLens {
+                range: Range::new(range.start, range.start),
+                command: Some(Command { title: size_estimate::summary(when), command: String::new(), arguments: None }),
+                data: None
+            }
+        }).collect();
+        Ok(Some(lenses))
+    }
+
+    async fn completion(&self, completion_params: CompletionParams) -> Result<Option<CompletionResponse>> {
+        
+        // If we ever want to do anything more than basic Role name suggestions in here,
+        // this code should be thrown out and replaced completely.
+
+        let (source,col) = {
+            let state = self.state.lock().unwrap();
+            // Closed documents (and positions past the end) have nothing to complete
+            let id = match state.sources.get(&completion_params.text_document_position.text_document.uri) {
+                Some(id) => *id,
+                None => return Ok(None)
+            };
+            let bindex = match codespan_lsp_local::position_to_byte_index(
+                &state.files, id, &completion_params.text_document_position.position) {
+                Ok(bindex) => bindex,
+                Err(_) => return Ok(None)
+            };
+            let src = state.files.source(id).to_owned();
+            (src,bindex)
+        };
+
+        if col < 10 { return Ok(None) }
+
+        match source.get(col - 6 .. col) {
+            None => return Ok(None),
+            Some(prior) => 
+                if prior != "Role \"" {
+                    return Ok(None)
+                }
+        }
+
+        let mut matches : Vec<CompletionItem> = 
+            Regex::new("Role \".*\"")
+                .unwrap()
+                .find_iter(&source).map(|x|{
+                    let s = x.as_str();
+                    CompletionItem {
+                        label: if let Some(xx) = s.get(6..s.len()-1) { xx.to_string() } else { s.to_string() }, 
+                        kind: None, detail: None, 
+                        documentation: None, deprecated: None, preselect: None, sort_text: None, 
+                        filter_text: None, insert_text: None, insert_text_format: None, 
+                        insert_text_mode: None, text_edit: None, additional_text_edits: None, 
+                        command: None, commit_characters: None, data: None, tags: None }
+                }
+                ).collect();
+        
+        if matches.is_empty() {return Ok(None)}
+        matches.sort_by_key(|x|x.label.clone());
+        matches.dedup_by_key(|x|x.label.clone());
+        Ok(Some(
+            lsp_types::CompletionResponse::List(
+                CompletionList { 
+                    is_incomplete: false, 
+                    items: matches
+                }
+            )
+        ))
+    }
+}
+
+/// A language server with a state of its own, for one client, to serve with
+/// tower_lsp::Server over any transport. Used by run, transport.rs and the tests.
+pub fn build_service() -> (LspService<impl LanguageServer>, tower_lsp::ClientSocket) {
+    LspService::build(|client| {
+        MyLSPServer { 
+            client,
+            state: std::sync::Mutex::new(State::new())
+        }
+    })
+        .custom_method("marlowe/simulation/start", MyLSPServer::simulation_start)
+        .custom_method("marlowe/simulation/listPossibleActions", MyLSPServer::simulation_list_possible_actions)
+        .custom_method("marlowe/simulation/applyInput", MyLSPServer::simulation_apply_input)
+        .custom_method("marlowe/simulation/advanceTime", MyLSPServer::simulation_advance_time)
+        .custom_method("marlowe/simulation/undo", MyLSPServer::simulation_undo)
+        .custom_method("marlowe/simulation/getState", MyLSPServer::simulation_get_state)
+        .finish()
+}
+
+/// Runs the command line mode or the language server, depending on the arguments.
+pub async fn run() {
+
+    // Subcommands (check, lint, fmt) run without an editor, anything else
+    // (like the --stdio that editors pass) starts the language server.
+    let args : Vec<String> = std::env::args().skip(1).collect();
+    if let Some(code) = cli::run(&args) {
+        std::process::exit(code);
+    }
+
+    // A shared server for editors that connect over the network, see transport.rs
+    if let Some(address) = transport::listen_address(&args) {
+        if let Err(message) = transport::listen(&address).await {
+            eprintln!("{message}");
+            std::process::exit(2);
+        }
+        return
+    }
+
+    let (service, socket) = build_service();
+    let stdin = tokio::io::stdin();
+    let stdout = tokio::io::stdout();
+
+    let server = Server::new(stdin, stdout, socket);
+
+    server.serve(service).await;
+}
+
@@ -0,0 +1,269 @@
+// Interactive simulation of a document, driven by the client through the
+// marlowe/simulation/* custom requests. This works like the simulator in the
+// Marlowe Playground: the client starts a session for a document, lists the
+// possible actions, applies inputs, moves time forward and can undo each step.
+//
+// Sessions are stored per document in State and are dropped when the document
+// changes, since the contract (and all ranges) might no longer be the same.
+
+use std::collections::{BTreeMap, HashMap};
+use marlowe_lang::types::marlowe::Contract;
+use serde::{Deserialize, Serialize};
+use lsp_types::*;
+#[cfg(not(target_arch = "wasm32"))]
+use tower_lsp::jsonrpc::{Error, Result};
+use crate::contract_model::{ContractPath, ParsedContract, parse_contract, node_at};
+use crate::interpreter::*;
+#[cfg(not(target_arch = "wasm32"))]
+use crate::MyLSPServer;
+use crate::term_sheet::ParameterKind;
+
+#[derive(Debug)]
+pub struct SimulationSession {
+    parsed: ParsedContract,
+    params: HashMap<String, i64>,
+    current: SimulationStep,
+    history: Vec<SimulationStep>,
+}
+
+#[derive(Clone, Debug)]
+struct SimulationStep {
+    path: ContractPath,
+    state: MarloweState,
+    time: i64,
+    payments: Vec<Payment>,
+    warnings: Vec<Warning>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SimulationStartParams {
+    pub text_document: TextDocumentIdentifier,
+    /// Values for all TimeParam and ConstantParam used by the contract.
+    /// Values from the parameter file of the contract are used for the ones that are left out.
+    #[serde(default)]
+    pub params: HashMap<String, i64>,
+    /// POSIX time (ms) at which the simulation starts. Defaults to 0.
+    pub start_time: Option<i64>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SimulationDocumentParams {
+    pub text_document: TextDocumentIdentifier,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SimulationApplyInputParams {
+    pub text_document: TextDocumentIdentifier,
+    /// Index of the case in the current When, as returned by listPossibleActions.
+    pub case_index: usize,
+    /// Required when the case is a Choice.
+    pub chosen_num: Option<i64>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SimulationAdvanceTimeParams {
+    pub text_document: TextDocumentIdentifier,
+    /// The new POSIX time (ms), must not be earlier than the current time.
+    pub time: i64,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AccountView {
+    pub owner: String,
+    pub token: String,
+    pub amount: i64,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ChoiceView {
+    pub choice_name: String,
+    pub choice_owner: String,
+    pub value: i64,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SimulationView {
+    pub time: i64,
+    pub accounts: Vec<AccountView>,
+    pub choices: Vec<ChoiceView>,
+    pub bound_values: BTreeMap<String, i64>,
+    pub payments: Vec<Payment>,
+    pub warnings: Vec<Warning>,
+    pub closed: bool,
+    /// Range of the When (or Close) the contract is currently at.
+    pub current_range: Option<Range>,
+    pub possible_actions: Vec<PossibleAction>,
+    pub can_undo: bool,
+}
+
+impl SimulationSession {
+
+    pub fn start(parsed: ParsedContract, params: HashMap<String, i64>, start_time: i64) -> std::result::Result<Self, String> {
+        let mut step = SimulationStep {
+            path: vec![],
+            state: MarloweState { min_time: start_time, ..Default::default() },
+            time: start_time,
+            payments: vec![],
+            warnings: vec![],
+        };
+        let env = Environment { time_interval: (start_time, start_time), params: params.clone() };
+        let (path, output) = compute_transaction(&parsed.contract, vec![], &env, &mut step.state, None)?;
+        step.path = path;
+        step.payments = output.payments;
+        step.warnings = output.warnings;
+        Ok(SimulationSession { parsed, params, current: step, history: vec![] })
+    }
+
+    fn environment(&self, time: i64) -> Environment {
+        Environment { time_interval: (time, time), params: self.params.clone() }
+    }
+
+    fn is_closed(&self) -> bool {
+        matches!(node_at(&self.parsed.contract, &self.current.path), Some(Contract::Close))
+    }
+
+    pub fn possible_actions(&self) -> Vec<PossibleAction> {
+        let env = self.environment(self.current.time);
+        possible_actions(&self.parsed.contract, &self.current.path, &env, &self.current.state)
+    }
+
+    fn transaction(&mut self, time: i64, input: Option<&Input>) -> std::result::Result<(), String> {
+        let env = self.environment(time);
+        let mut next = self.current.clone();
+        next.time = time;
+        let (path, output) = compute_transaction(&self.parsed.contract, next.path.clone(), &env, &mut next.state, input)?;
+        next.path = path;
+        next.payments.extend(output.payments);
+        next.warnings.extend(output.warnings);
+        self.history.push(std::mem::replace(&mut self.current, next));
+        Ok(())
+    }
+
+    pub fn apply_input(&mut self, case_index: usize, chosen_num: Option<i64>) -> std::result::Result<(), String> {
+        let action = self.possible_actions().into_iter().find(|a| a.case_index == case_index)
+            .ok_or_else(|| format!("The current contract has no case with index {case_index}."))?;
+        if !action.enabled {
+            return Err(format!("The action '{}' can not be applied right now.", action.description))
+        }
+        let mut input = action.input.ok_or(action.description)?;
+        if let Input::Choice { chosen_num: n, .. } = &mut input {
+            *n = chosen_num.ok_or_else(|| String::from("A chosen number is required for choices."))?;
+        }
+        self.transaction(self.current.time, Some(&input))
+    }
+
+    pub fn advance_time(&mut self, time: i64) -> std::result::Result<(), String> {
+        if time < self.current.time {
+            return Err(format!("Time can only move forward. The current time is {}.", self.current.time))
+        }
+        self.transaction(time, None)
+    }
+
+    pub fn undo(&mut self) -> std::result::Result<(), String> {
+        match self.history.pop() {
+            Some(previous) => { self.current = previous; Ok(()) }
+            None => Err(String::from("There is nothing to undo.")),
+        }
+    }
+
+    pub fn view(&self) -> SimulationView {
+        let state = &self.current.state;
+        SimulationView {
+            time: self.current.time,
+            accounts: state.accounts.iter().map(|((owner, token), amount)|
+                AccountView { owner: owner.clone(), token: token.clone(), amount: *amount }).collect(),
+            choices: state.choices.iter().map(|((choice_name, choice_owner), value)|
+                ChoiceView { choice_name: choice_name.clone(), choice_owner: choice_owner.clone(), value: *value }).collect(),
+            bound_values: state.bound_values.clone(),
+            payments: self.current.payments.clone(),
+            warnings: self.current.warnings.clone(),
+            closed: self.is_closed(),
+            current_range: self.parsed.contract_ranges.get(&self.current.path).copied(),
+            possible_actions: self.possible_actions(),
+            can_undo: !self.history.is_empty(),
+        }
+    }
+
+    /// The currently active When gets a WRITE highlight, all cases that can currently fire get READ.
+    pub fn highlights(&self) -> Vec<DocumentHighlight> {
+        let mut result = vec![];
+        if let Some(range) = self.parsed.contract_ranges.get(&self.current.path) {
+            result.push(DocumentHighlight { range: *range, kind: Some(DocumentHighlightKind::WRITE) })
+        }
+        for action in self.possible_actions().iter().filter(|a| a.enabled) {
+            let mut case_path = self.current.path.clone();
+            case_path.push(action.case_index);
+            if let Some(range) = self.parsed.case_ranges.get(&case_path) {
+                result.push(DocumentHighlight { range: *range, kind: Some(DocumentHighlightKind::READ) })
+            }
+        }
+        result
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl MyLSPServer {
+
+    fn with_simulation<T>(&self, uri: &Url, f: impl FnOnce(&mut SimulationSession) -> std::result::Result<T, String>) -> Result<T> {
+        let mut state = self.state.lock().unwrap();
+        match state.simulations.get_mut(uri) {
+            Some(session) => f(session).map_err(Error::invalid_params),
+            None => Err(Error::invalid_params(format!("No simulation has been started for {uri}."))),
+        }
+    }
+
+    pub async fn simulation_start(&self, params: SimulationStartParams) -> Result<SimulationView> {
+        let uri = params.text_document.uri;
+        let mut state = self.state.lock().unwrap();
+        let source = crate::get_contract_source(&state, &uri)
+            .ok_or_else(|| Error::invalid_params(format!("Unknown document: {uri}")))?;
+        let parsed = parse_contract(&source).map_err(Error::invalid_params)?;
+        let mut values = HashMap::new();
+        if let Some(file) = state.param_files.get(&uri) {
+            values.extend(file.values(ParameterKind::ConstantParam));
+            values.extend(file.values(ParameterKind::TimeParam));
+        }
+        values.extend(params.params);
+        let session = SimulationSession::start(parsed, values, params.start_time.unwrap_or(0))
+            .map_err(Error::invalid_params)?;
+        let view = session.view();
+        state.simulations.insert(uri, session);
+        Ok(view)
+    }
+
+    pub async fn simulation_list_possible_actions(&self, params: SimulationDocumentParams) -> Result<Vec<PossibleAction>> {
+        self.with_simulation(&params.text_document.uri, |s| Ok(s.possible_actions()))
+    }
+
+    pub async fn simulation_apply_input(&self, params: SimulationApplyInputParams) -> Result<SimulationView> {
+        self.with_simulation(&params.text_document.uri, |s| {
+            s.apply_input(params.case_index, params.chosen_num)?;
+            Ok(s.view())
+        })
+    }
+
+    pub async fn simulation_advance_time(&self, params: SimulationAdvanceTimeParams) -> Result<SimulationView> {
+        self.with_simulation(&params.text_document.uri, |s| {
+            s.advance_time(params.time)?;
+            Ok(s.view())
+        })
+    }
+
+    pub async fn simulation_undo(&self, params: SimulationDocumentParams) -> Result<SimulationView> {
+        self.with_simulation(&params.text_document.uri, |s| {
+            s.undo()?;
+            Ok(s.view())
+        })
+    }
+
+    pub async fn simulation_get_state(&self, params: SimulationDocumentParams) -> Result<SimulationView> {
+        self.with_simulation(&params.text_document.uri, |s| Ok(s.view()))
+    }
+}
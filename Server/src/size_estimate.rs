@@ -0,0 +1,313 @@
+// Estimates of how big a contract is on chain, since Cardano transactions have a
+// maximum size and large Marlowe contracts fail when they are deployed or when
+// they reach a step whose transaction does not fit.
+//
+// - The datum of a When is the Plutus Data of MarloweData (parameters, state and
+//   the When itself as the contract) serialized as CBOR. The state is estimated
+//   for the worst case: every account, choice and Let in the contract holds a value.
+// - A step is a transaction that applies one case (or the timeout) of a When. It
+//   spends the current output and produces the datum of the next When that the
+//   contract waits in, or nothing if it closes. Pay, If, Let and Assert are
+//   evaluated in the same transaction, so If takes the larger of its branches and
+//   every payment to a party adds an output.
+// - Creating the contract is a step too, whose output holds the datum of the root.
+//
+// The fixed parts of a transaction (inputs, collateral, change, signatures..)
+// are covered by `SizeLimits::transaction_overhead`, so the results are estimates,
+// but they are good enough to tell when a contract needs merkleization.
+
+use std::collections::{BTreeSet, HashMap};
+use lsp_types::{Diagnostic, DiagnosticSeverity, NumberOrString, Position, Range};
+use marlowe_lang::types::marlowe::*;
+use crate::contract_model::{ContractPath, ParsedContract, walk_contracts};
+use crate::plutus_data::{DataEncoder, PlutusData, PLACEHOLDER_AMOUNT, PLACEHOLDER_TIME};
+
+#[derive(Clone, Copy, Debug, Hash, serde::Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct SizeLimits {
+    /// Datums larger than this (in bytes) are reported.
+    pub max_datum_size: usize,
+    /// The maximum size of a transaction (in bytes), 16 KiB on mainnet.
+    pub max_transaction_size: usize,
+    /// The size of everything in a step transaction except for the datum, the redeemer and the payments.
+    pub transaction_overhead: usize,
+}
+
+impl Default for SizeLimits {
+    fn default() -> Self {
+        SizeLimits { max_datum_size: 5_000, max_transaction_size: 16_384, transaction_overhead: 1_100 }
+    }
+}
+
+/// Roughly the size of a transaction output paying ada and one native token to an address.
+const PAYMENT_OUTPUT_SIZE: usize = 90;
+
+#[derive(Clone, Debug)]
+pub struct StepEstimate {
+    pub size: usize,
+    pub description: String,
+    /// The case (or When, for the timeout) that the step applies.
+    pub range: Option<Range>,
+}
+
+#[derive(Clone, Debug)]
+pub struct WhenEstimate {
+    pub path: ContractPath,
+    pub range: Range,
+    pub datum_size: usize,
+    /// The largest of the transactions that leave this When.
+    pub largest_step: Option<StepEstimate>,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct SizeEstimate {
+    /// The size of the datum when the contract is created.
+    pub datum_size: usize,
+    pub root_range: Option<Range>,
+    /// The largest step transaction along any path, including the one that creates the contract.
+    pub largest_step: Option<StepEstimate>,
+    pub whens: Vec<WhenEstimate>,
+    /// Values that were not known, so the estimate used placeholders for them.
+    pub placeholders: BTreeSet<String>,
+    pub limits: SizeLimits,
+}
+
+/// The worst case state: all accounts, choices and bound values that the contract can create.
+fn worst_case_state(contract: &Contract, encoder: &mut DataEncoder) -> PlutusData {
+    let mut accounts = vec![];
+    let mut choices = vec![];
+    let mut bound_values = BTreeSet::new();
+    walk_contracts(contract, &mut |_, node| match node {
+        Contract::When { when, .. } => for case in when.iter().flatten() {
+            match &case.case {
+                Some(Action::Deposit { into_account, of_token, .. }) =>
+                    accounts.push(PlutusData::Constr(0, vec![encoder.party(into_account), encoder.token(of_token)])),
+                Some(Action::Choice { for_choice, .. }) => choices.push(encoder.choice_id(for_choice)),
+                _ => {}
+            }
+        },
+        Contract::Pay { to: Some(Payee::Account(party)), token, .. } =>
+            accounts.push(PlutusData::Constr(0, vec![encoder.party(party), encoder.token(token)])),
+        Contract::Let { r#let, .. } => { bound_values.insert(r#let.clone()); }
+        _ => {}
+    });
+    let entries = |keys: Vec<PlutusData>| {
+        let mut unique: Vec<PlutusData> = vec![];
+        for key in keys {
+            if !unique.contains(&key) {
+                unique.push(key)
+            }
+        }
+        PlutusData::Map(unique.into_iter().map(|key| (key, PlutusData::Integer(PLACEHOLDER_AMOUNT as i128))).collect())
+    };
+    let bound_values = bound_values.into_iter().map(|name| PlutusData::Bytes(name.into_bytes())).collect();
+    PlutusData::Constr(0, vec![entries(accounts), entries(choices), entries(bound_values), PlutusData::Integer(PLACEHOLDER_TIME as i128)])
+}
+
+/// The redeemer of a transaction that applies an action: a list with one input.
+fn redeemer_size(action: &Option<Action>, encoder: &mut DataEncoder) -> usize {
+    let input = match action {
+        Some(Action::Deposit { party, of_token, into_account, .. }) => PlutusData::Constr(0, vec![
+            encoder.party(into_account), encoder.party(party), encoder.token(of_token), PlutusData::Integer(PLACEHOLDER_AMOUNT as i128),
+        ]),
+        Some(Action::Choice { for_choice, .. }) =>
+            PlutusData::Constr(1, vec![encoder.choice_id(for_choice), PlutusData::Integer(PLACEHOLDER_AMOUNT as i128)]),
+        _ => PlutusData::Constr(2, vec![]),
+    };
+    PlutusData::List(vec![PlutusData::Constr(0, vec![input])]).encoded_size()
+}
+
+/// Where a continuation ends up after Pay, If, Let and Assert have been evaluated:
+/// the path of the next When (None if the contract closes) and the number of payments to parties.
+fn settle(contract: Option<&Contract>, path: &mut ContractPath, payments: usize, outcomes: &mut Vec<(Option<ContractPath>, usize)>) {
+    let mut follow = |child: &Option<Box<Contract>>, index: usize, payments: usize, outcomes: &mut Vec<(Option<ContractPath>, usize)>| {
+        path.push(index);
+        settle(child.as_deref(), path, payments, outcomes);
+        path.pop();
+    };
+    match contract {
+        None | Some(Contract::Close) => outcomes.push((None, payments)),
+        Some(Contract::When { .. }) => outcomes.push((Some(path.clone()), payments)),
+        Some(Contract::Pay { to, then, .. }) => {
+            let payments = payments + if let Some(Payee::Party(_)) = to { 1 } else { 0 };
+            follow(then, 0, payments, outcomes)
+        }
+        Some(Contract::If { then, r#else, .. }) => {
+            follow(then, 0, payments, outcomes);
+            follow(r#else, 1, payments, outcomes)
+        }
+        Some(Contract::Let { then, .. }) | Some(Contract::Assert { then, .. }) => follow(then, 0, payments, outcomes),
+    }
+}
+
+pub fn estimate(parsed: &ParsedContract, time_params: &HashMap<String, i64>, limits: SizeLimits) -> SizeEstimate {
+
+    let root = &parsed.contract;
+    let mut encoder = DataEncoder { time_params: time_params.clone(), ..Default::default() };
+
+    // MarloweParams holds the currency symbol of the role tokens (28 bytes).
+    let params_size = PlutusData::Constr(0, vec![PlutusData::Bytes(vec![0; 28])]).encoded_size();
+    let state_size = worst_case_state(root, &mut encoder).encoded_size();
+    // MarloweData is a constructor with three fields: the header and the end of the list.
+    let datum_size = |contract_size: usize| 2 + 1 + params_size + state_size + contract_size + 1;
+
+    let mut when_paths = vec![];
+    walk_contracts(root, &mut |path, node| if let Contract::When { .. } = node { when_paths.push((path.clone(), node)) });
+
+    let mut datum_sizes: HashMap<ContractPath, usize> = HashMap::new();
+    for (path, node) in &when_paths {
+        datum_sizes.insert(path.clone(), datum_size(encoder.contract(node).encoded_size()));
+    }
+
+    let step_size = |redeemer: usize, outcome: &(Option<ContractPath>, usize)| {
+        let output = outcome.0.as_ref().and_then(|p| datum_sizes.get(p)).copied().unwrap_or_default();
+        limits.transaction_overhead + redeemer + output + outcome.1 * PAYMENT_OUTPUT_SIZE
+    };
+
+    let mut result = SizeEstimate {
+        datum_size: datum_size(encoder.contract(root).encoded_size()),
+        root_range: parsed.contract_ranges.get(&vec![]).copied(),
+        limits,
+        ..Default::default()
+    };
+
+    let largest = |current: &mut Option<StepEstimate>, step: StepEstimate| {
+        if current.as_ref().map(|c| c.size < step.size).unwrap_or(true) {
+            *current = Some(step)
+        }
+    };
+
+    // Creating the contract: the output holds the root as it is.
+    largest(&mut result.largest_step, StepEstimate {
+        size: limits.transaction_overhead + result.datum_size,
+        description: String::from("Creating the contract"),
+        range: result.root_range,
+    });
+
+    for (path, node) in &when_paths {
+        let (when, timeout_continuation) = match node {
+            Contract::When { when, timeout_continuation, .. } => (when, timeout_continuation),
+            _ => continue,
+        };
+        let mut when_step = None;
+        for (index, case) in when.iter().enumerate() {
+            let (action, then) = match case { Some(c) => (&c.case, c.then.as_deref()), None => (&None, None) };
+            let redeemer = redeemer_size(action, &mut encoder);
+            let mut case_path = path.clone();
+            case_path.push(index);
+            let mut outcomes = vec![];
+            settle(then, &mut case_path.clone(), 0, &mut outcomes);
+            for outcome in &outcomes {
+                largest(&mut when_step, StepEstimate {
+                    size: step_size(redeemer, outcome),
+                    description: format!("Applying case {}", index + 1),
+                    range: parsed.case_ranges.get(&case_path).copied(),
+                });
+            }
+        }
+        let mut timeout_path = path.clone();
+        timeout_path.push(when.len());
+        let mut outcomes = vec![];
+        settle(timeout_continuation.as_deref(), &mut timeout_path, 0, &mut outcomes);
+        for outcome in &outcomes {
+            largest(&mut when_step, StepEstimate {
+                size: step_size(PlutusData::List(vec![]).encoded_size(), outcome),
+                description: String::from("The timeout"),
+                range: parsed.contract_ranges.get(path).copied().map(keyword_range),
+            });
+        }
+        if let Some(step) = &when_step {
+            largest(&mut result.largest_step, step.clone());
+        }
+        if let Some(range) = parsed.contract_ranges.get(path) {
+            result.whens.push(WhenEstimate {
+                path: path.clone(),
+                range: *range,
+                datum_size: datum_sizes[path],
+                largest_step: when_step,
+            });
+        }
+    }
+
+    result.placeholders = encoder.missing;
+    result
+}
+
+/// Formats a size in bytes like 1.2 kB.
+pub fn format_size(bytes: usize) -> String {
+    if bytes < 1000 {
+        format!("{bytes} B")
+    } else {
+        format!("{:.1} kB", bytes as f64 / 1000.0)
+    }
+}
+
+/// A one line summary for a When, used by the code lens and the hover.
+pub fn summary(when: &WhenEstimate) -> String {
+    match &when.largest_step {
+        Some(step) => format!("Datum ≈ {}, largest step ≈ {} ({})", format_size(when.datum_size), format_size(step.size), step.description.to_lowercase()),
+        None => format!("Datum ≈ {}", format_size(when.datum_size)),
+    }
+}
+
+/// The range of the When keyword, so that diagnostics don't cover the whole sub-contract.
+fn keyword_range(range: Range) -> Range {
+    let mut end = range.start;
+    end.character += 4;
+    Range::new(range.start, end)
+}
+
+/// The When whose keyword is at the position.
+pub fn when_at(estimate: &SizeEstimate, position: Position) -> Option<&WhenEstimate> {
+    estimate.whens.iter().find(|when| {
+        let range = keyword_range(when.range);
+        range.start <= position && position <= range.end
+    })
+}
+
+pub fn to_diagnostics(estimate: &SizeEstimate) -> Vec<Diagnostic> {
+    let limits = estimate.limits;
+    let placeholders = if estimate.placeholders.is_empty() {
+        String::new()
+    } else {
+        format!(" The estimate uses placeholders for {}.", estimate.placeholders.iter().cloned().collect::<Vec<String>>().join(", "))
+    };
+    let warning = |range: Range, code: &str, message: String| Diagnostic {
+        range,
+        severity: Some(DiagnosticSeverity::WARNING),
+        code: Some(NumberOrString::String(code.to_string())),
+        message: format!("{message}{placeholders}"),
+        ..Default::default()
+    };
+
+    let mut diagnostics = vec![];
+    // The root is only reported on its own when it is not a When, or the When below covers it.
+    let root_is_when = matches!(estimate.whens.first(), Some(w) if w.path.is_empty());
+    if let Some(range) = estimate.root_range {
+        let start = Range::new(range.start, range.start);
+        if estimate.datum_size > limits.max_datum_size && !root_is_when {
+            diagnostics.push(warning(start, "ML060", format!(
+                "The datum of this contract is estimated at {} bytes, more than the limit of {} bytes. Consider merkleizing the contract.",
+                estimate.datum_size, limits.max_datum_size)));
+        }
+        let creation = limits.transaction_overhead + estimate.datum_size;
+        if creation > limits.max_transaction_size {
+            diagnostics.push(warning(start, "ML061", format!(
+                "Creating this contract is estimated to need a transaction of {creation} bytes, more than the maximum transaction size of {} bytes.",
+                limits.max_transaction_size)));
+        }
+    }
+    for when in &estimate.whens {
+        if when.datum_size > limits.max_datum_size {
+            diagnostics.push(warning(keyword_range(when.range), "ML060", format!(
+                "The datum of this When is estimated at {} bytes, more than the limit of {} bytes. Consider merkleizing the contract.",
+                when.datum_size, limits.max_datum_size)));
+        }
+        if let Some(step) = when.largest_step.as_ref().filter(|step| step.size > limits.max_transaction_size) {
+            diagnostics.push(warning(step.range.unwrap_or(when.range), "ML061", format!(
+                "{} is estimated to need a transaction of {} bytes, more than the maximum transaction size of {} bytes.",
+                step.description, step.size, limits.max_transaction_size)));
+        }
+    }
+    diagnostics
+}
@@ -0,0 +1,164 @@
+// Suppression comments, for warnings that are intentional:
+//
+//   // marlowe-lsp-ignore ML020          the node after the comment
+//   // marlowe-lsp-ignore-file ML001     the whole document
+//
+// Several ids can be given, separated by spaces or commas. Without ids, every rule
+// is ignored. Syntax errors (ML000) can not be suppressed.
+//
+// Comments are found with the S-expression grammar (sex.grammars), where comments
+// are attached to the node that follows them. A diagnostic is suppressed when it starts
+// inside that node, or on the line where that node starts, so that a comment on the
+// line before a warning always works.
+//
+// marlowe_lang does not know about comments, so they are replaced by spaces before a
+// contract is parsed. This keeps every position in the document the same.
+
+use lsp_types::{Diagnostic, NumberOrString, Position, Range, TextEdit};
+use pest::Parser;
+use pest::iterators::Pair;
+use crate::sex::{Rule, SexParser};
+
+pub const IGNORE: &str = "marlowe-lsp-ignore";
+pub const IGNORE_FILE: &str = "marlowe-lsp-ignore-file";
+
+#[derive(Clone, Debug)]
+pub struct Comment {
+    /// Byte offsets of the comment, from the slashes to the end of the line.
+    pub start: usize,
+    pub end: usize,
+    pub text: String,
+    /// The node that the comment is attached to, if any node follows it.
+    pub target: Option<Range>,
+    /// Byte offset of the node that the comment is attached to.
+    pub target_start: Option<usize>,
+}
+
+fn range(start: pest::Position, end: pest::Position) -> Range {
+    let (start_line, start_col) = start.line_col();
+    let (end_line, end_col) = end.line_col();
+    Range::new(
+        Position::new(start_line as u32 - 1, start_col as u32 - 1),
+        Position::new(end_line as u32 - 1, end_col as u32 - 1))
+}
+
+fn collect(pair: Pair<Rule>, comments: &mut Vec<Comment>) {
+    let rule = pair.as_rule();
+    let children: Vec<Pair<Rule>> = pair.into_inner().collect();
+    // In a commented node, the last child is the node that the comments belong to
+    let target = match (rule, children.last()) {
+        (Rule::commented, Some(node)) => Some(node.as_span()),
+        _ => None,
+    };
+    for child in &children {
+        if child.as_rule() == Rule::comment {
+            let span = child.as_span();
+            comments.push(Comment {
+                start: span.start(),
+                end: span.end(),
+                text: span.as_str().trim_end().to_string(),
+                target: target.as_ref().map(|node| range(node.start_pos(), node.end_pos())),
+                target_start: target.as_ref().map(|node| node.start()),
+            });
+        } else {
+            collect(child.clone(), comments)
+        }
+    }
+}
+
+/// The comments in a DSL document. Empty if the document can not be parsed.
+pub fn comments(source: &str) -> Vec<Comment> {
+    let mut comments = vec![];
+    if let Ok(pairs) = SexParser::parse(Rule::expressions, source) {
+        for pair in pairs {
+            collect(pair, &mut comments)
+        }
+    }
+    comments
+}
+
+/// The source with every comment replaced by spaces, for parsers that do not support comments.
+pub fn without_comments(source: &str) -> String {
+    if !source.contains("//") {
+        return source.to_string()
+    }
+    let mut result = source.to_string();
+    for comment in comments(source) {
+        result.replace_range(comment.start..comment.end, &" ".repeat(comment.end - comment.start))
+    }
+    result
+}
+
+#[derive(Clone, Debug)]
+struct Directive {
+    /// None for the whole document.
+    target: Option<Range>,
+    /// Empty for every rule.
+    ids: Vec<String>,
+}
+
+impl Directive {
+    fn covers(&self, diagnostic: &Diagnostic, id: &str) -> bool {
+        let rule_matches = self.ids.is_empty() || self.ids.iter().any(|i| i == id);
+        let start = diagnostic.range.start;
+        rule_matches && match self.target {
+            None => true,
+            Some(target) => start.line == target.start.line || (target.start <= start && start < target.end),
+        }
+    }
+}
+
+/// The suppression comments of a document.
+#[derive(Clone, Debug, Default)]
+pub struct Suppressions {
+    directives: Vec<Directive>,
+}
+
+impl Suppressions {
+    pub fn from_comments(comments: &[Comment]) -> Suppressions {
+        let directives = comments.iter().filter_map(|comment| {
+            let text = comment.text.trim_start_matches('/').trim();
+            // The longer keyword first, since the other one is a prefix of it
+            let (rest, target) = if let Some(rest) = text.strip_prefix(IGNORE_FILE) {
+                (rest, None)
+            } else {
+                (text.strip_prefix(IGNORE)?, Some(comment.target?))
+            };
+            if !rest.is_empty() && !rest.starts_with([' ', '\t', ':']) {
+                return None
+            }
+            let ids = rest.split([' ', '\t', ',', ':']).filter(|s| !s.is_empty()).map(|s| s.to_uppercase()).collect();
+            Some(Directive { target, ids })
+        }).collect();
+        Suppressions { directives }
+    }
+
+    /// Removes the diagnostics that are suppressed by a comment.
+    pub fn apply(&self, diagnostics: &mut Vec<Diagnostic>) {
+        if self.directives.is_empty() {
+            return
+        }
+        diagnostics.retain(|diagnostic| {
+            let id = match &diagnostic.code {
+                Some(NumberOrString::String(code)) if code != "ML000" => code,
+                _ => return true,
+            };
+            !self.directives.iter().any(|directive| directive.covers(diagnostic, id))
+        })
+    }
+}
+
+/// An edit that puts a suppression comment for a rule on the line before a diagnostic.
+pub fn suppress_edit(source: &str, diagnostic: &Diagnostic, id: &str) -> TextEdit {
+    let line = diagnostic.range.start.line;
+    let indentation: String = source.lines().nth(line as usize).unwrap_or_default()
+        .chars().take_while(|c| c.is_whitespace()).collect();
+    let start = Position::new(line, 0);
+    TextEdit::new(Range::new(start, start), format!("{indentation}// {IGNORE} {id}\n"))
+}
+
+/// An edit that puts a suppression comment for a rule at the top of the document.
+pub fn suppress_file_edit(id: &str) -> TextEdit {
+    let start = Position::new(0, 0);
+    TextEdit::new(Range::new(start, start), format!("// {IGNORE_FILE} {id}\n"))
+}
@@ -0,0 +1,194 @@
+// A human readable summary ("term sheet") of a contract, in Markdown.
+//
+// The facts that go into the term sheet are collected by
+// recursively_validate_contract while it walks the contract, so the summary
+// always covers exactly what the validator sees, with the same notion of
+// deadlines and conditions that the timeout checks use.
+
+use std::collections::BTreeSet;
+use lsp_types::Range;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ParameterKind {
+    TimeParam,
+    ConstantParam,
+}
+
+#[derive(Clone, Debug)]
+pub enum FactKind {
+    Party(String),
+    Token(String),
+    Parameter { name: String, kind: ParameterKind },
+    Deposit { into_account: String, from: String, token: String, amount: String },
+    Choice { name: String, owner: String, bounds: String },
+    Notify { observation: String },
+    Payment { from_account: String, to: String, token: String, amount: String },
+    Deadline { timeout: String, value: Option<i64> },
+}
+
+/// Something the validator found in the contract.
+#[derive(Clone, Debug)]
+pub struct ContractFact {
+    pub kind: FactKind,
+    pub range: Range,
+    /// The timeout of the When that this fact is part of (for actions).
+    pub deadline: Option<String>,
+    /// What has to happen before we get here.
+    pub conditions: Vec<String>,
+}
+
+/// A fixed offset from UTC, for showing dates.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct TimeZone {
+    pub offset_minutes: i64,
+}
+
+impl TimeZone {
+
+    /// Parses "UTC", "Z" or an offset like "+02:00", "-0530" or "UTC+1".
+    pub fn parse(text: &str) -> Result<TimeZone, String> {
+        let invalid = || format!("Invalid timezone '{text}', expected UTC or an offset like +02:00.");
+        let offset = text.trim().trim_start_matches("UTC").trim_start_matches("GMT");
+        if offset.is_empty() || offset == "Z" {
+            return Ok(TimeZone::default())
+        }
+        let (sign, offset) = match offset.split_at(1) {
+            ("+", rest) => (1, rest),
+            ("-", rest) => (-1, rest),
+            _ => return Err(invalid()),
+        };
+        let (hours, minutes) = match offset.split_once(':') {
+            Some((hours, minutes)) => (hours, minutes),
+            None if offset.len() == 4 => offset.split_at(2),
+            None => (offset, "0"),
+        };
+        match (hours.parse::<i64>(), minutes.parse::<i64>()) {
+            (Ok(hours), Ok(minutes)) if hours <= 14 && minutes < 60 => Ok(TimeZone { offset_minutes: sign * (hours * 60 + minutes) }),
+            _ => Err(invalid()),
+        }
+    }
+
+    pub fn name(&self) -> String {
+        if self.offset_minutes == 0 {
+            return String::from("UTC")
+        }
+        let sign = if self.offset_minutes < 0 { '-' } else { '+' };
+        format!("UTC{sign}{:02}:{:02}", self.offset_minutes.abs() / 60, self.offset_minutes.abs() % 60)
+    }
+}
+
+/// Formats a POSIX time in milliseconds as a date in a timezone.
+pub fn format_posix_time(ms: i64, zone: TimeZone) -> String {
+    let seconds = ms.div_euclid(1000) + zone.offset_minutes * 60;
+    let (days, rest) = (seconds.div_euclid(86400), seconds.rem_euclid(86400));
+    // Civil date from days since 1970-01-01 (Howard Hinnant's algorithm)
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    format!("{year:04}-{month:02}-{day:02} {:02}:{:02}:{:02} {}", rest / 3600, rest % 3600 / 60, rest % 60, zone.name())
+}
+
+fn cell(text: &str) -> String {
+    text.replace('|', "\\|").replace('\n', " ")
+}
+
+fn code(text: &str) -> String {
+    format!("`{}`", cell(text))
+}
+
+fn conditions(fact: &ContractFact) -> String {
+    if fact.conditions.is_empty() {
+        String::from("always")
+    } else {
+        fact.conditions.iter().map(|c| cell(c)).collect::<Vec<String>>().join("; then ")
+    }
+}
+
+fn line(fact: &ContractFact) -> String {
+    (fact.range.start.line + 1).to_string()
+}
+
+fn table(out: &mut String, title: &str, headers: &[&str], rows: Vec<Vec<String>>) {
+    out.push_str(&format!("\n## {title}\n\n"));
+    if rows.is_empty() {
+        out.push_str("None.\n");
+        return
+    }
+    out.push_str(&format!("| {} |\n", headers.join(" | ")));
+    out.push_str(&format!("|{}\n", headers.iter().map(|_| " --- |").collect::<String>()));
+    for row in rows {
+        out.push_str(&format!("| {} |\n", row.join(" | ")));
+    }
+}
+
+/// Renders the term sheet for the facts collected by the validator.
+pub fn render(title: &str, facts: &[ContractFact], zone: TimeZone) -> String {
+
+    let mut out = format!("# Term sheet: {title}\n");
+
+    let parties: BTreeSet<&String> = facts.iter().filter_map(|f| match &f.kind { FactKind::Party(p) => Some(p), _ => None }).collect();
+    table(&mut out, "Parties and roles", &["Party", "Kind"], parties.into_iter().map(|p| vec![
+        code(p),
+        String::from(if p.starts_with("(Role") { "Role" } else { "Public key" }),
+    ]).collect());
+
+    let tokens: BTreeSet<&String> = facts.iter().filter_map(|f| match &f.kind { FactKind::Token(t) => Some(t), _ => None }).collect();
+    table(&mut out, "Tokens", &["Token"], tokens.into_iter().map(|t| vec![code(t)]).collect());
+
+    let parameters: BTreeSet<(ParameterKind, &String)> = facts.iter().filter_map(|f| match &f.kind {
+        FactKind::Parameter { name, kind } => Some((*kind, name)),
+        _ => None,
+    }).collect();
+    table(&mut out, "Parameters", &["Name", "Type"], parameters.into_iter().map(|(kind, name)| vec![
+        code(name),
+        String::from(match kind { ParameterKind::TimeParam => "Time (POSIX milliseconds)", ParameterKind::ConstantParam => "Integer" }),
+    ]).collect());
+
+    let mut deposits = vec![];
+    let mut choices = vec![];
+    let mut notifications = vec![];
+    let mut payments = vec![];
+    let mut deadlines = vec![];
+    for fact in facts {
+        let deadline = match fact.deadline.as_deref().map(|d| (d, d.parse::<i64>())) {
+            Some((d, Ok(ms))) => format!("{d} ({})", format_posix_time(ms, zone)),
+            Some((d, Err(_))) => d.to_string(),
+            None => String::from("none"),
+        };
+        match &fact.kind {
+            FactKind::Deposit { into_account, from, token, amount } =>
+                deposits.push(vec![code(from), code(into_account), code(amount), code(token), cell(&deadline), conditions(fact), line(fact)]),
+            FactKind::Choice { name, owner, bounds } =>
+                choices.push(vec![code(name), code(owner), cell(bounds), cell(&deadline), conditions(fact), line(fact)]),
+            FactKind::Notify { observation } =>
+                notifications.push(vec![code(observation), cell(&deadline), conditions(fact), line(fact)]),
+            FactKind::Payment { from_account, to, token, amount } =>
+                payments.push(vec![code(from_account), code(to), code(amount), code(token), conditions(fact), line(fact)]),
+            FactKind::Deadline { timeout, value } =>
+                deadlines.push((*value, timeout.clone(), fact)),
+            _ => {}
+        }
+    }
+
+    table(&mut out, "Deposits", &["Who", "Into the account of", "Amount", "Token", "Deadline", "Conditions", "Line"], deposits);
+    table(&mut out, "Choices", &["Choice", "Made by", "Allowed values", "Deadline", "Conditions", "Line"], choices);
+    table(&mut out, "Notifications", &["Observation", "Deadline", "Conditions", "Line"], notifications);
+    table(&mut out, "Payments", &["From the account of", "To", "Amount", "Token", "Conditions", "Line"], payments);
+
+    // Known times first, in order, then the ones that depend on parameters.
+    deadlines.sort_by_key(|(value, timeout, _)| (value.is_none(), *value, timeout.clone()));
+    table(&mut out, "Timeline", &["Deadline", "Date", "Reached", "Line"], deadlines.into_iter().map(|(value, timeout, fact)| vec![
+        cell(&timeout),
+        value.map(|ms| format_posix_time(ms, zone)).unwrap_or_else(|| String::from("depends on parameters")),
+        conditions(fact),
+        line(fact),
+    ]).collect());
+
+    out
+}
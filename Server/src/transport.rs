@@ -0,0 +1,204 @@
+// Listening for editors that connect over the network, for a shared server that
+// browser based editors can use. Editors that start the server themselves talk to it
+// over stdin/stdout instead.
+//
+//   marlowe_lsp --listen tcp:127.0.0.1:9257    LSP messages with Content-Length headers
+//   marlowe_lsp --listen ws:127.0.0.1:9257     one JSON-RPC message per WebSocket message
+//
+// Every connection gets its own MyLSPServer, so clients do not see each others
+// documents or configuration. A connection is closed after the client sends exit, or
+// when it disconnects. Ctrl+C stops accepting connections and waits for the open ones
+// to end (a second Ctrl+C stops right away).
+
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use futures::{SinkExt, StreamExt};
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{Notify, mpsc};
+use tokio_tungstenite::tungstenite::Message;
+use tower_lsp::Server;
+use tower_lsp::jsonrpc::Request;
+use tower_service::Service;
+
+pub const OPTION: &str = "--listen";
+
+/// The address after --listen (or --listen=), if it is given.
+pub fn listen_address(args: &[String]) -> Option<String> {
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        if arg == OPTION {
+            return Some(args.next().cloned().unwrap_or_default())
+        }
+        if let Some(address) = arg.strip_prefix(OPTION).and_then(|rest| rest.strip_prefix('=')) {
+            return Some(address.to_string())
+        }
+    }
+    None
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Transport {
+    Tcp,
+    WebSocket,
+}
+
+fn parse_address(address: &str) -> Result<(Transport, &str), String> {
+    match address.split_once(':') {
+        Some(("tcp", address)) => Ok((Transport::Tcp, address)),
+        Some(("ws", address)) => Ok((Transport::WebSocket, address)),
+        _ => Err(format!("{OPTION} expects tcp:<address> or ws:<address>, like tcp:127.0.0.1:9257, not '{address}'."))
+    }
+}
+
+/// Accepts connections until Ctrl+C is pressed.
+pub async fn listen(address: &str) -> Result<(), String> {
+    let (transport, address) = parse_address(address)?;
+    let listener = TcpListener::bind(address).await.map_err(|e| format!("Could not listen on {address}: {e}"))?;
+    let local = listener.local_addr().map(|a| a.to_string()).unwrap_or_else(|_| address.to_string());
+    eprintln!("Listening for {} connections on {local}", if transport == Transport::Tcp { "tcp" } else { "ws" });
+
+    // Every connection holds a sender, so the receiver knows when they have all ended
+    let (open, mut all_closed) = mpsc::channel::<()>(1);
+    loop {
+        tokio::select! {
+            accepted = listener.accept() => match accepted {
+                Ok((stream, peer)) => {
+                    let open = open.clone();
+                    tokio::spawn(async move {
+                        eprintln!("{peer} connected");
+                        let result = match transport {
+                            Transport::Tcp => serve_tcp(stream).await,
+                            Transport::WebSocket => serve_websocket(stream).await,
+                        };
+                        match result {
+                            Ok(()) => eprintln!("{peer} disconnected"),
+                            Err(e) => eprintln!("{peer} disconnected: {e}"),
+                        }
+                        drop(open)
+                    });
+                }
+                Err(e) => eprintln!("Could not accept a connection: {e}")
+            },
+            _ = tokio::signal::ctrl_c() => break
+        }
+    }
+
+    drop(listener);
+    drop(open);
+    eprintln!("No longer accepting connections, waiting for the open ones to end");
+    tokio::select! {
+        _ = all_closed.recv() => {},
+        _ = tokio::signal::ctrl_c() => {}
+    }
+    Ok(())
+}
+
+/// Tells when the client has sent exit. Server::serve only stops at the next message
+/// or at the end of the input, which a client that stays connected never sends.
+struct ExitSignal<S> {
+    inner: S,
+    exited: Arc<Notify>,
+}
+
+impl<S: Service<Request>> Service<Request> for ExitSignal<S> {
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = S::Future;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), S::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Request) -> S::Future {
+        let exit = request.method() == "exit";
+        let future = self.inner.call(request);
+        if exit {
+            self.exited.notify_one()
+        }
+        future
+    }
+}
+
+/// Runs a language server of its own for one connection, until the client sends exit or disconnects.
+async fn serve_connection(read: impl tokio::io::AsyncRead + Unpin, write: impl tokio::io::AsyncWrite) {
+    let (service, socket) = crate::server::build_service();
+    let exited = Arc::new(Notify::new());
+    let service = ExitSignal { inner: service, exited: exited.clone() };
+    tokio::select! {
+        _ = Server::new(read, write, socket).serve(service) => {},
+        _ = exited.notified() => {}
+    }
+}
+
+async fn serve_tcp(stream: TcpStream) -> Result<(), String> {
+    let (read, write) = tokio::io::split(stream);
+    serve_connection(read, write).await;
+    Ok(())
+}
+
+/// Reads one LSP message (headers and content), None at the end of the stream.
+async fn read_message(reader: &mut (impl AsyncBufRead + Unpin)) -> std::io::Result<Option<String>> {
+    let mut length = None;
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line).await? == 0 {
+            return Ok(None)
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            if name.eq_ignore_ascii_case("content-length") {
+                length = value.trim().parse::<usize>().ok();
+            }
+        }
+    }
+    let length = length.ok_or_else(|| std::io::Error::new(std::io::ErrorKind::InvalidData, "Missing Content-Length header"))?;
+    let mut content = vec![0; length];
+    reader.read_exact(&mut content).await?;
+    String::from_utf8(content).map(Some).map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))
+}
+
+/// tower-lsp speaks LSP with Content-Length headers, WebSocket clients send plain JSON-RPC
+/// messages, so the server gets a pipe and the headers are added and removed on the way.
+async fn serve_websocket(stream: TcpStream) -> Result<(), String> {
+    let websocket = tokio_tungstenite::accept_async(stream).await.map_err(|e| e.to_string())?;
+    let (mut sink, mut messages) = websocket.split();
+    let (server_end, bridge_end) = tokio::io::duplex(1 << 16);
+    let (server_read, server_write) = tokio::io::split(server_end);
+    let (bridge_read, mut bridge_write) = tokio::io::split(bridge_end);
+
+    let outgoing = tokio::spawn(async move {
+        let mut reader = BufReader::new(bridge_read);
+        while let Ok(Some(content)) = read_message(&mut reader).await {
+            if sink.send(Message::Text(content)).await.is_err() {
+                break
+            }
+        }
+        let _ = sink.close().await;
+    });
+    let incoming = async move {
+        while let Some(Ok(message)) = messages.next().await {
+            let content = match message {
+                Message::Text(text) => text.into_bytes(),
+                Message::Binary(bytes) => bytes,
+                Message::Close(_) => break,
+                _ => continue,
+            };
+            let header = format!("Content-Length: {}\r\n\r\n", content.len());
+            if bridge_write.write_all(header.as_bytes()).await.is_err() || bridge_write.write_all(&content).await.is_err() {
+                break
+            }
+        }
+    };
+
+    // Ends when the client sends exit or disconnects. Either way the pipe is closed,
+    // which lets the outgoing half send what is left and close the WebSocket.
+    tokio::select! {
+        _ = serve_connection(server_read, server_write) => {},
+        _ = incoming => {}
+    }
+    outgoing.await.map_err(|e| e.to_string())
+}
@@ -0,0 +1,96 @@
+// The WebAssembly build, for editors in the browser (like a Monaco based IDE). It gives
+// the same results as the language server, without a server process:
+//
+//   cargo rustc --lib --release --target wasm32-unknown-unknown --crate-type cdylib
+//   wasm-bindgen --target web --out-dir pkg target/wasm32-unknown-unknown/release/marlowe_lsp.wasm
+//
+//   import init, { diagnostics, format } from "./pkg/marlowe_lsp.js";
+//   await init();
+//   const problems = diagnostics(model.getValue(), "file:///escrow.marlowe");
+//
+// Every function takes the text of a document and its uri, which is only used to tell
+// DSL documents from .marlowe.json documents. Settings have the shape of the "marlowe"
+// editor settings (see config.rs) and can be left out. Results are the LSP types
+// (Diagnostic, Hover, SemanticTokens...) as plain objects.
+//
+// There is no file system, so parameter files and continuation maps are not used.
+
+use lsp_types::{Position, Url};
+use serde::Serialize;
+use wasm_bindgen::prelude::*;
+use crate::{Config, Document};
+
+fn document_uri(uri: &str) -> Result<Url, JsValue> {
+    Url::parse(uri).map_err(|e| JsValue::from_str(&format!("Invalid uri '{uri}': {e}")))
+}
+
+fn config(settings: JsValue) -> Result<Config, JsValue> {
+    if settings.is_undefined() || settings.is_null() {
+        return Ok(Config::default())
+    }
+    let settings: serde_json::Value = serde_wasm_bindgen::from_value(settings)?;
+    match Config::load(None, Some(&settings), None) {
+        (config, problems) if problems.is_empty() => Ok(config),
+        (_, problems) => Err(JsValue::from_str(&problems.join("\n")))
+    }
+}
+
+fn open(text: &str, uri: &str, settings: JsValue) -> Result<Document, JsValue> {
+    Ok(Document::parse_with_config(document_uri(uri)?, text, &config(settings)?))
+}
+
+fn to_js(value: &impl Serialize) -> Result<JsValue, JsValue> {
+    // Plain objects instead of Maps, and null instead of undefined, like JSON.parse gives
+    Ok(value.serialize(&serde_wasm_bindgen::Serializer::json_compatible())?)
+}
+
+#[derive(Serialize)]
+struct ParseResult {
+    /// The outline of the contract, null if it can not be parsed.
+    symbols: Option<Vec<lsp_types::DocumentSymbol>>,
+    /// Syntax errors (ML000).
+    errors: Vec<lsp_types::Diagnostic>,
+}
+
+/// Parses a document: its outline (DocumentSymbol[]) and syntax errors (Diagnostic[]).
+#[wasm_bindgen]
+pub fn parse(text: &str, uri: &str) -> Result<JsValue, JsValue> {
+    let document = open(text, uri, JsValue::UNDEFINED)?;
+    let errors = document.diagnostics().iter()
+        .filter(|d| crate::rules::rule_id(d) == "ML000")
+        .cloned().collect();
+    to_js(&ParseResult { symbols: document.symbols(), errors })
+}
+
+/// All diagnostics of a document (Diagnostic[]), as the language server publishes them.
+#[wasm_bindgen]
+pub fn diagnostics(text: &str, uri: &str, settings: JsValue) -> Result<JsValue, JsValue> {
+    to_js(&open(text, uri, settings)?.diagnostics())
+}
+
+/// The formatted text of a document. Throws if the document can not be parsed.
+/// Only formats, the document is not validated.
+#[wasm_bindgen]
+pub fn format(text: &str, uri: &str, settings: JsValue) -> Result<String, JsValue> {
+    let config = config(settings)?;
+    crate::format::format_document(&document_uri(uri)?, text, &config.format).map_err(|e| JsValue::from_str(&e))
+}
+
+/// The hover at a position (zero based line and character), a Hover or null.
+#[wasm_bindgen]
+pub fn hover_at(text: &str, uri: &str, line: u32, character: u32) -> Result<JsValue, JsValue> {
+    to_js(&open(text, uri, JsValue::UNDEFINED)?.hover(Position::new(line, character)))
+}
+
+/// The semantic tokens of a DSL document (SemanticTokens), null for JSON documents.
+/// The token types are the ones of semantic_token_legend.
+#[wasm_bindgen]
+pub fn semantic_tokens(text: &str, uri: &str) -> Result<JsValue, JsValue> {
+    to_js(&open(text, uri, JsValue::UNDEFINED)?.semantic_tokens())
+}
+
+/// The legend of semantic_tokens (SemanticTokensLegend).
+#[wasm_bindgen]
+pub fn semantic_token_legend() -> Result<JsValue, JsValue> {
+    to_js(&crate::semantic_token_legend())
+}
@@ -0,0 +1,236 @@
+// A test client that talks to the language server the way an editor does: JSON-RPC
+// messages with Content-Length headers, over an in-memory pipe to a server that runs
+// in the same process (server::build_service, served by tower_lsp::Server).
+//
+// Requests from the server to the client (workspace/configuration,
+// client/registerCapability) are answered, notifications are kept until a test asks
+// for them. A started client has answered workspace/configuration, so documents are
+// validated with its settings. Everything waits at most TIMEOUT, so a server that does
+// not answer fails the test instead of hanging it.
+
+#![allow(dead_code)]
+
+use std::collections::VecDeque;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use marlowe_lsp::lsp_types::{Diagnostic, Url};
+use serde_json::{json, Value};
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader, DuplexStream, ReadHalf, WriteHalf};
+
+pub const TIMEOUT: Duration = Duration::from_secs(10);
+
+pub struct TestClient {
+    reader: BufReader<ReadHalf<DuplexStream>>,
+    writer: WriteHalf<DuplexStream>,
+    next_id: i64,
+    /// The "marlowe" editor settings, the answer to workspace/configuration.
+    pub settings: Value,
+    /// What the server answered to initialize (InitializeResult).
+    pub initialize_result: Value,
+    notifications: VecDeque<Value>,
+    configuration_requests: usize,
+}
+
+impl TestClient {
+
+    /// Starts a server and initializes it, without editor settings or workspace.
+    pub async fn start() -> TestClient {
+        TestClient::start_with(json!({}), None).await
+    }
+
+    /// Starts a server and initializes it with editor settings, and a workspace root
+    /// for marlowe-lsp.toml.
+    pub async fn start_with(settings: Value, root: Option<&Path>) -> TestClient {
+        let (client_end, server_end) = tokio::io::duplex(1 << 20);
+        let (server_read, server_write) = tokio::io::split(server_end);
+        let (service, socket) = marlowe_lsp::server::build_service();
+        tokio::spawn(tower_lsp::Server::new(server_read, server_write, socket).serve(service));
+
+        let (read, writer) = tokio::io::split(client_end);
+        let mut client = TestClient { reader: BufReader::new(read), writer, next_id: 1, settings, initialize_result: Value::Null, notifications: VecDeque::new(), configuration_requests: 0 };
+        let root_uri = root.map(|root| Url::from_directory_path(root).unwrap());
+        client.initialize_result = client.request("initialize", json!({
+            "processId": null,
+            "rootUri": root_uri,
+            "capabilities": { "workspace": { "configuration": true } }
+        })).await;
+        client.notify("initialized", json!({})).await;
+        // The server validates with the default configuration until it has the editor settings,
+        // tests start once it asked for them
+        while client.configuration_requests == 0 {
+            if let Some(message) = client.read().await {
+                client.notifications.push_back(message);
+            }
+        }
+        client
+    }
+
+    async fn send(&mut self, message: Value) {
+        let content = message.to_string();
+        let header = format!("Content-Length: {}\r\n\r\n", content.len());
+        self.writer.write_all(header.as_bytes()).await.unwrap();
+        self.writer.write_all(content.as_bytes()).await.unwrap();
+        self.writer.flush().await.unwrap();
+    }
+
+    async fn receive(&mut self) -> Value {
+        let mut length = 0;
+        loop {
+            let mut line = String::new();
+            let read = tokio::time::timeout(TIMEOUT, self.reader.read_line(&mut line)).await
+                .expect("the server did not send anything").unwrap();
+            assert!(read > 0, "the server closed the connection");
+            let line = line.trim_end();
+            if line.is_empty() {
+                break
+            }
+            if let Some(value) = line.strip_prefix("Content-Length:") {
+                length = value.trim().parse().unwrap();
+            }
+        }
+        let mut content = vec![0; length];
+        self.reader.read_exact(&mut content).await.unwrap();
+        serde_json::from_slice(&content).unwrap()
+    }
+
+    /// Reads a message. Requests of the server get their answer here, and None is returned for them.
+    async fn read(&mut self) -> Option<Value> {
+        let message = self.receive().await;
+        let result = match message["method"].as_str() {
+            Some(_) if message.get("id").is_none() => return Some(message),
+            Some("workspace/configuration") => {
+                self.configuration_requests += 1;
+                json!([self.settings])
+            }
+            Some(_) => Value::Null,
+            None => return Some(message),
+        };
+        let id = message["id"].clone();
+        self.send(json!({ "jsonrpc": "2.0", "id": id, "result": result })).await;
+        None
+    }
+
+    /// Reads the next message that is not a request of the server.
+    async fn next_message(&mut self) -> Value {
+        loop {
+            if let Some(message) = self.read().await {
+                return message
+            }
+        }
+    }
+
+    /// Sends a request and returns its result. Errors fail the test.
+    pub async fn request(&mut self, method: &str, params: Value) -> Value {
+        let id = self.next_id;
+        self.next_id += 1;
+        let mut message = json!({ "jsonrpc": "2.0", "id": id, "method": method });
+        if !params.is_null() {
+            message["params"] = params;
+        }
+        self.send(message).await;
+        loop {
+            let message = self.next_message().await;
+            if message.get("method").is_some() {
+                self.notifications.push_back(message);
+            } else if message["id"] == json!(id) {
+                assert!(message.get("error").is_none(), "{method} failed: {}", message["error"]);
+                return message["result"].clone()
+            }
+        }
+    }
+
+    pub async fn notify(&mut self, method: &str, params: Value) {
+        let mut message = json!({ "jsonrpc": "2.0", "method": method });
+        if !params.is_null() {
+            message["params"] = params;
+        }
+        self.send(message).await
+    }
+
+    /// The first notification that matches, the others are kept for later.
+    async fn wait_for(&mut self, matches: impl Fn(&Value) -> bool) -> Value {
+        if let Some(index) = self.notifications.iter().position(&matches) {
+            return self.notifications.remove(index).unwrap()
+        }
+        loop {
+            let message = self.next_message().await;
+            if message.get("method").is_none() {
+                continue
+            }
+            if matches(&message) {
+                return message
+            }
+            self.notifications.push_back(message);
+        }
+    }
+
+    /// The next notification with a method.
+    pub async fn notification(&mut self, method: &str) -> Value {
+        self.wait_for(|n| n["method"] == method).await
+    }
+
+    /// The next diagnostics that are published for a document.
+    pub async fn diagnostics(&mut self, uri: &Url) -> Vec<Diagnostic> {
+        let notification = self.wait_for(|n| n["method"] == "textDocument/publishDiagnostics" && n["params"]["uri"] == uri.as_str()).await;
+        serde_json::from_value(notification["params"]["diagnostics"].clone()).unwrap()
+    }
+
+    /// Opens a document and returns its diagnostics.
+    pub async fn open(&mut self, uri: &Url, text: &str) -> Vec<Diagnostic> {
+        let language = if uri.path().ends_with(".json") { "MarloweJSON" } else { "Marlowe" };
+        self.notify("textDocument/didOpen", json!({
+            "textDocument": { "uri": uri, "languageId": language, "version": 1, "text": text }
+        })).await;
+        self.diagnostics(uri).await
+    }
+
+    /// Sends changes (TextDocumentContentChangeEvent) to a document and returns its diagnostics.
+    pub async fn change(&mut self, uri: &Url, version: i32, changes: Value) -> Vec<Diagnostic> {
+        self.notify("textDocument/didChange", json!({
+            "textDocument": { "uri": uri, "version": version },
+            "contentChanges": changes
+        })).await;
+        self.diagnostics(uri).await
+    }
+
+    pub async fn hover(&mut self, uri: &Url, line: u32, character: u32) -> Value {
+        self.request("textDocument/hover", json!({
+            "textDocument": { "uri": uri },
+            "position": { "line": line, "character": character }
+        })).await
+    }
+
+    pub async fn completion(&mut self, uri: &Url, line: u32, character: u32) -> Value {
+        self.request("textDocument/completion", json!({
+            "textDocument": { "uri": uri },
+            "position": { "line": line, "character": character }
+        })).await
+    }
+
+    /// The outline of a document (DocumentSymbol[]), null if it can not be parsed.
+    pub async fn symbols(&mut self, uri: &Url) -> Value {
+        self.request("textDocument/documentSymbol", json!({ "textDocument": { "uri": uri } })).await
+    }
+
+    pub async fn shutdown(mut self) {
+        self.request("shutdown", Value::Null).await;
+        self.notify("exit", Value::Null).await;
+    }
+}
+
+/// The directory with the sample contracts.
+pub fn sample_contracts() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("sample_contracts")
+}
+
+pub fn file_uri(path: &Path) -> Url {
+    Url::from_file_path(path).unwrap()
+}
+
+/// The codes of diagnostics, in the order they were published.
+pub fn codes(diagnostics: &[Diagnostic]) -> Vec<String> {
+    diagnostics.iter().map(|d| match &d.code {
+        Some(marlowe_lsp::lsp_types::NumberOrString::String(code)) => code.clone(),
+        code => format!("{code:?}"),
+    }).collect()
+}
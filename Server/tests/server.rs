@@ -0,0 +1,153 @@
+// The language server as an editor sees it, see common/mod.rs for the test client.
+
+mod common;
+
+use common::{codes, file_uri, sample_contracts, TestClient};
+use marlowe_lsp::lsp_types::{DiagnosticSeverity, NumberOrString, Url};
+use serde_json::json;
+
+fn uri(name: &str) -> Url {
+    Url::parse(&format!("file:///contracts/{name}")).unwrap()
+}
+
+const ESCROW: &str = "When\n    [Case\n        (Deposit\n            (Role \"seller\")\n            (Role \"buyer\")\n            (Token \"\" \"\")\n            (Constant 10)\n        )\n        ?refund]\n    100\n    Close";
+
+#[tokio::test]
+async fn initialize_advertises_capabilities() {
+    let client = TestClient::start().await;
+    let capabilities = &client.initialize_result["capabilities"];
+    assert_eq!(capabilities["hoverProvider"], json!(true));
+    assert_eq!(capabilities["documentFormattingProvider"], json!(true));
+    assert_eq!(capabilities["completionProvider"]["triggerCharacters"], json!(["\""]));
+    assert!(capabilities["semanticTokensProvider"]["legend"]["tokenTypes"].is_array());
+    client.shutdown().await;
+}
+
+#[tokio::test]
+async fn publishes_diagnostics_when_a_document_is_opened() {
+    let mut client = TestClient::start().await;
+    let diagnostics = client.open(&uri("escrow.marlowe"), ESCROW).await;
+    assert_eq!(codes(&diagnostics), ["ML011"]);
+    let hole = &diagnostics[0];
+    assert_eq!(hole.severity, Some(DiagnosticSeverity::WARNING));
+    assert_eq!(hole.source.as_deref(), Some("marlowe"));
+    assert_eq!((hole.range.start.line, hole.range.start.character), (8, 8));
+    assert!(hole.code_description.as_ref().unwrap().href.as_str().ends_with("#ml011-hole-contract"));
+    client.shutdown().await;
+}
+
+#[tokio::test]
+async fn changes_are_validated_again() {
+    let mut client = TestClient::start().await;
+    let escrow = uri("escrow.marlowe");
+    client.open(&escrow, ESCROW).await;
+
+    // Fill the hole
+    let diagnostics = client.change(&escrow, 2, json!([{
+        "range": { "start": { "line": 8, "character": 8 }, "end": { "line": 8, "character": 15 } },
+        "text": "Close"
+    }])).await;
+    assert_eq!(codes(&diagnostics), Vec::<String>::new());
+
+    // Break it, the whole text at once
+    let diagnostics = client.change(&escrow, 3, json!([{ "text": "When [Case" }])).await;
+    assert_eq!(codes(&diagnostics), ["ML000"], "{diagnostics:?}");
+    assert_eq!(diagnostics[0].severity, Some(DiagnosticSeverity::ERROR));
+    client.shutdown().await;
+}
+
+#[tokio::test]
+async fn hover_describes_the_construct() {
+    let mut client = TestClient::start().await;
+    let escrow = uri("escrow.marlowe");
+    client.open(&escrow, ESCROW).await;
+
+    let hover = client.hover(&escrow, 3, 14).await;
+    let text = hover["contents"]["value"].as_str().unwrap();
+    assert!(text.starts_with("A Party is represented as either a public key hash or a role name."), "{text}");
+
+    let hover = client.hover(&escrow, 0, 1).await;
+    let text = hover["contents"]["value"].as_str().unwrap();
+    assert!(text.starts_with("Marlowe has six ways of building contracts."), "{text}");
+    client.shutdown().await;
+}
+
+#[tokio::test]
+async fn completion_suggests_roles_of_the_contract() {
+    let mut client = TestClient::start().await;
+    let escrow = uri("escrow.marlowe");
+    client.open(&escrow, ESCROW).await;
+
+    // Start typing another party on a line of its own: (Role "
+    client.change(&escrow, 2, json!([{
+        "range": { "start": { "line": 4, "character": 26 }, "end": { "line": 4, "character": 26 } },
+        "text": "\n            (Role \""
+    }])).await;
+    let completion = client.completion(&escrow, 5, 19).await;
+    let labels: Vec<&str> = completion["items"].as_array().unwrap().iter().map(|item| item["label"].as_str().unwrap()).collect();
+    assert_eq!(labels, ["buyer", "seller"]);
+
+    // Anywhere else there is nothing to suggest
+    assert_eq!(client.completion(&escrow, 0, 2).await, json!(null));
+    assert_eq!(client.completion(&escrow, 100, 0).await, json!(null));
+    client.shutdown().await;
+}
+
+#[tokio::test]
+async fn closed_documents_have_nothing_to_complete() {
+    let mut client = TestClient::start().await;
+    let escrow = uri("escrow.marlowe");
+    let opened = client.open(&escrow, ESCROW).await;
+    client.notify("textDocument/didClose", json!({ "textDocument": { "uri": escrow } })).await;
+    assert_eq!(client.diagnostics(&escrow).await, vec![]);
+    assert_eq!(client.completion(&escrow, 3, 19).await, json!(null));
+    assert_eq!(client.completion(&uri("never-opened.marlowe"), 0, 0).await, json!(null));
+
+    // A change that arrives after the close is ignored, and the document can be opened again
+    client.notify("textDocument/didChange", json!({ "textDocument": { "uri": escrow, "version": 2 }, "contentChanges": [{ "text": "Close" }] })).await;
+    assert_eq!(client.open(&escrow, ESCROW).await, opened);
+    client.shutdown().await;
+}
+
+#[tokio::test]
+async fn editor_settings_change_severities_and_add_rules() {
+    let settings = json!({
+        "rules": { "hole-contract": "error" },
+        "analysis": {
+            "customRules": [{ "id": "HOUSE001", "node": "Role", "pattern": "\"seller\"", "message": "Sellers need a PK.", "severity": "hint" }]
+        }
+    });
+    let mut client = TestClient::start_with(settings, None).await;
+    let diagnostics = client.open(&uri("escrow.marlowe"), ESCROW).await;
+    assert_eq!(codes(&diagnostics), ["HOUSE001", "ML011"], "{diagnostics:?}");
+    assert_eq!(diagnostics[0].severity, Some(DiagnosticSeverity::HINT));
+    assert_eq!(diagnostics[0].message, "Sellers need a PK.");
+    assert_eq!(diagnostics[1].severity, Some(DiagnosticSeverity::ERROR));
+
+    // Pushed settings replace the ones that were pulled
+    client.notify("workspace/didChangeConfiguration", json!({ "settings": { "marlowe": { "rules": { "ML011": "off" } } } })).await;
+    let diagnostics = client.diagnostics(&uri("escrow.marlowe")).await;
+    assert_eq!(codes(&diagnostics), Vec::<String>::new());
+    client.shutdown().await;
+}
+
+#[tokio::test]
+async fn problems_with_the_configuration_are_shown() {
+    let settings = json!({ "rules": { "no-such-rule": "off" } });
+    let mut client = TestClient::start_with(settings, None).await;
+    let message = client.notification("window/showMessage").await;
+    assert_eq!(message["params"]["message"], json!("Unknown rule in the configuration: no-such-rule"));
+    client.shutdown().await;
+}
+
+#[tokio::test]
+async fn json_documents_are_validated_like_the_dsl() {
+    let mut client = TestClient::start().await;
+    let swap = file_uri(&sample_contracts().join("swap.marlowe.json"));
+    let text = std::fs::read_to_string(swap.to_file_path().unwrap()).unwrap();
+    assert_eq!(codes(&client.open(&swap, &text).await), Vec::<String>::new());
+
+    let diagnostics = client.change(&swap, 2, json!([{ "text": "{ \"when\": [" }])).await;
+    assert_eq!(diagnostics[0].code, Some(NumberOrString::String("ML000".into())));
+    client.shutdown().await;
+}
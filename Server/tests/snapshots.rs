@@ -0,0 +1,108 @@
+// The diagnostics and the outline of every contract in sample_contracts, compared with
+// the snapshots in tests/snapshots. After an intended change, write new snapshots with
+//
+//   UPDATE_SNAPSHOTS=1 cargo test --test snapshots
+//
+// and review the difference before committing them. Snapshots are never empty, an empty
+// file is a snapshot that was lost rather than a contract without diagnostics.
+
+mod common;
+
+use std::path::{Path, PathBuf};
+use common::{file_uri, sample_contracts, TestClient};
+use marlowe_lsp::lsp_types::{Diagnostic, DiagnosticSeverity, NumberOrString};
+use serde_json::Value;
+
+fn snapshots() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests").join("snapshots")
+}
+
+fn is_contract(path: &Path) -> bool {
+    let name = path.file_name().and_then(|n| n.to_str()).unwrap_or_default();
+    name.ends_with(".marlowe") || name.ends_with(".marlowe.json")
+}
+
+/// One line per diagnostic, in the order of their ranges: 1-based line:column of the range,
+/// severity, code and message.
+fn render_diagnostics(diagnostics: &[Diagnostic], out: &mut String) {
+    out.push_str("diagnostics:");
+    if diagnostics.is_empty() {
+        out.push_str(" none");
+    }
+    out.push('\n');
+    let mut diagnostics = diagnostics.to_vec();
+    diagnostics.sort_by_key(|d| (d.range.start, d.range.end, format!("{:?}", d.code)));
+    for d in diagnostics {
+        let severity = match d.severity {
+            Some(DiagnosticSeverity::ERROR) => "error",
+            Some(DiagnosticSeverity::WARNING) => "warning",
+            Some(DiagnosticSeverity::INFORMATION) => "info",
+            Some(_) => "hint",
+            None => "none",
+        };
+        let code = match &d.code {
+            Some(NumberOrString::String(code)) => code.clone(),
+            code => format!("{code:?}"),
+        };
+        let (start, end) = (d.range.start, d.range.end);
+        let message = d.message.trim_end().replace('\n', "\n      ");
+        out.push_str(&format!("  {}:{}-{}:{} {severity} {code} {message}\n", start.line + 1, start.character + 1, end.line + 1, end.character + 1));
+    }
+}
+
+/// The outline as a tree: 1-based line:column where each symbol starts, its name and detail.
+fn render_symbols(symbols: &Value, depth: usize, out: &mut String) {
+    for symbol in symbols.as_array().into_iter().flatten() {
+        let start = &symbol["range"]["start"];
+        let detail = symbol["detail"].as_str().map(|d| format!(" ({d})")).unwrap_or_default();
+        out.push_str(&format!("{}{}:{} {}{detail}\n", "  ".repeat(depth), start["line"].as_u64().unwrap() + 1, start["character"].as_u64().unwrap() + 1, symbol["name"].as_str().unwrap()));
+        render_symbols(&symbol["children"], depth + 1, out);
+    }
+}
+
+fn render(diagnostics: &[Diagnostic], symbols: &Value) -> String {
+    let mut out = String::new();
+    render_diagnostics(diagnostics, &mut out);
+    out.push_str("outline:");
+    if symbols.is_null() {
+        out.push_str(" none");
+    }
+    out.push('\n');
+    render_symbols(symbols, 1, &mut out);
+    out
+}
+
+#[tokio::test]
+async fn sample_contracts_match_their_snapshots() {
+    let update = std::env::var_os("UPDATE_SNAPSHOTS").is_some();
+    let mut contracts: Vec<PathBuf> = std::fs::read_dir(sample_contracts()).unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| is_contract(path))
+        .collect();
+    contracts.sort();
+    assert!(!contracts.is_empty());
+
+    let mut client = TestClient::start().await;
+    let mut failures = vec![];
+    for path in contracts {
+        let name = path.file_name().unwrap().to_string_lossy().to_string();
+        let text = std::fs::read_to_string(&path).unwrap();
+        let uri = file_uri(&path);
+        let diagnostics = client.open(&uri, &text).await;
+        let actual = render(&diagnostics, &client.symbols(&uri).await);
+        let snapshot = snapshots().join(format!("{name}.snap"));
+        if update {
+            std::fs::create_dir_all(snapshots()).unwrap();
+            std::fs::write(&snapshot, &actual).unwrap();
+            continue
+        }
+        match std::fs::read_to_string(&snapshot) {
+            Ok(expected) if expected.trim().is_empty() => failures.push(format!("{name}: the snapshot is empty, run with UPDATE_SNAPSHOTS=1 to write it again.")),
+            Ok(expected) if expected.replace("\r\n", "\n") == actual => {}
+            Ok(expected) => failures.push(format!("{name}: the snapshot changed.\n--- expected\n{expected}--- actual\n{actual}")),
+            Err(_) => failures.push(format!("{name}: there is no snapshot, run with UPDATE_SNAPSHOTS=1 to write it.\n{actual}")),
+        }
+    }
+    client.shutdown().await;
+    assert!(failures.is_empty(), "\n{}", failures.join("\n"));
+}
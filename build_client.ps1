@@ -4,6 +4,9 @@ param()
 Remove-Item .\Clients\VSCode\*.vsix -ErrorAction SilentlyContinue
 Remove-Item .\Clients\VSCode\build\client\extension.js -verbose:$VerbosePreference  -ErrorAction SilentlyContinue
 Set-Location ./Clients/VSCode -ErrorAction Stop
+# The server links its diagnostics to the rule documentation next to it
+New-Item -ItemType Directory -Force ./bin/docs | Out-Null
+Copy-Item ../../Server/docs/rules.md ./bin/docs/rules.md -ErrorAction Stop
 vsce package
 if($LASTEXITCODE -ne 0) {
     Write-Error "Failed to build client."